@@ -5,9 +5,20 @@
 //!
 //! Uses a delay of `LATENCY_MS` milliseconds in case the default input and output streams are not
 //! precisely synchronised.
+//!
+//! With `--record PATH` the input is also written to a 32 bit float WAV file.
+
+mod libs;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use clap::Parser;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use libs::wav::{BitDepth, BroadcastExt, WavError, WavParams, WavWriter};
 use ringbuf::HeapRb;
 
 #[derive(Parser, Debug)]
@@ -25,6 +36,10 @@ struct Opt {
     #[arg(short, long, value_name = "DELAY_MS", default_value_t = 150.0)]
     latency: f32,
 
+    /// Record the input to a WAV file
+    #[arg(short, long, value_name = "PATH")]
+    record: Option<PathBuf>,
+
     /// Use the JACK host
     #[cfg(all(
         any(
@@ -115,6 +130,46 @@ fn main() -> anyhow::Result<()> {
         producer.push(0.0).unwrap();
     }
 
+    // The recording gets its own copy of the input, written to disk away from the audio thread
+    let recording = Arc::new(AtomicBool::new(true));
+    let (mut record_producer, recorder) = match &opt.record {
+        Some(path) => {
+            let params = WavParams {
+                sample_rate: config.sample_rate.0,
+                channels: config.channels,
+            };
+            // Broadcast Wave stamp, so the take lands at the right time on a timeline
+            let bext = BroadcastExt {
+                originator: "rust_audio_playground".into(),
+                ..BroadcastExt::now(params.sample_rate)
+            };
+            let mut writer = WavWriter::with_chunks(
+                BufWriter::new(File::create(path)?),
+                params,
+                BitDepth::F32(0.0),
+                &[bext.to_chunk()],
+            )?;
+            let ring = HeapRb::<f32>::new(config.sample_rate.0 as usize * config.channels as usize);
+            let (record_producer, mut record_consumer) = ring.split();
+            let recording = recording.clone();
+            let recorder = std::thread::spawn(move || -> Result<(), WavError> {
+                loop {
+                    let done = !recording.load(Ordering::Acquire);
+                    while let Some(sample) = record_consumer.pop() {
+                        writer.write_sample(BitDepth::F32(sample))?;
+                    }
+                    if done {
+                        return writer.finalize();
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+            });
+            (Some(record_producer), Some(recorder))
+        }
+        None => (None, None),
+    };
+
+    let channels = config.channels as usize;
     let input_data_fn = move |data: &[f32], _: &cpal::InputCallbackInfo| {
         let mut output_fell_behind = false;
         for &sample in data {
@@ -125,6 +180,16 @@ fn main() -> anyhow::Result<()> {
         if output_fell_behind {
             eprintln!("output stream fell behind: try increasing latency");
         }
+        // Only whole buffers of whole frames go to the recording, part of one would shift the
+        // channels of every frame after it
+        if let Some(record_producer) = record_producer.as_mut() {
+            let frames = &data[..data.len() - data.len() % channels];
+            if record_producer.free_len() >= frames.len() {
+                record_producer.push_slice(frames);
+            } else {
+                eprintln!("recording fell behind: a buffer was dropped");
+            }
+        }
     };
 
     let output_data_fn = move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
@@ -165,6 +230,11 @@ fn main() -> anyhow::Result<()> {
     std::thread::sleep(std::time::Duration::from_secs(3));
     drop(input_stream);
     drop(output_stream);
+    if let Some(recorder) = recorder {
+        recording.store(false, Ordering::Release);
+        recorder.join().expect("recording thread panicked")?;
+        println!("Recorded to {}", opt.record.unwrap().display());
+    }
     println!("Done!");
     Ok(())
 }
@@ -1,19 +1,14 @@
 mod libs;
 
-use crate::libs::wav::WavFile;
+use crate::libs::resample::{Quality, Resampler};
+use crate::libs::source;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{FromSample, Sample, SizedSample};
-use libs::wav::BitDepth;
-use std::path::Path;
+use ringbuf::HeapRb;
+use std::path::{Path, PathBuf};
 
-fn bit_depth_to_float(s: &BitDepth) -> f32 {
-    let v = match s {
-        BitDepth::U8(v) => *v as f32 / i8::MAX as f32,
-        BitDepth::U16(v) => *v as f32 / i16::MAX as f32,
-        BitDepth::U32(v) => *v as f32 / i32::MAX as f32,
-    };
-    v
-}
+/// How long a note is held, for files with a sustain loop
+const HOLD_SECONDS: f64 = 2.0;
 
 fn main() {
     // Conditionally compile with jack if the feature is specified.
@@ -50,6 +45,9 @@ fn main() {
     ))]
     let host = cpal::default_host();
 
+    // Any WAV, AIFF, FLAC or Ogg Vorbis file
+    let path = PathBuf::from(std::env::args().nth(1).unwrap_or("out/test.wav".into()));
+
     let device = host
         .default_output_device()
         .expect("failed to find output device");
@@ -59,70 +57,140 @@ fn main() {
     println!("Default output config: {:?}", config);
 
     match config.sample_format() {
-        cpal::SampleFormat::I8 => run::<i8>(&device, &config.into()).unwrap(),
-        cpal::SampleFormat::I16 => run::<i16>(&device, &config.into()).unwrap(),
-        // cpal::SampleFormat::I24 => run::<I24>(&device, &config.into()),
-        cpal::SampleFormat::I32 => run::<i32>(&device, &config.into()).unwrap(),
-        // cpal::SampleFormat::I48 => run::<I48>(&device, &config.into()),
-        cpal::SampleFormat::I64 => run::<i64>(&device, &config.into()).unwrap(),
-        cpal::SampleFormat::U8 => run::<u8>(&device, &config.into()).unwrap(),
-        cpal::SampleFormat::U16 => run::<u16>(&device, &config.into()).unwrap(),
-        // cpal::SampleFormat::U24 => run::<U24>(&device, &config.into()),
-        cpal::SampleFormat::U32 => run::<u32>(&device, &config.into()).unwrap(),
-        // cpal::SampleFormat::U48 => run::<U48>(&device, &config.into()),
-        cpal::SampleFormat::U64 => run::<u64>(&device, &config.into()).unwrap(),
-        cpal::SampleFormat::F32 => run::<f32>(&device, &config.into()).unwrap(),
-        cpal::SampleFormat::F64 => run::<f64>(&device, &config.into()).unwrap(),
+        cpal::SampleFormat::I8 => run::<i8>(&device, &config.into(), &path).unwrap(),
+        cpal::SampleFormat::I16 => run::<i16>(&device, &config.into(), &path).unwrap(),
+        // cpal::SampleFormat::I24 => run::<I24>(&device, &config.into(), &path),
+        cpal::SampleFormat::I32 => run::<i32>(&device, &config.into(), &path).unwrap(),
+        // cpal::SampleFormat::I48 => run::<I48>(&device, &config.into(), &path),
+        cpal::SampleFormat::I64 => run::<i64>(&device, &config.into(), &path).unwrap(),
+        cpal::SampleFormat::U8 => run::<u8>(&device, &config.into(), &path).unwrap(),
+        cpal::SampleFormat::U16 => run::<u16>(&device, &config.into(), &path).unwrap(),
+        // cpal::SampleFormat::U24 => run::<U24>(&device, &config.into(), &path),
+        cpal::SampleFormat::U32 => run::<u32>(&device, &config.into(), &path).unwrap(),
+        // cpal::SampleFormat::U48 => run::<U48>(&device, &config.into(), &path),
+        cpal::SampleFormat::U64 => run::<u64>(&device, &config.into(), &path).unwrap(),
+        cpal::SampleFormat::F32 => run::<f32>(&device, &config.into(), &path).unwrap(),
+        cpal::SampleFormat::F64 => run::<f64>(&device, &config.into(), &path).unwrap(),
         sample_format => panic!("Unsupported sample format '{sample_format}'"),
     };
 }
 
-pub fn run<T>(device: &cpal::Device, config: &cpal::StreamConfig) -> Result<(), anyhow::Error>
+pub fn run<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    path: &Path,
+) -> Result<(), anyhow::Error>
 where
     T: SizedSample + FromSample<f32>,
 {
-    let file = WavFile::read(Path::new("out/test.wav")).unwrap();
-
-    let file_data: Vec<f32> = file.data.iter().map(bit_depth_to_float).collect();
-    let audio_length = file_data.len() as f32 / file.hdr.fmt_ck.sample_rate as f32;
+    let mut file = source::open(path)?;
+    let file_channels = file.channels() as usize;
+    // Sustain loops repeat while the note is held, which here is a fixed time
+    let sustain = file
+        .sampler()
+        .and_then(|s| s.sustain_loop())
+        .filter(|l| l.start <= l.end && (l.end as u64) < file.len())
+        .cloned();
+    let hold = (HOLD_SECONDS * file.sample_rate() as f64) as u64;
+    // Files at another rate than the device would play sharp or flat
+    let mut resampler = (file.sample_rate() != config.sample_rate.0).then(|| {
+        println!(
+            "Resampling from {} Hz to {} Hz",
+            file.sample_rate(),
+            config.sample_rate.0
+        );
+        Resampler::new(
+            file.sample_rate(),
+            config.sample_rate.0,
+            file.channels(),
+            Quality::default(),
+        )
+    });
 
-    let mut s: usize = 0;
-    let mut next_value = move || {
-        let sample_data = file_data.get(s);
-        match sample_data {
-            Some(sample) => {
-                s += 1;
-                *sample
+    // The file is streamed from disk by another thread, one second ahead of playback
+    let ring = HeapRb::<f32>::new(config.sample_rate.0 as usize * file_channels);
+    let (mut producer, mut consumer) = ring.split();
+    let streamer = std::thread::spawn(move || {
+        let mut played = 0;
+        loop {
+            // Stop at the end of the loop while it still has to repeat
+            let looping = sustain
+                .as_ref()
+                .filter(|l| played < hold && file.position() <= l.end as u64);
+            let frames = match looping {
+                Some(l) => 1024.min(l.end as u64 + 1 - file.position()) as usize,
+                None => 1024,
+            };
+            let block = file.read_block(frames).unwrap();
+            let ended = block.is_empty();
+            played += block.frames() as u64;
+            let block: Vec<f32> = match &mut resampler {
+                // The resampler holds back the last few frames until told the file ended
+                Some(r) if ended => r.flush().iter().map(|&v| v as f32).collect(),
+                Some(r) => r.process(&block.to_f64()).iter().map(|&v| v as f32).collect(),
+                None => block.to_f32(),
+            };
+            let mut written = 0;
+            loop {
+                written += producer.push_slice(&block[written..]);
+                if written == block.len() {
+                    break;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(5));
+            }
+            if ended {
+                break;
             }
-            _ => 0.0, // Pad with zeroes after it ends
+            if let Some(l) = looping {
+                if file.position() == l.end as u64 + 1 {
+                    file.seek(l.start as u64).unwrap();
+                }
+            }
+        }
+    });
+
+    let mut next_frame = move |frame: &mut [f32]| {
+        for sample in frame.iter_mut() {
+            *sample = consumer.pop().unwrap_or(0.0); // Pad with zeroes after it ends
         }
     };
+    let mut frame = vec![0.0; file_channels];
 
     let channels = config.channels as usize;
     let err_fn = |err| eprintln!("an error occurred on the output audio stream: {}", err);
     let stream = device
         .build_output_stream(
             &config,
-            move |data: &mut [f32], _| write_data(data, channels, &mut next_value),
+            move |data: &mut [f32], _| write_data(data, channels, &mut frame, &mut next_frame),
             err_fn,
             None,
         )
         .unwrap();
     stream.play().unwrap();
-    std::thread::sleep(std::time::Duration::from_millis(
-        (audio_length * 1000.0) as u64,
-    ));
+    // The streamer is done once the rest of the file is in the ring, which holds a second
+    streamer.join().unwrap();
+    std::thread::sleep(std::time::Duration::from_secs(1));
     Ok(())
 }
 
-fn write_data<T>(output: &mut [T], channels: usize, next_sample: &mut dyn FnMut() -> f32)
-where
+/// Plays one frame of the file for every output frame. Mono files go to every output channel,
+/// otherwise file channels map to outputs in order and any extra outputs are left silent.
+fn write_data<T>(
+    output: &mut [T],
+    channels: usize,
+    frame: &mut [f32],
+    next_frame: &mut dyn FnMut(&mut [f32]),
+) where
     T: Sample + FromSample<f32>,
 {
-    for frame in output.chunks_mut(channels) {
-        let value: T = T::from_sample(next_sample());
-        for sample in frame.iter_mut() {
-            *sample = value;
+    for out in output.chunks_mut(channels) {
+        next_frame(frame);
+        for (c, sample) in out.iter_mut().enumerate() {
+            let value = match frame.len() {
+                1 => frame[0],
+                _ => frame.get(c).copied().unwrap_or(0.0),
+            };
+            *sample = T::from_sample(value);
         }
     }
 }
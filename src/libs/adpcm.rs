@@ -0,0 +1,219 @@
+// IMA ADPCM as stored in WAV files: 4 bits a sample, in blocks that each start with a full
+// sample and step index for every channel
+
+const INDEX_TABLE: [i32; 8] = [-1, -1, -1, -1, 2, 4, 6, 8];
+
+const STEP_TABLE: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449,
+    494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272,
+    2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493,
+    10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+/// Bytes of block header for each channel
+const HEADER_SIZE: usize = 4;
+/// Each channel's samples are interleaved in runs of 4 bytes, 8 samples
+const GROUP_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Channel {
+    predictor: i32,
+    index: usize,
+}
+
+impl Channel {
+    fn decode(&mut self, nibble: u8) -> i16 {
+        let step = STEP_TABLE[self.index];
+        let mut diff = step >> 3;
+        if nibble & 4 != 0 {
+            diff += step;
+        }
+        if nibble & 2 != 0 {
+            diff += step >> 1;
+        }
+        if nibble & 1 != 0 {
+            diff += step >> 2;
+        }
+        self.predictor = match nibble & 8 {
+            0 => self.predictor + diff,
+            _ => self.predictor - diff,
+        }
+        .clamp(i16::MIN as i32, i16::MAX as i32);
+        self.index = (self.index as i32 + INDEX_TABLE[(nibble & 7) as usize]).clamp(0, 88) as usize;
+        self.predictor as i16
+    }
+    fn encode(&mut self, sample: i16) -> u8 {
+        let mut diff = sample as i32 - self.predictor;
+        let mut nibble = 0;
+        if diff < 0 {
+            nibble = 8;
+            diff = -diff;
+        }
+        let mut step = STEP_TABLE[self.index];
+        for bit in [4, 2, 1] {
+            if diff >= step {
+                nibble |= bit;
+                diff -= step;
+            }
+            step >>= 1;
+        }
+        // Track what the decoder will reconstruct, not the input
+        self.decode(nibble);
+        nibble
+    }
+}
+
+/// Frames in a block of `block_align` bytes, `None` if the block can't hold the headers and
+/// whole groups
+pub fn samples_per_block(block_align: u16, channels: u16) -> Option<usize> {
+    let channels = channels as usize;
+    let header = HEADER_SIZE * channels;
+    let block_align = block_align as usize;
+    if channels == 0
+        || block_align < header
+        || !(block_align - header).is_multiple_of(GROUP_SIZE * channels)
+    {
+        return None;
+    }
+    Some((block_align - header) * 2 / channels + 1)
+}
+
+/// Frames held by a block that may be cut short, like the last one of a file
+pub fn frames_in_block(bytes: usize, channels: u16) -> usize {
+    let channels = channels as usize;
+    match bytes.checked_sub(HEADER_SIZE * channels) {
+        Some(rest) => 1 + rest / (GROUP_SIZE * channels) * 8,
+        None => 0,
+    }
+}
+
+/// Decodes whole blocks of `block_align` bytes into interleaved samples. A short last block
+/// gives the frames it has.
+pub fn decode(data: &[u8], channels: u16, block_align: u16) -> Vec<i16> {
+    let channels = channels as usize;
+    let mut samples = Vec::new();
+    for block in data.chunks(block_align as usize) {
+        let frames = frames_in_block(block.len(), channels as u16);
+        if frames == 0 {
+            break;
+        }
+        let start = samples.len();
+        samples.resize(start + frames * channels, 0);
+        let out = &mut samples[start..];
+        let mut states = Vec::with_capacity(channels);
+        for (c, hdr) in block.chunks_exact(HEADER_SIZE).take(channels).enumerate() {
+            let predictor = i16::from_le_bytes([hdr[0], hdr[1]]);
+            states.push(Channel {
+                predictor: predictor as i32,
+                index: (hdr[2] as usize).min(88),
+            });
+            out[c] = predictor;
+        }
+        let groups = block[HEADER_SIZE * channels..].chunks_exact(GROUP_SIZE * channels);
+        for (g, group) in groups.enumerate() {
+            for (c, bytes) in group.chunks_exact(GROUP_SIZE).enumerate() {
+                for (i, byte) in bytes.iter().enumerate() {
+                    // Low nibble first
+                    for (j, nibble) in [byte & 0x0F, byte >> 4].into_iter().enumerate() {
+                        let frame = 1 + g * 8 + i * 2 + j;
+                        out[frame * channels + c] = states[c].decode(nibble);
+                    }
+                }
+            }
+        }
+    }
+    samples
+}
+
+/// Encodes interleaved samples into blocks of `block_align` bytes. The last block is padded
+/// with its final sample, so the real length has to be kept elsewhere, e.g. in a fact chunk.
+/// None if the blocks can't be that size, as with `samples_per_block`.
+pub fn encode(samples: &[i16], channels: u16, block_align: u16) -> Option<Vec<u8>> {
+    let frames_per_block = samples_per_block(block_align, channels)?;
+    let channels = channels as usize;
+    let mut states = vec![Channel::default(); channels];
+    // Start with a step that fits the opening samples, rather than ramping up from the smallest
+    for (c, state) in states.iter_mut().enumerate() {
+        let first: Vec<i32> = samples
+            .iter()
+            .skip(c)
+            .step_by(channels)
+            .take(9)
+            .map(|&s| s as i32)
+            .collect();
+        let largest = first
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .max()
+            .unwrap_or(0);
+        state.index = STEP_TABLE
+            .iter()
+            .position(|&step| step * 2 >= largest)
+            .unwrap_or(88);
+    }
+    let mut data = Vec::new();
+    for block in samples.chunks(frames_per_block * channels) {
+        let frame = |i: usize, c: usize| {
+            let frames = block.len() / channels;
+            block[i.min(frames - 1) * channels + c]
+        };
+        for (c, state) in states.iter_mut().enumerate() {
+            state.predictor = frame(0, c) as i32;
+            data.extend_from_slice(&frame(0, c).to_le_bytes());
+            data.extend_from_slice(&[state.index as u8, 0]);
+        }
+        for g in 0..(frames_per_block - 1) / 8 {
+            for (c, state) in states.iter_mut().enumerate() {
+                for i in 0..GROUP_SIZE {
+                    let low = state.encode(frame(1 + g * 8 + i * 2, c));
+                    let high = state.encode(frame(2 + g * 8 + i * 2, c));
+                    data.push(low | (high << 4));
+                }
+            }
+        }
+    }
+    Some(data)
+}
+
+#[test]
+fn test_adpcm_round_trip() {
+    let channels = 2;
+    let block_align = 256 * channels;
+    let frames_per_block = samples_per_block(block_align, channels).unwrap();
+    assert_eq!(frames_per_block, 505);
+
+    // Two and a half blocks of a slow sine on the left and a faster one on the right
+    let frames = frames_per_block * 5 / 2;
+    let samples: Vec<i16> = (0..frames)
+        .flat_map(|i| {
+            let t = i as f64 / 8000.0;
+            [
+                ((t * 300.0 * std::f64::consts::TAU).sin() * 12000.0) as i16,
+                ((t * 1100.0 * std::f64::consts::TAU).sin() * 8000.0) as i16,
+            ]
+        })
+        .collect();
+    let data = encode(&samples, channels, block_align).unwrap();
+    assert_eq!(data.len(), 3 * block_align as usize);
+    assert_eq!(encode(&samples, channels, 3), None);
+
+    let decoded = decode(&data, channels, block_align);
+    assert_eq!(decoded.len(), 3 * frames_per_block * channels as usize);
+    // The first sample of each block is stored as is
+    assert_eq!(decoded[0], samples[0]);
+    assert_eq!(
+        decoded[frames_per_block * 2 + 1],
+        samples[frames_per_block * 2 + 1]
+    );
+    let worst = samples
+        .iter()
+        .zip(&decoded)
+        .map(|(a, b)| (*a as i32 - *b as i32).abs())
+        .max()
+        .unwrap();
+    assert!(worst < 1500, "error of {worst}");
+
+    // A block cut off after the headers and one group
+    assert_eq!(decode(&data[..16], channels, block_align).len(), 9 * 2);
+}
@@ -0,0 +1,495 @@
+// AIFF and AIFF-C, the big-endian cousins of WAV
+use crate::libs::buffer::SampleBuffer;
+use crate::libs::wav::{BitDepth, Chunk, Fourcc};
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::{
+    fmt,
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+/// Value of the `FVER` chunk for the only AIFF-C version there is
+const AIFC_VERSION_1: u32 = 0xA280_5140;
+
+#[derive(Debug)]
+pub enum AiffError {
+    Io(std::io::Error),
+    /// The file doesn't start with a FORM header
+    NotForm,
+    /// The form isn't AIFF or AIFF-C
+    NotAiff,
+    /// An AIFF-C compression type this module can't decode
+    UnsupportedCompression(Fourcc),
+    UnsupportedSampleSize(u16),
+    /// The file ends before a header does
+    Truncated,
+    MissingChunk(Fourcc),
+    MalformedChunk(Fourcc),
+    /// Samples that can't be stored with the chosen compression
+    SampleMismatch,
+}
+
+impl fmt::Display for AiffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AiffError::Io(e) => write!(f, "I/O error: {e}"),
+            AiffError::NotForm => write!(f, "Not an IFF file"),
+            AiffError::NotAiff => write!(f, "Not an AIFF file"),
+            AiffError::UnsupportedCompression(id) => {
+                write!(
+                    f,
+                    "Unsupported compression `{}`",
+                    String::from_utf8_lossy(id)
+                )
+            }
+            AiffError::UnsupportedSampleSize(bits) => {
+                write!(f, "Unsupported sample size of {bits} bits")
+            }
+            AiffError::Truncated => write!(f, "File is truncated"),
+            AiffError::MissingChunk(id) => {
+                write!(f, "Missing `{}` chunk", String::from_utf8_lossy(id))
+            }
+            AiffError::MalformedChunk(id) => {
+                write!(f, "Malformed `{}` chunk", String::from_utf8_lossy(id))
+            }
+            AiffError::SampleMismatch => write!(f, "Samples don't match the compression type"),
+        }
+    }
+}
+
+impl std::error::Error for AiffError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AiffError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for AiffError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            ErrorKind::UnexpectedEof => AiffError::Truncated,
+            _ => AiffError::Io(e),
+        }
+    }
+}
+
+/// How the samples are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Big-endian integers, written as plain AIFF
+    None,
+    /// Little-endian integers, AIFF-C `sowt`
+    Sowt,
+    /// Big-endian 32 bit floats, AIFF-C `fl32`
+    Fl32,
+    /// Big-endian 64 bit floats, AIFF-C `fl64`
+    Fl64,
+}
+
+impl Compression {
+    fn from_fourcc(id: Fourcc) -> Result<Compression, AiffError> {
+        match &id {
+            b"NONE" | b"twos" => Ok(Compression::None),
+            b"sowt" => Ok(Compression::Sowt),
+            b"fl32" | b"FL32" => Ok(Compression::Fl32),
+            b"fl64" | b"FL64" => Ok(Compression::Fl64),
+            _ => Err(AiffError::UnsupportedCompression(id)),
+        }
+    }
+    fn fourcc(&self) -> (Fourcc, &'static str) {
+        match self {
+            Compression::None => (*b"NONE", "not compressed"),
+            Compression::Sowt => (*b"sowt", ""),
+            Compression::Fl32 => (*b"fl32", "32-bit floating point"),
+            Compression::Fl64 => (*b"fl64", "64-bit floating point"),
+        }
+    }
+}
+
+/// The `COMM` chunk
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommHdr {
+    pub channels: u16,
+    pub frames: u32,
+    /// Bits per sample. Sizes that aren't a whole number of bytes are stored left-justified
+    /// in the next size up.
+    pub sample_size: u16,
+    pub sample_rate: f64,
+    pub compression: Compression,
+}
+
+impl CommHdr {
+    fn parse(body: &[u8], aifc: bool) -> Result<CommHdr, AiffError> {
+        if body.len() < 18 || (aifc && body.len() < 22) {
+            return Err(AiffError::MalformedChunk(*b"COMM"));
+        }
+        let compression = match aifc {
+            true => Compression::from_fourcc(body[18..22].try_into().unwrap())?,
+            false => Compression::None,
+        };
+        let sample_size = BigEndian::read_u16(&body[6..8]);
+        // Floats have a fixed size whatever the field says, integers take 1 to 4 bytes
+        if matches!(compression, Compression::None | Compression::Sowt)
+            && !(1..=32).contains(&sample_size)
+        {
+            return Err(AiffError::UnsupportedSampleSize(sample_size));
+        }
+        Ok(CommHdr {
+            channels: BigEndian::read_u16(&body[0..2]),
+            frames: BigEndian::read_u32(&body[2..6]),
+            sample_size,
+            sample_rate: f80_to_f64(body[8..18].try_into().unwrap()),
+            compression,
+        })
+    }
+    /// Bytes taken by one sample
+    fn sample_bytes(&self) -> usize {
+        match self.compression {
+            Compression::Fl32 => 4,
+            Compression::Fl64 => 8,
+            _ => (self.sample_size as usize).div_ceil(8),
+        }
+    }
+    fn decode(&self, bytes: &[u8]) -> Result<Vec<BitDepth>, AiffError> {
+        let size = self.sample_bytes();
+        let samples = bytes.chunks_exact(size);
+        Ok(match (self.compression, size) {
+            (Compression::Fl32, _) => samples
+                .map(|b| BitDepth::F32(BigEndian::read_f32(b)))
+                .collect(),
+            (Compression::Fl64, _) => samples
+                .map(|b| BitDepth::F64(BigEndian::read_f64(b)))
+                .collect(),
+            // AIFF 8 bit samples are signed, unlike WAV
+            (_, 1) => samples.map(|b| BitDepth::U8(b[0] as i8)).collect(),
+            (Compression::Sowt, 2) => samples
+                .map(|b| BitDepth::U16(LittleEndian::read_i16(b)))
+                .collect(),
+            (Compression::Sowt, 3) => samples
+                .map(|b| BitDepth::U24(LittleEndian::read_i24(b)))
+                .collect(),
+            (Compression::Sowt, 4) => samples
+                .map(|b| BitDepth::U32(LittleEndian::read_i32(b)))
+                .collect(),
+            (_, 2) => samples
+                .map(|b| BitDepth::U16(BigEndian::read_i16(b)))
+                .collect(),
+            (_, 3) => samples
+                .map(|b| BitDepth::U24(BigEndian::read_i24(b)))
+                .collect(),
+            (_, 4) => samples
+                .map(|b| BitDepth::U32(BigEndian::read_i32(b)))
+                .collect(),
+            _ => return Err(AiffError::UnsupportedSampleSize(self.sample_size)),
+        })
+    }
+    fn write_to<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        let aifc = self.compression != Compression::None;
+        let (id, name) = self.compression.fourcc();
+        // Pascal string, padded so the count byte and the text take an even number of bytes
+        let name_size = (name.len() + 2) & !1;
+        let size = 18 + if aifc { 4 + name_size } else { 0 };
+        w.write_all(b"COMM")?;
+        w.write_u32::<BigEndian>(size as u32)?;
+        w.write_u16::<BigEndian>(self.channels)?;
+        w.write_u32::<BigEndian>(self.frames)?;
+        w.write_u16::<BigEndian>(self.sample_size)?;
+        w.write_all(&f64_to_f80(self.sample_rate))?;
+        if aifc {
+            w.write_all(&id)?;
+            w.write_u8(name.len() as u8)?;
+            w.write_all(name.as_bytes())?;
+            if name.len() % 2 == 0 {
+                w.write_u8(0)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct AiffFile {
+    pub data: SampleBuffer,
+    pub compression: Compression,
+    /// Chunks the codec doesn't interpret, kept so they are written back unchanged
+    pub chunks: Vec<Chunk>,
+}
+
+impl AiffFile {
+    /// Integers are written as plain AIFF, floats as AIFF-C
+    pub fn from_buffer(data: SampleBuffer) -> AiffFile {
+        let compression = match data.bit_depth() {
+            Some(BitDepth::F32(_)) => Compression::Fl32,
+            Some(BitDepth::F64(_)) => Compression::Fl64,
+            _ => Compression::None,
+        };
+        AiffFile {
+            data,
+            compression,
+            chunks: Vec::new(),
+        }
+    }
+    pub fn read(path: &Path) -> Result<AiffFile, AiffError> {
+        AiffFile::read_from(&mut BufReader::new(File::open(path)?))
+    }
+    pub fn read_from<R: Read + Seek>(r: &mut R) -> Result<AiffFile, AiffError> {
+        r.seek(SeekFrom::Start(0))?;
+        let mut form = [0; 12];
+        r.read_exact(&mut form)?;
+        if &form[0..4] != b"FORM" {
+            return Err(AiffError::NotForm);
+        }
+        let aifc = match &form[8..12] {
+            b"AIFF" => false,
+            b"AIFC" => true,
+            _ => return Err(AiffError::NotAiff),
+        };
+        let end = 8 + BigEndian::read_u32(&form[4..8]) as u64;
+
+        let mut comm = None;
+        let mut sound = None;
+        let mut chunks = Vec::new();
+        let mut pos = 12;
+        while pos + 8 <= end {
+            let mut id = [0; 4];
+            // A header cut off by the end of the file ends the form
+            match r.read_exact(&mut id) {
+                Ok(()) => (),
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            let size = r.read_u32::<BigEndian>()? as u64;
+            // Bodies cut off by the end of the file are kept as they are
+            let mut body = Vec::new();
+            r.by_ref().take(size).read_to_end(&mut body)?;
+            match &id {
+                b"COMM" => comm = Some(CommHdr::parse(&body, aifc)?),
+                b"SSND" => sound = Some(body),
+                // Rewritten for AIFF-C files
+                b"FVER" => (),
+                _ => chunks.push(Chunk { id, data: body }),
+            }
+            // Chunks are word aligned
+            pos += 8 + size + size % 2;
+            r.seek(SeekFrom::Start(pos))?;
+        }
+        let comm = comm.ok_or(AiffError::MissingChunk(*b"COMM"))?;
+        if comm.channels == 0 {
+            return Err(AiffError::MalformedChunk(*b"COMM"));
+        }
+        // The sample data follows an offset, which is usually 0
+        let sound = sound.unwrap_or_default();
+        let offset = sound
+            .get(0..4)
+            .map_or(0, |b| BigEndian::read_u32(b) as usize);
+        let bytes = sound.get(8 + offset..).unwrap_or(&[]);
+        let len = comm.frames as usize * comm.channels as usize * comm.sample_bytes();
+        let samples = comm.decode(&bytes[..len.min(bytes.len())])?;
+
+        Ok(AiffFile {
+            data: SampleBuffer::from_interleaved(
+                comm.sample_rate.round() as u32,
+                comm.channels,
+                samples,
+            ),
+            compression: comm.compression,
+            chunks,
+        })
+    }
+    pub fn write(&self, path: &Path) -> Result<(), AiffError> {
+        let mut f = BufWriter::new(File::create(path)?);
+        self.write_to(&mut f)?;
+        f.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        Ok(())
+    }
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<(), AiffError> {
+        let samples = self.data.interleaved();
+        let bits = self.data.bit_depth().map_or(16, |s| s.bits());
+        let fits = samples.iter().all(|s| {
+            s.bits() == bits
+                && match self.compression {
+                    Compression::None | Compression::Sowt => !s.is_float(),
+                    Compression::Fl32 => matches!(s, BitDepth::F32(_)),
+                    Compression::Fl64 => matches!(s, BitDepth::F64(_)),
+                }
+        });
+        if !fits {
+            return Err(AiffError::SampleMismatch);
+        }
+        let comm = CommHdr {
+            channels: self.data.channels(),
+            frames: self.data.frames() as u32,
+            sample_size: bits,
+            sample_rate: self.data.sample_rate as f64,
+            compression: self.compression,
+        };
+        let mut comm_ck = Vec::new();
+        comm.write_to(&mut comm_ck)?;
+        let aifc = self.compression != Compression::None;
+        let data_size = samples.len() * bits as usize / 8;
+        let chunks_size: usize = self.chunks.iter().map(|c| c.file_size() as usize).sum();
+        let form_size = 4
+            + if aifc { 12 } else { 0 }
+            + comm_ck.len()
+            + chunks_size
+            + 16
+            + data_size
+            + data_size % 2;
+
+        w.write_all(b"FORM")?;
+        w.write_u32::<BigEndian>(form_size as u32)?;
+        w.write_all(if aifc { b"AIFC" } else { b"AIFF" })?;
+        if aifc {
+            w.write_all(b"FVER")?;
+            w.write_u32::<BigEndian>(4)?;
+            w.write_u32::<BigEndian>(AIFC_VERSION_1)?;
+        }
+        w.write_all(&comm_ck)?;
+        for ck in &self.chunks {
+            w.write_all(&ck.id)?;
+            w.write_u32::<BigEndian>(ck.data.len() as u32)?;
+            w.write_all(&ck.data)?;
+            if ck.data.len() % 2 != 0 {
+                w.write_u8(0)?;
+            }
+        }
+        w.write_all(b"SSND")?;
+        w.write_u32::<BigEndian>(8 + data_size as u32)?;
+        // Offset and block size, neither is used
+        w.write_all(&[0; 8])?;
+        for s in samples {
+            match (*s, self.compression) {
+                (BitDepth::U8(v), _) => w.write_i8(v)?,
+                (BitDepth::U16(v), Compression::Sowt) => w.write_i16::<LittleEndian>(v)?,
+                (BitDepth::U24(v), Compression::Sowt) => w.write_i24::<LittleEndian>(v)?,
+                (BitDepth::U32(v), Compression::Sowt) => w.write_i32::<LittleEndian>(v)?,
+                (BitDepth::U16(v), _) => w.write_i16::<BigEndian>(v)?,
+                (BitDepth::U24(v), _) => w.write_i24::<BigEndian>(v)?,
+                (BitDepth::U32(v), _) => w.write_i32::<BigEndian>(v)?,
+                (BitDepth::F32(v), _) => w.write_f32::<BigEndian>(v)?,
+                (BitDepth::F64(v), _) => w.write_f64::<BigEndian>(v)?,
+            }
+        }
+        if !data_size.is_multiple_of(2) {
+            w.write_u8(0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes an 80 bit IEEE 754 extended float, which is how AIFF stores the sample rate
+pub fn f80_to_f64(b: [u8; 10]) -> f64 {
+    let sign = if b[0] & 0x80 != 0 { -1.0 } else { 1.0 };
+    let exponent = (BigEndian::read_u16(&b[0..2]) & 0x7fff) as i32;
+    // The integer bit is explicit, so the mantissa is a plain 64 bit number
+    let mantissa = BigEndian::read_u64(&b[2..10]);
+    if exponent == 0 && mantissa == 0 {
+        return 0.0;
+    }
+    sign * mantissa as f64 * 2f64.powi(exponent - 16383 - 63)
+}
+
+/// Encodes an 80 bit IEEE 754 extended float. Every finite f64 fits exactly.
+pub fn f64_to_f80(v: f64) -> [u8; 10] {
+    let mut b = [0; 10];
+    if v == 0.0 || !v.is_finite() {
+        return b;
+    }
+    let sign = if v < 0.0 { 0x8000 } else { 0 };
+    let bits = v.abs().to_bits();
+    let (exponent, mantissa) = match (bits >> 52) as i32 {
+        // Subnormal, normalise the mantissa by hand
+        0 => {
+            let m = bits & ((1 << 52) - 1);
+            let shift = m.leading_zeros() as i32 - 11;
+            (1 - 1023 - shift, (m << shift) << 11)
+        }
+        e => (e - 1023, ((bits & ((1 << 52) - 1)) | (1 << 52)) << 11),
+    };
+    BigEndian::write_u16(&mut b[0..2], sign | (exponent + 16383) as u16);
+    BigEndian::write_u64(&mut b[2..10], mantissa);
+    b
+}
+
+#[test]
+fn test_f80_sample_rates() {
+    // 44100 Hz as written by every Mac
+    let b = [0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0];
+    assert_eq!(f80_to_f64(b), 44100.0);
+    assert_eq!(f64_to_f80(44100.0), b);
+    for rate in [8000.0, 22050.0, 48000.0, 96000.0, 0.5] {
+        assert_eq!(f80_to_f64(f64_to_f80(rate)), rate);
+    }
+}
+
+#[test]
+fn test_round_trip_compressions() -> Result<(), AiffError> {
+    let buffers = [
+        (Compression::None, vec![BitDepth::U8(-3), BitDepth::U8(5)]),
+        (
+            Compression::None,
+            vec![BitDepth::U24(-300000), BitDepth::U24(7)],
+        ),
+        (
+            Compression::Sowt,
+            vec![BitDepth::U16(-2), BitDepth::U16(300)],
+        ),
+        (
+            Compression::Fl32,
+            vec![BitDepth::F32(0.25), BitDepth::F32(-1.0)],
+        ),
+    ];
+    for (compression, samples) in buffers {
+        let mut file =
+            AiffFile::from_buffer(SampleBuffer::from_interleaved(48000, 2, samples.clone()));
+        file.compression = compression;
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        file.write_to(&mut bytes)?;
+        assert_eq!(bytes.get_ref().len() % 2, 0);
+
+        let read = AiffFile::read_from(&mut bytes)?;
+        assert_eq!(read.compression, compression);
+        assert_eq!(read.data.sample_rate, 48000);
+        assert_eq!(read.data.channels(), 2);
+        assert_eq!(
+            format!("{:?}", read.data.interleaved()),
+            format!("{:?}", samples)
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_bad_comm_fields() -> Result<(), AiffError> {
+    let mut bytes = Vec::new();
+    AiffFile::from_buffer(SampleBuffer::mono(8000, vec![BitDepth::U16(1); 4]))
+        .write_to(&mut bytes)?;
+    let comm = bytes.windows(4).position(|id| id == b"COMM").unwrap() + 8;
+    let read = |bytes: &[u8]| AiffFile::read_from(&mut std::io::Cursor::new(bytes));
+
+    let mut no_channels = bytes.clone();
+    no_channels[comm..comm + 2].fill(0);
+    assert!(matches!(
+        read(&no_channels),
+        Err(AiffError::MalformedChunk(id)) if &id == b"COMM"
+    ));
+    for size in [0u16, 33] {
+        let mut bad_size = bytes.clone();
+        bad_size[comm + 6..comm + 8].copy_from_slice(&size.to_be_bytes());
+        assert!(matches!(
+            read(&bad_size),
+            Err(AiffError::UnsupportedSampleSize(s)) if s == size
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_mismatched_compression() {
+    let mut file = AiffFile::from_buffer(SampleBuffer::mono(8000, vec![BitDepth::U16(0)]));
+    file.compression = Compression::Fl32;
+    let result = file.write_to(&mut Vec::new());
+    assert!(matches!(result, Err(AiffError::SampleMismatch)));
+}
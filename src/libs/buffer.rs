@@ -0,0 +1,160 @@
+use crate::libs::convert::{self, Quantizer};
+use crate::libs::wav::BitDepth;
+use std::{iter::StepBy, slice::ChunksExact};
+
+/// Multi-channel audio. Samples are stored interleaved, one frame after the other, with
+/// planar views built on demand.
+#[derive(Debug, Clone)]
+pub struct SampleBuffer {
+    pub sample_rate: u32,
+    channels: u16,
+    samples: Vec<BitDepth>,
+}
+
+impl SampleBuffer {
+    pub fn new(sample_rate: u32, channels: u16) -> SampleBuffer {
+        SampleBuffer::from_interleaved(sample_rate, channels, Vec::new())
+    }
+    /// Takes samples ordered frame by frame. A trailing partial frame is dropped.
+    pub fn from_interleaved(sample_rate: u32, channels: u16, samples: Vec<BitDepth>) -> Self {
+        assert!(channels > 0, "A buffer needs at least one channel");
+        let mut samples = samples;
+        samples.truncate(samples.len() - samples.len() % channels as usize);
+        SampleBuffer {
+            sample_rate,
+            channels,
+            samples,
+        }
+    }
+    /// Takes one vector of samples per channel. Channels are cut to the shortest one.
+    pub fn from_planar(sample_rate: u32, planes: Vec<Vec<BitDepth>>) -> Self {
+        let frames = planes.iter().map(Vec::len).min().unwrap_or(0);
+        let mut samples = Vec::with_capacity(frames * planes.len());
+        for i in 0..frames {
+            samples.extend(planes.iter().map(|p| p[i]));
+        }
+        SampleBuffer::from_interleaved(sample_rate, planes.len() as u16, samples)
+    }
+    pub fn mono(sample_rate: u32, samples: Vec<BitDepth>) -> Self {
+        SampleBuffer::from_interleaved(sample_rate, 1, samples)
+    }
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+    /// Number of frames, which is the length of each channel
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+    /// Length in seconds
+    pub fn duration(&self) -> f64 {
+        self.frames() as f64 / self.sample_rate as f64
+    }
+    /// Kind of sample the buffer holds, `None` while it's empty
+    pub fn bit_depth(&self) -> Option<BitDepth> {
+        self.samples.first().copied()
+    }
+    pub fn interleaved(&self) -> &[BitDepth] {
+        &self.samples
+    }
+    pub fn interleaved_mut(&mut self) -> &mut [BitDepth] {
+        &mut self.samples
+    }
+    pub fn into_interleaved(self) -> Vec<BitDepth> {
+        self.samples
+    }
+    /// Interleaved samples as floats in [-1, 1], the way cpal wants them
+    pub fn to_f32(&self) -> Vec<f32> {
+        self.samples.iter().map(convert::to_f32).collect()
+    }
+    pub fn to_f64(&self) -> Vec<f64> {
+        self.samples.iter().map(convert::to_f64).collect()
+    }
+    /// Interleaved floats in [-1, 1] quantised to the kind of `bit_depth`
+    pub fn from_f64(
+        sample_rate: u32,
+        channels: u16,
+        samples: &[f64],
+        bit_depth: BitDepth,
+        quantizer: &mut Quantizer,
+    ) -> SampleBuffer {
+        let samples = samples
+            .iter()
+            .map(|&v| quantizer.quantize(v, bit_depth))
+            .collect();
+        SampleBuffer::from_interleaved(sample_rate, channels, samples)
+    }
+    /// Copy of the buffer with every sample converted to the kind of `bit_depth`
+    pub fn convert(&self, bit_depth: BitDepth, quantizer: &mut Quantizer) -> SampleBuffer {
+        SampleBuffer::from_f64(
+            self.sample_rate,
+            self.channels,
+            &self.to_f64(),
+            bit_depth,
+            quantizer,
+        )
+    }
+    /// Copies the samples into one vector per channel
+    pub fn to_planar(&self) -> Vec<Vec<BitDepth>> {
+        (0..self.channels as usize)
+            .map(|c| self.channel(c).copied().collect())
+            .collect()
+    }
+    /// Samples of frame `i`, one for each channel
+    pub fn frame(&self, i: usize) -> &[BitDepth] {
+        let c = self.channels as usize;
+        &self.samples[i * c..(i + 1) * c]
+    }
+    pub fn iter_frames(&self) -> ChunksExact<'_, BitDepth> {
+        self.samples.chunks_exact(self.channels as usize)
+    }
+    /// Iterates over the samples of one channel
+    pub fn channel(&self, channel: usize) -> StepBy<std::slice::Iter<'_, BitDepth>> {
+        assert!(channel < self.channels as usize, "No such channel");
+        self.samples[channel..]
+            .iter()
+            .step_by(self.channels as usize)
+    }
+    pub fn push_frame(&mut self, frame: &[BitDepth]) {
+        assert_eq!(frame.len(), self.channels as usize, "Wrong frame size");
+        self.samples.extend_from_slice(frame);
+    }
+    /// Moves the frames of `other` to the end of this buffer, like `Vec::append`
+    pub fn append(&mut self, other: &mut SampleBuffer) {
+        assert_eq!(self.channels, other.channels, "Channel counts differ");
+        self.samples.append(&mut other.samples);
+    }
+}
+
+#[test]
+fn test_planar_round_trip() {
+    let left = vec![BitDepth::U16(1), BitDepth::U16(2), BitDepth::U16(3)];
+    let right = vec![BitDepth::U16(-1), BitDepth::U16(-2)];
+    let buf = SampleBuffer::from_planar(44100, vec![left, right]);
+    assert_eq!(buf.channels(), 2);
+    assert_eq!(buf.frames(), 2);
+    assert!(matches!(
+        buf.interleaved(),
+        [
+            BitDepth::U16(1),
+            BitDepth::U16(-1),
+            BitDepth::U16(2),
+            BitDepth::U16(-2)
+        ]
+    ));
+    let planes = buf.to_planar();
+    assert!(matches!(
+        planes[1][..],
+        [BitDepth::U16(-1), BitDepth::U16(-2)]
+    ));
+}
+
+#[test]
+fn test_partial_frame_dropped() {
+    let buf = SampleBuffer::from_interleaved(8000, 2, vec![BitDepth::U8(0); 5]);
+    assert_eq!(buf.frames(), 2);
+    assert_eq!(buf.iter_frames().count(), 2);
+    assert_eq!(buf.channel(1).count(), 2);
+}
@@ -0,0 +1,168 @@
+// Conversions between integer samples and floats in [-1, 1]
+use crate::libs::wav::BitDepth;
+
+/// How a float is brought to the integer grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    Nearest,
+    /// What `as` casts do
+    TowardZero,
+    Floor,
+}
+
+/// Noise added before rounding, in LSBs of the target depth. Decorrelates the quantisation
+/// error from the signal, which matters most when going down to 16 or 8 bits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dither {
+    None,
+    /// Uniform noise in [-amplitude/2, amplitude/2]
+    Rectangular(f64),
+    /// Sum of two uniform sources, in [-amplitude, amplitude]
+    Triangular(f64),
+}
+
+/// Full scale of an integer depth. Ints are divided by 2^(bits - 1), so the most negative
+/// value maps exactly to -1.0 and integer round trips are lossless.
+fn full_scale(bit_depth: &BitDepth) -> f64 {
+    match bit_depth {
+        BitDepth::F32(_) | BitDepth::F64(_) => 1.0,
+        _ => (1u64 << (bit_depth.bits() - 1)) as f64,
+    }
+}
+
+pub fn to_f64(s: &BitDepth) -> f64 {
+    match *s {
+        BitDepth::U8(v) => v as f64 / full_scale(s),
+        BitDepth::U16(v) => v as f64 / full_scale(s),
+        BitDepth::U24(v) => v as f64 / full_scale(s),
+        BitDepth::U32(v) => v as f64 / full_scale(s),
+        BitDepth::F32(v) => v as f64,
+        BitDepth::F64(v) => v,
+    }
+}
+
+pub fn to_f32(s: &BitDepth) -> f32 {
+    to_f64(s) as f32
+}
+
+/// Quantises `v` to the kind of `bit_depth` with rounding to nearest and no dither. Integers
+/// saturate instead of wrapping, floats are passed through.
+pub fn from_f64(v: f64, bit_depth: BitDepth) -> BitDepth {
+    Quantizer::new(Rounding::Nearest, Dither::None).quantize(v, bit_depth)
+}
+
+/// Converts a sample to another depth by value, e.g. 0.5 of full scale stays 0.5
+pub fn convert(s: &BitDepth, bit_depth: BitDepth) -> BitDepth {
+    match (*s, bit_depth) {
+        // Widening integers is exact, skip the float round trip
+        (BitDepth::U8(v), BitDepth::U16(_)) => BitDepth::U16((v as i16) << 8),
+        (BitDepth::U16(v), BitDepth::U24(_)) => BitDepth::U24((v as i32) << 8),
+        (BitDepth::U16(v), BitDepth::U32(_)) => BitDepth::U32((v as i32) << 16),
+        (BitDepth::U24(v), BitDepth::U32(_)) => BitDepth::U32(v << 8),
+        _ => from_f64(to_f64(s), bit_depth),
+    }
+}
+
+/// Float to integer conversion with a choice of rounding and dither. Keeps the state of the
+/// noise generator, so use one per stream.
+pub struct Quantizer {
+    pub rounding: Rounding,
+    pub dither: Dither,
+    seed: u32,
+}
+
+impl Quantizer {
+    pub fn new(rounding: Rounding, dither: Dither) -> Quantizer {
+        Quantizer {
+            rounding,
+            dither,
+            seed: 0x9E37_79B9,
+        }
+    }
+
+    pub fn quantize(&mut self, v: f64, bit_depth: BitDepth) -> BitDepth {
+        if bit_depth.is_float() {
+            return match bit_depth {
+                BitDepth::F32(_) => BitDepth::F32(v as f32),
+                _ => BitDepth::F64(v),
+            };
+        }
+        let scale = full_scale(&bit_depth);
+        let v = v * scale + self.noise();
+        let v = match self.rounding {
+            Rounding::Nearest => v.round(),
+            Rounding::TowardZero => v.trunc(),
+            Rounding::Floor => v.floor(),
+        };
+        let v = v.clamp(-scale, scale - 1.0);
+        match bit_depth {
+            BitDepth::U8(_) => BitDepth::U8(v as i8),
+            BitDepth::U16(_) => BitDepth::U16(v as i16),
+            BitDepth::U24(_) => BitDepth::U24(v as i32),
+            _ => BitDepth::U32(v as i32),
+        }
+    }
+
+    fn noise(&mut self) -> f64 {
+        match self.dither {
+            Dither::None => 0.0,
+            Dither::Rectangular(a) => (self.uniform() - 0.5) * a,
+            Dither::Triangular(a) => (self.uniform() - self.uniform()) * a,
+        }
+    }
+
+    /// xorshift32, uniform in [0, 1)
+    fn uniform(&mut self) -> f64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed as f64 / (u32::MAX as f64 + 1.0)
+    }
+}
+
+impl Default for Quantizer {
+    fn default() -> Self {
+        Quantizer::new(Rounding::Nearest, Dither::None)
+    }
+}
+
+#[test]
+fn test_integer_round_trip() {
+    for v in [i16::MIN, -1, 0, 1, i16::MAX] {
+        let s = from_f64(to_f64(&BitDepth::U16(v)), BitDepth::U16(0));
+        assert!(matches!(s, BitDepth::U16(r) if r == v));
+    }
+    assert_eq!(to_f64(&BitDepth::U24(-(1 << 23))), -1.0);
+}
+
+#[test]
+fn test_quantize_saturates() {
+    assert!(matches!(from_f64(1.5, BitDepth::U8(0)), BitDepth::U8(127)));
+    assert!(matches!(
+        from_f64(-1.5, BitDepth::U16(0)),
+        BitDepth::U16(i16::MIN)
+    ));
+    assert!(matches!(from_f64(1.5, BitDepth::F32(0.0)), BitDepth::F32(v) if v == 1.5));
+}
+
+#[test]
+fn test_rounding_modes() {
+    let v = -2.5 / 128.0;
+    let mut q = Quantizer::new(Rounding::TowardZero, Dither::None);
+    assert!(matches!(q.quantize(v, BitDepth::U8(0)), BitDepth::U8(-2)));
+    q.rounding = Rounding::Floor;
+    assert!(matches!(q.quantize(v, BitDepth::U8(0)), BitDepth::U8(-3)));
+    q.rounding = Rounding::Nearest;
+    assert!(matches!(q.quantize(v, BitDepth::U8(0)), BitDepth::U8(-3)));
+}
+
+#[test]
+fn test_triangular_dither_stays_within_one_lsb() {
+    let mut q = Quantizer::new(Rounding::Nearest, Dither::Triangular(1.0));
+    for _ in 0..1000 {
+        match q.quantize(0.0, BitDepth::U16(0)) {
+            BitDepth::U16(v) => assert!((-1..=1).contains(&v)),
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,1362 @@
+// FLAC, lossless compression with linear prediction and Rice coded residuals
+use crate::libs::buffer::SampleBuffer;
+use crate::libs::convert;
+use crate::libs::md5::Md5;
+use crate::libs::wav::BitDepth;
+use std::{
+    fmt,
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+};
+
+const STREAMINFO: u8 = 0;
+const PADDING: u8 = 1;
+const SEEKTABLE: u8 = 3;
+const STREAMINFO_SIZE: usize = 34;
+/// Precision of the LPC coefficients the encoder writes
+const QLP_PRECISION: u32 = 12;
+
+#[derive(Debug)]
+pub enum FlacError {
+    Io(std::io::Error),
+    /// The file doesn't start with `fLaC`
+    NotFlac,
+    MissingStreamInfo,
+    /// The file ends in the middle of a block or frame
+    Truncated,
+    /// A frame or subframe that doesn't follow the format
+    BadFrame,
+    /// A frame header or frame doesn't match its CRC
+    Crc,
+    /// The decoded samples don't match the MD5 in STREAMINFO
+    Md5Mismatch,
+    UnsupportedSampleSize(u32),
+    UnsupportedChannels(u16),
+    UnsupportedSampleRate(u32),
+    /// FLAC only stores integer samples
+    FloatSamples,
+}
+
+impl fmt::Display for FlacError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlacError::Io(e) => write!(f, "I/O error: {e}"),
+            FlacError::NotFlac => write!(f, "Not a FLAC file"),
+            FlacError::MissingStreamInfo => write!(f, "Missing STREAMINFO block"),
+            FlacError::Truncated => write!(f, "File is truncated"),
+            FlacError::BadFrame => write!(f, "Malformed frame"),
+            FlacError::Crc => write!(f, "Frame doesn't match its CRC"),
+            FlacError::Md5Mismatch => write!(f, "Decoded samples don't match the MD5"),
+            FlacError::UnsupportedSampleSize(bits) => {
+                write!(f, "Unsupported sample size of {bits} bits")
+            }
+            FlacError::UnsupportedChannels(n) => write!(f, "Unsupported channel count {n}"),
+            FlacError::UnsupportedSampleRate(rate) => {
+                write!(f, "Unsupported sample rate {rate}")
+            }
+            FlacError::FloatSamples => write!(f, "FLAC can't store float samples"),
+        }
+    }
+}
+
+impl std::error::Error for FlacError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FlacError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for FlacError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            ErrorKind::UnexpectedEof => FlacError::Truncated,
+            _ => FlacError::Io(e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub min_block_size: u16,
+    pub max_block_size: u16,
+    /// 0 when unknown
+    pub min_frame_size: u32,
+    pub max_frame_size: u32,
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub bits_per_sample: u8,
+    /// Length in frames, 0 when unknown
+    pub total_samples: u64,
+    /// MD5 of the decoded samples, all zeros when unknown
+    pub md5: [u8; 16],
+}
+
+impl StreamInfo {
+    /// Reads just the STREAMINFO block at the start of a file
+    pub fn read<R: Read>(r: &mut R) -> Result<StreamInfo, FlacError> {
+        let mut hdr = [0; 8];
+        r.read_exact(&mut hdr)?;
+        if &hdr[0..4] != b"fLaC" {
+            return Err(FlacError::NotFlac);
+        }
+        if hdr[4] & 0x7F != STREAMINFO {
+            return Err(FlacError::MissingStreamInfo);
+        }
+        let mut body = [0; STREAMINFO_SIZE];
+        r.read_exact(&mut body)?;
+        StreamInfo::parse(&body)
+    }
+    fn parse(b: &[u8]) -> Result<StreamInfo, FlacError> {
+        let mut r = BitReader::new(b);
+        let mut info = StreamInfo {
+            min_block_size: r.read(16)? as u16,
+            max_block_size: r.read(16)? as u16,
+            min_frame_size: r.read(24)? as u32,
+            max_frame_size: r.read(24)? as u32,
+            sample_rate: r.read(20)? as u32,
+            channels: r.read(3)? as u8 + 1,
+            bits_per_sample: r.read(5)? as u8 + 1,
+            total_samples: r.read(36)?,
+            md5: [0; 16],
+        };
+        for byte in info.md5.iter_mut() {
+            *byte = r.read(8)? as u8;
+        }
+        if info.bits_per_sample < 4 {
+            return Err(FlacError::UnsupportedSampleSize(
+                info.bits_per_sample as u32,
+            ));
+        }
+        Ok(info)
+    }
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut w = BitWriter::new();
+        w.write(self.min_block_size as u64, 16);
+        w.write(self.max_block_size as u64, 16);
+        w.write(self.min_frame_size as u64, 24);
+        w.write(self.max_frame_size as u64, 24);
+        w.write(self.sample_rate as u64, 20);
+        w.write(self.channels as u64 - 1, 3);
+        w.write(self.bits_per_sample as u64 - 1, 5);
+        w.write(self.total_samples, 36);
+        for &byte in &self.md5 {
+            w.write(byte as u64, 8);
+        }
+        w.bytes
+    }
+}
+
+/// A metadata block the codec doesn't interpret, like VORBIS_COMMENT or PICTURE
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    pub kind: u8,
+    pub data: Vec<u8>,
+}
+
+/// Encoder settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionLevel {
+    /// Frames in each FLAC frame
+    pub block_size: usize,
+    /// 0 to only use the fixed predictors
+    pub max_lpc_order: usize,
+    pub max_partition_order: u32,
+    /// Try left/side, right/side and mid/side on stereo
+    pub stereo_decorrelation: bool,
+}
+
+impl CompressionLevel {
+    /// Close to the levels of the reference encoder, from 0 (fastest) to 8 (smallest)
+    pub fn new(level: u8) -> CompressionLevel {
+        let (block_size, max_lpc_order, max_partition_order, stereo_decorrelation) = match level {
+            0 => (1152, 0, 3, false),
+            1 | 2 => (1152, 0, 3, true),
+            3 => (4096, 6, 4, false),
+            4 | 5 => (4096, 8, 5, true),
+            6 => (4096, 8, 6, true),
+            _ => (4096, 12, 6, true),
+        };
+        CompressionLevel {
+            block_size,
+            max_lpc_order,
+            max_partition_order,
+            stereo_decorrelation,
+        }
+    }
+}
+
+impl Default for CompressionLevel {
+    fn default() -> Self {
+        CompressionLevel::new(5)
+    }
+}
+
+pub struct FlacFile {
+    pub data: SampleBuffer,
+    /// Settings `write` encodes with
+    pub level: CompressionLevel,
+    /// Blocks other than STREAMINFO, SEEKTABLE and PADDING, kept so they are written back
+    pub metadata: Vec<Metadata>,
+}
+
+impl FlacFile {
+    /// Integer samples only, floats have to be converted first
+    pub fn from_buffer(data: SampleBuffer) -> FlacFile {
+        FlacFile {
+            data,
+            level: CompressionLevel::default(),
+            metadata: Vec::new(),
+        }
+    }
+    pub fn read(path: &Path) -> Result<FlacFile, FlacError> {
+        FlacFile::read_from(&mut BufReader::new(File::open(path)?))
+    }
+    /// Decodes the whole stream and checks it against the MD5 in STREAMINFO
+    pub fn read_from<R: Read>(r: &mut R) -> Result<FlacFile, FlacError> {
+        let mut bytes = Vec::new();
+        r.read_to_end(&mut bytes)?;
+        let mut pos = id3_len(&bytes);
+        if bytes.get(pos..pos + 4) != Some(b"fLaC") {
+            return Err(FlacError::NotFlac);
+        }
+        pos += 4;
+
+        let mut info = None;
+        let mut metadata = Vec::new();
+        loop {
+            let hdr = bytes.get(pos..pos + 4).ok_or(FlacError::Truncated)?;
+            let last = hdr[0] & 0x80 != 0;
+            let kind = hdr[0] & 0x7F;
+            let len = u32::from_be_bytes([0, hdr[1], hdr[2], hdr[3]]) as usize;
+            let body = bytes
+                .get(pos + 4..pos + 4 + len)
+                .ok_or(FlacError::Truncated)?;
+            match kind {
+                STREAMINFO => info = Some(StreamInfo::parse(body)?),
+                PADDING | SEEKTABLE => {}
+                _ => metadata.push(Metadata {
+                    kind,
+                    data: body.to_vec(),
+                }),
+            }
+            pos += 4 + len;
+            if last {
+                break;
+            }
+        }
+        let info = info.ok_or(FlacError::MissingStreamInfo)?;
+        let channels = info.channels as usize;
+        let bits = info.bits_per_sample as u32;
+
+        let mut md5 = Md5::new();
+        let sample_bytes = bits.div_ceil(8) as usize;
+        // The total comes from the header, so reserve no more than the rest of the file could
+        // hold at a byte a sample
+        let mut samples = Vec::with_capacity(
+            usize::try_from(info.total_samples)
+                .unwrap_or(usize::MAX)
+                .saturating_mul(channels)
+                .min(bytes.len() - pos),
+        );
+        // Without a total, frames run to the end of the file or the first thing that isn't one
+        while (info.total_samples == 0 && bytes.get(pos..pos + 2).is_some_and(is_sync))
+            || (samples.len() / channels) < info.total_samples as usize
+        {
+            let (len, planes) = decode_frame(&bytes[pos..], &info)?;
+            if planes.len() != channels {
+                return Err(FlacError::BadFrame);
+            }
+            for i in 0..planes[0].len() {
+                for plane in &planes {
+                    md5.update(&plane[i].to_le_bytes()[..sample_bytes]);
+                    samples.push(to_sample(plane[i], bits));
+                }
+            }
+            pos += len;
+        }
+        if info.md5 != [0; 16] && md5.finish() != info.md5 {
+            return Err(FlacError::Md5Mismatch);
+        }
+        Ok(FlacFile {
+            data: SampleBuffer::from_interleaved(info.sample_rate, info.channels as u16, samples),
+            level: CompressionLevel::default(),
+            metadata,
+        })
+    }
+    pub fn write(&self, path: &Path) -> Result<(), FlacError> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_to(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<(), FlacError> {
+        let data = &self.data;
+        let (kind, bits) = match data.bit_depth() {
+            Some(BitDepth::F32(_) | BitDepth::F64(_)) => return Err(FlacError::FloatSamples),
+            Some(kind) => (kind, kind.bits() as u32),
+            None => (BitDepth::U16(0), 16),
+        };
+        let channels = data.channels();
+        if !(1..=8).contains(&channels) {
+            return Err(FlacError::UnsupportedChannels(channels));
+        }
+        if data.sample_rate == 0 || data.sample_rate > 0xFFFFF {
+            return Err(FlacError::UnsupportedSampleRate(data.sample_rate));
+        }
+
+        let values: Vec<i64> = data
+            .interleaved()
+            .iter()
+            .map(|s| match convert::convert(s, kind) {
+                BitDepth::U8(v) => v as i64,
+                BitDepth::U16(v) => v as i64,
+                BitDepth::U24(v) | BitDepth::U32(v) => v as i64,
+                _ => unreachable!(),
+            })
+            .collect();
+        let mut md5 = Md5::new();
+        for v in &values {
+            md5.update(&v.to_le_bytes()[..bits as usize / 8]);
+        }
+
+        let level = self.level;
+        let block_size = level.block_size.clamp(16, 65535);
+        let frames = data.frames();
+        let channels = channels as usize;
+        let mut encoded = Vec::new();
+        let mut min_frame_size = u32::MAX;
+        let mut max_frame_size = 0;
+        for (number, start) in (0..frames).step_by(block_size).enumerate() {
+            let end = (start + block_size).min(frames);
+            let planes: Vec<Vec<i64>> = (0..channels)
+                .map(|c| {
+                    values[start * channels..end * channels]
+                        .iter()
+                        .skip(c)
+                        .step_by(channels)
+                        .copied()
+                        .collect()
+                })
+                .collect();
+            let frame = encode_frame(&planes, number as u64, bits, data.sample_rate, &level);
+            min_frame_size = min_frame_size.min(frame.len() as u32);
+            max_frame_size = max_frame_size.max(frame.len() as u32);
+            encoded.extend(frame);
+        }
+        // Like libFLAC, the block size is given even when the stream is shorter, since the last
+        // block is allowed to be short and anything under 16 is invalid
+        let info = StreamInfo {
+            min_block_size: block_size as u16,
+            max_block_size: block_size as u16,
+            min_frame_size: if frames == 0 { 0 } else { min_frame_size },
+            max_frame_size,
+            sample_rate: data.sample_rate,
+            channels: channels as u8,
+            bits_per_sample: bits as u8,
+            total_samples: frames as u64,
+            md5: md5.finish(),
+        };
+
+        w.write_all(b"fLaC")?;
+        let mut blocks = vec![(STREAMINFO, info.to_bytes())];
+        blocks.extend(self.metadata.iter().map(|m| (m.kind, m.data.clone())));
+        let count = blocks.len();
+        for (i, (kind, body)) in blocks.into_iter().enumerate() {
+            let last = if i + 1 == count { 0x80 } else { 0 };
+            w.write_all(&[kind | last])?;
+            w.write_all(&(body.len() as u32).to_be_bytes()[1..])?;
+            w.write_all(&body)?;
+        }
+        w.write_all(&encoded)?;
+        Ok(())
+    }
+}
+
+/// Length of an ID3v2 tag in front of the stream, which some taggers add
+pub fn id3_len(b: &[u8]) -> usize {
+    if b.len() < 10 || &b[0..3] != b"ID3" {
+        return 0;
+    }
+    let size = b[6..10]
+        .iter()
+        .fold(0, |acc, &byte| (acc << 7) | (byte & 0x7F) as usize);
+    let footer = if b[5] & 0x10 != 0 { 10 } else { 0 };
+    10 + size + footer
+}
+
+fn is_sync(b: &[u8]) -> bool {
+    b[0] == 0xFF && b[1] & 0xFE == 0xF8
+}
+
+/// Widens a sample of an odd size like 12 or 20 bits to the next `BitDepth`
+fn to_sample(v: i64, bits: u32) -> BitDepth {
+    match bits {
+        ..=8 => BitDepth::U8((v << (8 - bits)) as i8),
+        9..=16 => BitDepth::U16((v << (16 - bits)) as i16),
+        17..=24 => BitDepth::U24((v << (24 - bits)) as i32),
+        _ => BitDepth::U32((v << (32 - bits)) as i32),
+    }
+}
+
+/// Decodes one frame, returning its length in bytes and the samples of each channel
+fn decode_frame(data: &[u8], info: &StreamInfo) -> Result<(usize, Vec<Vec<i64>>), FlacError> {
+    let mut r = BitReader::new(data);
+    // 14 sync bits and a reserved 0
+    if r.read(15)? != 0x7FFC {
+        return Err(FlacError::BadFrame);
+    }
+    // Fixed or variable block size, which only changes what the coded number counts
+    r.read(1)?;
+    let block_size_code = r.read(4)?;
+    let sample_rate_code = r.read(4)?;
+    let assignment = r.read(4)?;
+    let sample_size_code = r.read(3)?;
+    r.read(1)?;
+    read_utf8(&mut r)?;
+    let block_size = match block_size_code {
+        0 => return Err(FlacError::BadFrame),
+        1 => 192,
+        2..=5 => 576 << (block_size_code - 2),
+        6 => r.read(8)? + 1,
+        7 => r.read(16)? + 1,
+        _ => 256 << (block_size_code - 8),
+    } as usize;
+    // Only the size of the field matters, the rate in STREAMINFO is the one used
+    match sample_rate_code {
+        12 => r.skip(8),
+        13 | 14 => r.skip(16),
+        15 => return Err(FlacError::BadFrame),
+        _ => {}
+    }
+    let bits = match sample_size_code {
+        0 => info.bits_per_sample as u32,
+        1 => 8,
+        2 => 12,
+        4 => 16,
+        5 => 20,
+        6 => 24,
+        7 => 32,
+        _ => return Err(FlacError::BadFrame),
+    };
+    let header_len = r.byte_pos();
+    if r.read(8)? as u8 != crc8(&data[..header_len]) {
+        return Err(FlacError::Crc);
+    }
+
+    let channels = match assignment {
+        0..=7 => assignment + 1,
+        8..=10 => 2,
+        _ => return Err(FlacError::BadFrame),
+    };
+    let mut planes = Vec::with_capacity(channels as usize);
+    for channel in 0..channels {
+        // The side channel needs an extra bit
+        let side = matches!((assignment, channel), (8, 1) | (9, 0) | (10, 1));
+        planes.push(decode_subframe(&mut r, block_size, bits + side as u32)?);
+    }
+    r.align();
+    let frame_len = r.byte_pos();
+    if r.read(16)? as u16 != crc16(&data[..frame_len]) {
+        return Err(FlacError::Crc);
+    }
+
+    // Arithmetic on decoded samples wraps like libFLAC's, a bad stream decodes to noise
+    if let [a, b] = &mut planes[..] {
+        for (a, b) in a.iter_mut().zip(b.iter_mut()) {
+            match assignment {
+                // Left and side
+                8 => *b = a.wrapping_sub(*b),
+                // Side and right
+                9 => *a = a.wrapping_add(*b),
+                // Mid and side
+                10 => {
+                    let mid = a.wrapping_shl(1) | (*b & 1);
+                    (*a, *b) = (mid.wrapping_add(*b) >> 1, mid.wrapping_sub(*b) >> 1);
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok((frame_len + 2, planes))
+}
+
+fn decode_subframe(r: &mut BitReader, n: usize, bits: u32) -> Result<Vec<i64>, FlacError> {
+    if r.read(1)? != 0 {
+        return Err(FlacError::BadFrame);
+    }
+    let kind = r.read(6)?;
+    let wasted = if r.read(1)? == 1 {
+        r.read_unary()? as u32 + 1
+    } else {
+        0
+    };
+    if wasted >= bits {
+        return Err(FlacError::BadFrame);
+    }
+    let bits = bits - wasted;
+
+    let mut s = match kind {
+        0 => vec![r.read_signed(bits)?; n],
+        1 => (0..n)
+            .map(|_| r.read_signed(bits))
+            .collect::<Result<_, _>>()?,
+        8..=12 => {
+            let order = kind as usize - 8;
+            let mut s = read_warmup(r, n, order, bits)?;
+            read_residual(r, n, order, &mut s)?;
+            restore_fixed(&mut s, order);
+            s
+        }
+        32..=63 => {
+            let order = kind as usize - 31;
+            let mut s = read_warmup(r, n, order, bits)?;
+            let precision = r.read(4)? as u32 + 1;
+            let shift = r.read_signed(5)?;
+            if precision == 16 || shift < 0 {
+                return Err(FlacError::BadFrame);
+            }
+            let coefs = (0..order)
+                .map(|_| r.read_signed(precision))
+                .collect::<Result<Vec<_>, _>>()?;
+            read_residual(r, n, order, &mut s)?;
+            restore_lpc(&mut s, &coefs, shift as u32);
+            s
+        }
+        _ => return Err(FlacError::BadFrame),
+    };
+    if wasted > 0 {
+        for v in s.iter_mut() {
+            *v = v.wrapping_shl(wasted);
+        }
+    }
+    Ok(s)
+}
+
+fn read_warmup(
+    r: &mut BitReader,
+    n: usize,
+    order: usize,
+    bits: u32,
+) -> Result<Vec<i64>, FlacError> {
+    if order > n {
+        return Err(FlacError::BadFrame);
+    }
+    let mut s = Vec::with_capacity(n);
+    for _ in 0..order {
+        s.push(r.read_signed(bits)?);
+    }
+    Ok(s)
+}
+
+/// Appends the residual of a block of `n` samples to `s`
+fn read_residual(
+    r: &mut BitReader,
+    n: usize,
+    order: usize,
+    s: &mut Vec<i64>,
+) -> Result<(), FlacError> {
+    let (param_bits, escape) = match r.read(2)? {
+        0 => (4, 15),
+        1 => (5, 31),
+        _ => return Err(FlacError::BadFrame),
+    };
+    let partition_order = r.read(4)?;
+    let partitions = 1 << partition_order;
+    if !n.is_multiple_of(partitions) || n / partitions < order {
+        return Err(FlacError::BadFrame);
+    }
+    for p in 0..partitions {
+        let count = n / partitions - if p == 0 { order } else { 0 };
+        let k = r.read(param_bits)?;
+        if k == escape {
+            let bits = r.read(5)? as u32;
+            for _ in 0..count {
+                s.push(r.read_signed(bits)?);
+            }
+        } else {
+            for _ in 0..count {
+                let u = (r.read_unary()? << k) | r.read(k as u32)?;
+                s.push(unzigzag(u));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prediction from the previous `order` samples with the fixed polynomial coefficients
+fn fixed_prediction(s: &[i64], i: usize, order: usize) -> i64 {
+    const COEFS: [&[i64]; 5] = [&[], &[1], &[2, -1], &[3, -3, 1], &[4, -6, 4, -1]];
+    lpc_prediction(s, i, COEFS[order.min(4)], 0)
+}
+
+/// Wraps on overflow, which only a bad stream can cause
+fn lpc_prediction(s: &[i64], i: usize, coefs: &[i64], shift: u32) -> i64 {
+    let sum = coefs.iter().enumerate().fold(0i64, |sum, (j, c)| {
+        sum.wrapping_add(c.wrapping_mul(s[i - 1 - j]))
+    });
+    sum >> shift
+}
+
+fn restore_fixed(s: &mut [i64], order: usize) {
+    for i in order..s.len() {
+        s[i] = s[i].wrapping_add(fixed_prediction(s, i, order));
+    }
+}
+
+fn restore_lpc(s: &mut [i64], coefs: &[i64], shift: u32) {
+    for i in coefs.len()..s.len() {
+        s[i] = s[i].wrapping_add(lpc_prediction(s, i, coefs, shift));
+    }
+}
+
+fn zigzag(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+fn unzigzag(u: u64) -> i64 {
+    (u >> 1) as i64 ^ -((u & 1) as i64)
+}
+
+enum Predictor {
+    Constant,
+    Verbatim,
+    Fixed(usize),
+    Lpc { coefs: Vec<i64>, shift: u32 },
+}
+
+impl Predictor {
+    fn order(&self) -> usize {
+        match self {
+            Predictor::Constant | Predictor::Verbatim => 0,
+            Predictor::Fixed(order) => *order,
+            Predictor::Lpc { coefs, .. } => coefs.len(),
+        }
+    }
+}
+
+/// How the residual is split into partitions and the Rice parameter of each
+struct RicePlan {
+    partition_order: u32,
+    params: Vec<u32>,
+    /// 5 bit parameters instead of 4
+    wide: bool,
+}
+
+/// A subframe chosen by the encoder, with its estimated size
+struct Subframe {
+    predictor: Predictor,
+    wasted: u32,
+    bits: u32,
+    /// Samples with the wasted bits shifted out
+    samples: Vec<i64>,
+    residual: Vec<i64>,
+    rice: RicePlan,
+    size: u64,
+}
+
+fn encode_frame(
+    planes: &[Vec<i64>],
+    number: u64,
+    bits: u32,
+    sample_rate: u32,
+    level: &CompressionLevel,
+) -> Vec<u8> {
+    let n = planes[0].len();
+    let (assignment, subframes) = match planes {
+        [l, r] if level.stereo_decorrelation && bits < 32 => {
+            let side: Vec<i64> = l.iter().zip(r).map(|(l, r)| l - r).collect();
+            let mid: Vec<i64> = l.iter().zip(r).map(|(l, r)| (l + r) >> 1).collect();
+            let [l, r, mid, side] = [
+                plan_subframe(l, bits, level),
+                plan_subframe(r, bits, level),
+                plan_subframe(&mid, bits, level),
+                plan_subframe(&side, bits + 1, level),
+            ];
+            let sizes = [
+                l.size + r.size,
+                l.size + side.size,
+                side.size + r.size,
+                mid.size + side.size,
+            ];
+            let best = (0..4).min_by_key(|&i| sizes[i]).unwrap();
+            match best {
+                0 => (1, vec![l, r]),
+                1 => (8, vec![l, side]),
+                2 => (9, vec![side, r]),
+                _ => (10, vec![mid, side]),
+            }
+        }
+        _ => (
+            planes.len() as u64 - 1,
+            planes
+                .iter()
+                .map(|p| plan_subframe(p, bits, level))
+                .collect(),
+        ),
+    };
+
+    let mut w = BitWriter::new();
+    w.write(0xFFF8, 16);
+    let (block_size_code, block_size_extra) = match n {
+        192 => (1, None),
+        576 | 1152 | 2304 | 4608 => (2 + (n / 576).trailing_zeros() as u64, None),
+        256 | 512 | 1024 | 2048 | 4096 | 8192 | 16384 | 32768 => {
+            (8 + (n / 256).trailing_zeros() as u64, None)
+        }
+        ..=256 => (6, Some((n as u64 - 1, 8))),
+        _ => (7, Some((n as u64 - 1, 16))),
+    };
+    let (sample_rate_code, sample_rate_extra) = match sample_rate {
+        88200 => (1, None),
+        176400 => (2, None),
+        192000 => (3, None),
+        8000 => (4, None),
+        16000 => (5, None),
+        22050 => (6, None),
+        24000 => (7, None),
+        32000 => (8, None),
+        44100 => (9, None),
+        48000 => (10, None),
+        96000 => (11, None),
+        r if r % 1000 == 0 && r / 1000 <= 0xFF => (12, Some((r as u64 / 1000, 8))),
+        r if r <= 0xFFFF => (13, Some((r as u64, 16))),
+        r if r % 10 == 0 && r / 10 <= 0xFFFF => (14, Some((r as u64 / 10, 16))),
+        _ => (0, None),
+    };
+    let sample_size_code = match bits {
+        8 => 1,
+        12 => 2,
+        16 => 4,
+        20 => 5,
+        24 => 6,
+        32 => 7,
+        _ => 0,
+    };
+    w.write(block_size_code, 4);
+    w.write(sample_rate_code, 4);
+    w.write(assignment, 4);
+    w.write(sample_size_code, 3);
+    w.write(0, 1);
+    write_utf8(&mut w, number);
+    for (v, bits) in block_size_extra.into_iter().chain(sample_rate_extra) {
+        w.write(v, bits);
+    }
+    let crc = crc8(&w.bytes);
+    w.write(crc as u64, 8);
+
+    for subframe in &subframes {
+        write_subframe(&mut w, subframe);
+    }
+    w.align();
+    let crc = crc16(&w.bytes);
+    w.write(crc as u64, 16);
+    w.bytes
+}
+
+/// Tries every predictor the level allows and keeps the smallest
+fn plan_subframe(samples: &[i64], bits: u32, level: &CompressionLevel) -> Subframe {
+    let n = samples.len();
+    let verbatim = |samples: Vec<i64>, wasted, bits| Subframe {
+        size: 8 + wasted as u64 + n as u64 * bits as u64,
+        predictor: Predictor::Verbatim,
+        wasted,
+        bits,
+        samples,
+        residual: Vec::new(),
+        rice: RicePlan {
+            partition_order: 0,
+            params: Vec::new(),
+            wide: false,
+        },
+    };
+    if samples.iter().all(|&v| v == samples[0]) {
+        return Subframe {
+            predictor: Predictor::Constant,
+            size: 8 + bits as u64,
+            ..verbatim(samples.to_vec(), 0, bits)
+        };
+    }
+    // Low bits that are zero in every sample, like 16 bit audio in a 24 bit file
+    let wasted = samples
+        .iter()
+        .fold(0, |acc, &v| acc | v)
+        .trailing_zeros()
+        .min(bits - 1);
+    let shifted: Vec<i64> = samples.iter().map(|&v| v >> wasted).collect();
+    let bits = bits - wasted;
+    let mut best = verbatim(shifted.clone(), wasted, bits);
+
+    let mut candidates: Vec<(Predictor, Vec<i64>)> = Vec::new();
+    for order in 0..=4.min(n - 1) {
+        let residual = (order..n)
+            .map(|i| shifted[i] - fixed_prediction(&shifted, i, order))
+            .collect();
+        candidates.push((Predictor::Fixed(order), residual));
+    }
+    if level.max_lpc_order > 0 && n > level.max_lpc_order {
+        for coefs in lpc_coefs(&shifted, level.max_lpc_order) {
+            let Some((coefs, shift)) = quantize_coefs(&coefs, QLP_PRECISION) else {
+                continue;
+            };
+            let residual = (coefs.len()..n)
+                .map(|i| shifted[i] - lpc_prediction(&shifted, i, &coefs, shift))
+                .collect();
+            candidates.push((Predictor::Lpc { coefs, shift }, residual));
+        }
+    }
+
+    for (predictor, residual) in candidates {
+        // Decoders can assume residuals fit in 32 bits
+        if residual.iter().any(|&v| i32::try_from(v).is_err()) {
+            continue;
+        }
+        let order = predictor.order();
+        let (rice, rice_size) = plan_rice(&residual, order, level.max_partition_order, bits > 16);
+        let coef_size = match &predictor {
+            Predictor::Lpc { .. } => 4 + 5 + order as u64 * QLP_PRECISION as u64,
+            _ => 0,
+        };
+        let size = 8 + wasted as u64 + order as u64 * bits as u64 + coef_size + 6 + rice_size;
+        if size < best.size {
+            best = Subframe {
+                predictor,
+                wasted,
+                bits,
+                samples: shifted.clone(),
+                residual,
+                rice,
+                size,
+            };
+        }
+    }
+    best
+}
+
+/// Picks the partition order and Rice parameters, estimating the size from the sum of each
+/// partition the way the reference encoder does
+fn plan_rice(residual: &[i64], order: usize, max_order: u32, wide: bool) -> (RicePlan, u64) {
+    let n = residual.len() + order;
+    let mut partition_order = max_order.min(15);
+    while partition_order > 0
+        && (!n.is_multiple_of(1 << partition_order) || (n >> partition_order) < order)
+    {
+        partition_order -= 1;
+    }
+    // Sums and counts of the finest partitions, merged pairwise for each coarser order
+    let len = n >> partition_order;
+    let mut sums = Vec::new();
+    let mut counts = Vec::new();
+    let mut start = 0;
+    for p in 0..1 << partition_order {
+        let count = len - if p == 0 { order } else { 0 };
+        sums.push(
+            residual[start..start + count]
+                .iter()
+                .map(|&v| zigzag(v))
+                .sum::<u64>(),
+        );
+        counts.push(count as u64);
+        start += count;
+    }
+
+    let (limit, param_bits) = if wide { (30, 5) } else { (14, 4) };
+    let mut best: Option<(RicePlan, u64)> = None;
+    loop {
+        let mut params = Vec::new();
+        let mut size = 0;
+        for (&sum, &count) in sums.iter().zip(&counts) {
+            let k = match sum.checked_div(count) {
+                Some(mean) if mean > 0 => (63 - mean.leading_zeros()).min(limit),
+                _ => 0,
+            };
+            params.push(k);
+            size += param_bits + count * (k as u64 + 1) + (sum >> k);
+        }
+        if best.as_ref().is_none_or(|(_, s)| size < *s) {
+            best = Some((
+                RicePlan {
+                    partition_order,
+                    params,
+                    wide,
+                },
+                size,
+            ));
+        }
+        if partition_order == 0 {
+            break;
+        }
+        sums = sums.chunks(2).map(|c| c[0] + c[1]).collect();
+        counts = counts.chunks(2).map(|c| c[0] + c[1]).collect();
+        partition_order -= 1;
+    }
+    best.unwrap()
+}
+
+/// Predictor coefficients for every order up to `max_order`, from the autocorrelation of the
+/// block under a Welch window
+fn lpc_coefs(samples: &[i64], max_order: usize) -> Vec<Vec<f64>> {
+    let n = samples.len();
+    let half = (n as f64 - 1.0) / 2.0;
+    let windowed: Vec<f64> = samples
+        .iter()
+        .enumerate()
+        .map(|(i, &v)| {
+            let x = (i as f64 - half) / (half + 1.0);
+            v as f64 * (1.0 - x * x)
+        })
+        .collect();
+    let autoc: Vec<f64> = (0..=max_order)
+        .map(|lag| (lag..n).map(|i| windowed[i] * windowed[i - lag]).sum())
+        .collect();
+    if autoc[0] == 0.0 {
+        return Vec::new();
+    }
+
+    // Levinson-Durbin
+    let mut lpc = vec![0.0; max_order];
+    let mut err = autoc[0];
+    let mut orders = Vec::new();
+    for i in 0..max_order {
+        let mut k = -autoc[i + 1];
+        for j in 0..i {
+            k -= lpc[j] * autoc[i - j];
+        }
+        k /= err;
+        let prev = lpc.clone();
+        lpc[i] = k;
+        for j in 0..i {
+            lpc[j] = prev[j] + k * prev[i - 1 - j];
+        }
+        err *= 1.0 - k * k;
+        orders.push(lpc[..=i].iter().map(|c| -c).collect());
+        if err <= 0.0 {
+            break;
+        }
+    }
+    orders
+}
+
+/// Rounds the coefficients to `precision` bit integers and the shift that scales them back
+fn quantize_coefs(coefs: &[f64], precision: u32) -> Option<(Vec<i64>, u32)> {
+    let max = coefs.iter().fold(0.0f64, |m, c| m.max(c.abs()));
+    if !(max > 0.0 && max.is_finite()) {
+        return None;
+    }
+    let shift = (precision as i32 - 1 - (max.log2().floor() as i32 + 1)).clamp(0, 15);
+    let limit = (1 << (precision - 1)) as f64;
+    // The rounding error of each coefficient is carried into the next one
+    let mut error = 0.0;
+    let quantized = coefs
+        .iter()
+        .map(|c| {
+            let v = c * (1 << shift) as f64 + error;
+            let q = v.round().clamp(-limit, limit - 1.0);
+            error = v - q;
+            q as i64
+        })
+        .collect();
+    Some((quantized, shift as u32))
+}
+
+fn write_subframe(w: &mut BitWriter, sf: &Subframe) {
+    let kind = match &sf.predictor {
+        Predictor::Constant => 0,
+        Predictor::Verbatim => 1,
+        Predictor::Fixed(order) => 8 + *order as u64,
+        Predictor::Lpc { coefs, .. } => 31 + coefs.len() as u64,
+    };
+    w.write(0, 1);
+    w.write(kind, 6);
+    if sf.wasted > 0 {
+        w.write(1, 1);
+        w.write_unary(sf.wasted as u64 - 1);
+    } else {
+        w.write(0, 1);
+    }
+    let order = sf.predictor.order();
+    match &sf.predictor {
+        Predictor::Constant => w.write_signed(sf.samples[0], sf.bits),
+        Predictor::Verbatim => {
+            for &v in &sf.samples {
+                w.write_signed(v, sf.bits);
+            }
+        }
+        Predictor::Fixed(_) | Predictor::Lpc { .. } => {
+            for &v in &sf.samples[..order] {
+                w.write_signed(v, sf.bits);
+            }
+            if let Predictor::Lpc { coefs, shift } = &sf.predictor {
+                w.write(QLP_PRECISION as u64 - 1, 4);
+                w.write(*shift as u64, 5);
+                for &c in coefs {
+                    w.write_signed(c, QLP_PRECISION);
+                }
+            }
+            write_residual(w, &sf.residual, order, &sf.rice);
+        }
+    }
+}
+
+fn write_residual(w: &mut BitWriter, residual: &[i64], order: usize, rice: &RicePlan) {
+    let param_bits = if rice.wide { 5 } else { 4 };
+    w.write(rice.wide as u64, 2);
+    w.write(rice.partition_order as u64, 4);
+    let len = (residual.len() + order) >> rice.partition_order;
+    let mut start = 0;
+    for (p, &k) in rice.params.iter().enumerate() {
+        let count = len - if p == 0 { order } else { 0 };
+        w.write(k as u64, param_bits);
+        for &v in &residual[start..start + count] {
+            let u = zigzag(v);
+            w.write_unary(u >> k);
+            w.write(u, k);
+        }
+        start += count;
+    }
+}
+
+/// The UTF-8 like coding of frame numbers, up to 36 bits in 7 bytes
+fn read_utf8(r: &mut BitReader) -> Result<u64, FlacError> {
+    let first = r.read(8)?;
+    let len = (first as u8).leading_ones();
+    if len == 0 {
+        return Ok(first);
+    }
+    if len == 1 || len == 8 {
+        return Err(FlacError::BadFrame);
+    }
+    let mut v = first & (0x7F >> len);
+    for _ in 1..len {
+        let byte = r.read(8)?;
+        if byte & 0xC0 != 0x80 {
+            return Err(FlacError::BadFrame);
+        }
+        v = (v << 6) | (byte & 0x3F);
+    }
+    Ok(v)
+}
+
+fn write_utf8(w: &mut BitWriter, v: u64) {
+    if v < 0x80 {
+        w.write(v, 8);
+        return;
+    }
+    let len = (2..7).find(|&len| v < 1 << (5 * len + 1)).unwrap_or(7);
+    w.write(
+        ((0xFF00u16 >> len) as u8) as u64 | (v >> (6 * (len - 1))),
+        8,
+    );
+    for i in (0..len - 1).rev() {
+        w.write(0x80 | ((v >> (6 * i)) & 0x3F), 8);
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Reads big-endian bit fields, most significant bit first
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// In bits
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader { data, pos: 0 }
+    }
+    fn byte(&self) -> Result<u8, FlacError> {
+        self.data
+            .get(self.pos / 8)
+            .copied()
+            .ok_or(FlacError::Truncated)
+    }
+    fn read(&mut self, bits: u32) -> Result<u64, FlacError> {
+        let mut v = 0;
+        let mut left = bits;
+        while left > 0 {
+            let avail = 8 - (self.pos % 8) as u32;
+            let take = avail.min(left);
+            let chunk = (self.byte()? as u64 >> (avail - take)) & ((1 << take) - 1);
+            v = (v << take) | chunk;
+            left -= take;
+            self.pos += take as usize;
+        }
+        Ok(v)
+    }
+    fn read_signed(&mut self, bits: u32) -> Result<i64, FlacError> {
+        if bits == 0 {
+            return Ok(0);
+        }
+        let v = self.read(bits)?;
+        Ok(((v << (64 - bits)) as i64) >> (64 - bits))
+    }
+    /// Counts zeros up to the next 1
+    fn read_unary(&mut self) -> Result<u64, FlacError> {
+        let mut zeros = 0;
+        loop {
+            let offset = self.pos % 8;
+            let rest = self.byte()? << offset;
+            if rest == 0 {
+                zeros += 8 - offset as u64;
+                self.pos += 8 - offset;
+            } else {
+                let n = rest.leading_zeros() as usize;
+                self.pos += n + 1;
+                return Ok(zeros + n as u64);
+            }
+        }
+    }
+    fn skip(&mut self, bits: usize) {
+        self.pos += bits;
+    }
+    fn align(&mut self) {
+        self.pos = self.pos.div_ceil(8) * 8;
+    }
+    fn byte_pos(&self) -> usize {
+        self.pos / 8
+    }
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            bytes: Vec::new(),
+            acc: 0,
+            bits: 0,
+        }
+    }
+    /// Writes the low `bits` bits of `v`
+    fn write(&mut self, v: u64, bits: u32) {
+        if bits > 32 {
+            self.write(v >> 32, bits - 32);
+            self.write(v, 32);
+            return;
+        }
+        if bits == 0 {
+            return;
+        }
+        self.acc = (self.acc << bits) | (v & ((1 << bits) - 1));
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.acc >> self.bits) as u8);
+        }
+        self.acc &= (1 << self.bits) - 1;
+    }
+    fn write_signed(&mut self, v: i64, bits: u32) {
+        self.write(v as u64, bits);
+    }
+    fn write_unary(&mut self, zeros: u64) {
+        let mut zeros = zeros;
+        while zeros >= 32 {
+            self.write(0, 32);
+            zeros -= 32;
+        }
+        self.write(1, zeros as u32 + 1);
+    }
+    /// Pads with zeros to a byte boundary
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_signal(channels: u16, frames: usize, kind: BitDepth) -> SampleBuffer {
+    // A chord with a little noise, so every predictor has something to do
+    let mut seed = 0x2545F491u32;
+    let samples = (0..frames * channels as usize)
+        .map(|i| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let t = (i / channels as usize) as f64 / 44100.0;
+            let tone = (t * 440.0 * std::f64::consts::TAU).sin() * 0.4
+                + (t * 554.4 * std::f64::consts::TAU + i as f64 % 2.0).sin() * 0.3;
+            convert::from_f64(tone + (seed as f64 / u32::MAX as f64 - 0.5) * 0.01, kind)
+        })
+        .collect();
+    SampleBuffer::from_interleaved(44100, channels, samples)
+}
+
+#[test]
+fn test_round_trip_levels() -> Result<(), FlacError> {
+    for (channels, kind) in [
+        (1, BitDepth::U16(0)),
+        (2, BitDepth::U16(0)),
+        (2, BitDepth::U24(0)),
+        (3, BitDepth::U8(0)),
+        (1, BitDepth::U32(0)),
+    ] {
+        // Not a multiple of any block size, so the last frame is short
+        let data = test_signal(channels, 5000, kind);
+        let raw_size = data.interleaved().len() * kind.bits() as usize / 8;
+        for level in [0, 5, 8] {
+            let mut flac = FlacFile::from_buffer(data.clone());
+            flac.level = CompressionLevel::new(level);
+            let mut bytes = Vec::new();
+            flac.write_to(&mut bytes)?;
+            assert!(bytes.len() < raw_size, "level {level} didn't compress");
+            let read = FlacFile::read_from(&mut &bytes[..])?;
+            assert_eq!(read.data.channels(), channels);
+            assert_eq!(
+                format!("{:?}", read.data.interleaved()),
+                format!("{:?}", data.interleaved())
+            );
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_constant_and_wasted_bits() -> Result<(), FlacError> {
+    // Silence, then 16 bit audio stored as 24 bit
+    let mut data = SampleBuffer::mono(8000, vec![BitDepth::U24(0); 300]);
+    data.append(&mut SampleBuffer::mono(
+        8000,
+        (0..300)
+            .map(|i| BitDepth::U24((i * 37 % 1000) << 8))
+            .collect(),
+    ));
+    let mut bytes = Vec::new();
+    FlacFile::from_buffer(data.clone()).write_to(&mut bytes)?;
+    assert_eq!(
+        format!(
+            "{:?}",
+            FlacFile::read_from(&mut &bytes[..])?.data.interleaved()
+        ),
+        format!("{:?}", data.interleaved())
+    );
+    let info = StreamInfo::read(&mut &bytes[..])?;
+    assert_eq!(info.total_samples, 600);
+    assert_eq!(info.bits_per_sample, 24);
+    // Shorter than a block, which is still the size given
+    let block_size = CompressionLevel::default().block_size as u16;
+    assert_eq!(
+        (info.min_block_size, info.max_block_size),
+        (block_size, block_size)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_crc_check_values() {
+    assert_eq!(crc8(b"123456789"), 0xF4);
+    assert_eq!(crc16(b"123456789"), 0xFEE8);
+}
+
+#[test]
+fn test_corruption_is_detected() -> Result<(), FlacError> {
+    let mut bytes = Vec::new();
+    FlacFile::from_buffer(test_signal(1, 2000, BitDepth::U16(0))).write_to(&mut bytes)?;
+
+    let mut flipped = bytes.clone();
+    let last = flipped.len() - 10;
+    flipped[last] ^= 0x10;
+    assert!(matches!(
+        FlacFile::read_from(&mut &flipped[..]),
+        Err(FlacError::Crc | FlacError::BadFrame)
+    ));
+
+    // A total far past the frames there are runs out of input instead of memory
+    let mut oversized = bytes.clone();
+    oversized[21] |= 0x0F;
+    oversized[22..26].fill(0xFF);
+    assert!(matches!(
+        FlacFile::read_from(&mut &oversized[..]),
+        Err(FlacError::Truncated)
+    ));
+
+    // The MD5 is the last field of STREAMINFO, which ends at byte 42
+    let mut wrong_md5 = bytes.clone();
+    wrong_md5[41] ^= 1;
+    assert!(matches!(
+        FlacFile::read_from(&mut &wrong_md5[..]),
+        Err(FlacError::Md5Mismatch)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_overflowing_frame_wraps() -> Result<(), FlacError> {
+    let mut bytes = Vec::new();
+    FlacFile::from_buffer(test_signal(2, 32, BitDepth::U16(0))).write_to(&mut bytes)?;
+    let info = StreamInfo::read(&mut &bytes[..])?;
+
+    // 1024 frames of mid and side, 16 bit, each an order 4 fixed subframe with a wasted bit
+    // and the largest residuals, which the predictor grows past i64 within the block
+    let mut w = BitWriter::new();
+    for (v, bits) in [
+        (0xFFF8, 16),
+        (7, 4),
+        (0, 4),
+        (10, 4),
+        (4, 3),
+        (0, 1),
+        (0, 8),
+        (1023, 16),
+    ] {
+        w.write(v, bits);
+    }
+    w.write(crc8(&w.bytes) as u64, 8);
+    for bits in [16, 17] {
+        for (v, bits) in [(0, 1), (12, 6), (1, 1), (1, 1)] {
+            w.write(v, bits);
+        }
+        for _ in 0..4 {
+            w.write_signed(0, bits - 1);
+        }
+        for (v, bits) in [(0, 2), (0, 4), (15, 4), (31, 5)] {
+            w.write(v, bits);
+        }
+        for _ in 0..1020 {
+            w.write_signed((1 << 30) - 1, 31);
+        }
+    }
+    w.align();
+    w.write(crc16(&w.bytes) as u64, 16);
+
+    let (len, planes) = decode_frame(&w.bytes, &info)?;
+    assert_eq!(len, w.bytes.len());
+    assert_eq!(planes.len(), 2);
+    assert_eq!(planes[0].len(), 1024);
+    Ok(())
+}
@@ -0,0 +1,77 @@
+// G.711 A-law and mu-law, the 8 bit companding used by telephony
+
+const ULAW_BIAS: i32 = 0x84;
+const ULAW_CLIP: i32 = 32635;
+/// Largest 13 bit magnitude in each A-law segment
+const ALAW_SEGMENT_END: [i32; 8] = [0x1F, 0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF];
+
+pub fn alaw_encode(s: i16) -> u8 {
+    let mut v = s as i32 >> 3;
+    let mask = if v >= 0 {
+        0xD5
+    } else {
+        v = -v - 1;
+        0x55
+    };
+    let Some(segment) = ALAW_SEGMENT_END.iter().position(|&end| v <= end) else {
+        return 0x7F ^ mask;
+    };
+    let shift = if segment < 2 { 1 } else { segment };
+    let a = (segment << 4) as i32 | ((v >> shift) & 0x0F);
+    a as u8 ^ mask
+}
+
+pub fn alaw_decode(a: u8) -> i16 {
+    let a = a ^ 0x55;
+    let mut v = ((a & 0x0F) as i32) << 4;
+    let segment = (a & 0x70) >> 4;
+    match segment {
+        0 => v += 8,
+        1 => v += 0x108,
+        _ => v = (v + 0x108) << (segment - 1),
+    }
+    (if a & 0x80 != 0 { v } else { -v }) as i16
+}
+
+pub fn ulaw_encode(s: i16) -> u8 {
+    let mut v = s as i32;
+    let sign = if v < 0 {
+        v = -v;
+        0x80
+    } else {
+        0
+    };
+    v = v.min(ULAW_CLIP) + ULAW_BIAS;
+    let exponent = 31 - (v >> 7).leading_zeros();
+    let mantissa = (v >> (exponent + 3)) & 0x0F;
+    !(sign | (exponent << 4) as i32 | mantissa) as u8
+}
+
+pub fn ulaw_decode(u: u8) -> i16 {
+    let u = !u;
+    let exponent = (u >> 4) & 0x07;
+    let mantissa = (u & 0x0F) as i32;
+    let v = (((mantissa << 3) + ULAW_BIAS) << exponent) - ULAW_BIAS;
+    (if u & 0x80 != 0 { -v } else { v }) as i16
+}
+
+#[test]
+fn test_g711_reference_values() {
+    assert_eq!(ulaw_encode(0), 0xFF);
+    assert_eq!(ulaw_encode(i16::MAX), 0x80);
+    assert_eq!(ulaw_decode(0x80), 32124);
+    assert_eq!(ulaw_decode(0x00), -32124);
+    assert_eq!(alaw_encode(0), 0xD5);
+    assert_eq!(alaw_decode(0xD5), 8);
+    assert_eq!(alaw_decode(0x55), -8);
+    assert_eq!(alaw_decode(0xAA), 32256);
+
+    // Every code decodes to a value that encodes back to it
+    for code in 0..=255u8 {
+        assert_eq!(alaw_encode(alaw_decode(code)), code);
+        // Except mu-law's negative zero
+        if code != 0x7F {
+            assert_eq!(ulaw_encode(ulaw_decode(code)), code);
+        }
+    }
+}
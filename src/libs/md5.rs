@@ -0,0 +1,111 @@
+// MD5 (RFC 1321), which FLAC uses to fingerprint the decoded samples
+
+const S: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9,
+    14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10, 15,
+    21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+/// Incremental MD5 digest
+#[derive(Clone)]
+pub struct Md5 {
+    state: [u32; 4],
+    /// K[i] = floor(|sin(i + 1)| * 2^32)
+    k: [u32; 64],
+    block: [u8; 64],
+    block_len: usize,
+    len: u64,
+}
+
+impl Md5 {
+    pub fn new() -> Md5 {
+        let mut k = [0; 64];
+        for (i, k) in k.iter_mut().enumerate() {
+            *k = ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32;
+        }
+        Md5 {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476],
+            k,
+            block: [0; 64],
+            block_len: 0,
+            len: 0,
+        }
+    }
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        while !data.is_empty() {
+            let n = (64 - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + n].copy_from_slice(&data[..n]);
+            self.block_len += n;
+            data = &data[n..];
+            if self.block_len == 64 {
+                let block = self.block;
+                self.compress(&block);
+                self.block_len = 0;
+            }
+        }
+    }
+    pub fn finish(mut self) -> [u8; 16] {
+        let bits = self.len.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_le_bytes());
+        let mut digest = [0; 16];
+        for (out, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            out.copy_from_slice(&word.to_le_bytes());
+        }
+        digest
+    }
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut m = [0u32; 16];
+        for (m, b) in m.iter_mut().zip(block.chunks_exact(4)) {
+            *m = u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        }
+        let [mut a, mut b, mut c, mut d] = self.state;
+        for (i, &s) in S.iter().enumerate() {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(self.k[i]).wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(s));
+        }
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+}
+
+impl Default for Md5 {
+    fn default() -> Self {
+        Md5::new()
+    }
+}
+
+#[test]
+fn test_md5_vectors() {
+    let hex = |d: [u8; 16]| d.iter().map(|b| format!("{b:02x}")).collect::<String>();
+    let digest = |s: &[u8]| {
+        let mut md5 = Md5::new();
+        md5.update(s);
+        hex(md5.finish())
+    };
+    assert_eq!(digest(b""), "d41d8cd98f00b204e9800998ecf8427e");
+    assert_eq!(
+        digest(b"The quick brown fox jumps over the lazy dog"),
+        "9e107d9d372bb6826bd81d3542a419d6"
+    );
+    let long = [b'a'; 1000];
+    let mut split = Md5::new();
+    split.update(&long[..3]);
+    split.update(&long[3..]);
+    assert_eq!(hex(split.finish()), digest(&long));
+    assert_eq!(digest(&long), "cabe45dcc9ae5b66ba86600cca6b8ba8");
+}
@@ -0,0 +1,118 @@
+// Summing several buffers into one
+use crate::libs::buffer::SampleBuffer;
+use crate::libs::convert;
+use crate::libs::wav::BitDepth;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MixError {
+    SampleRates { first: u32, other: u32 },
+    Channels { first: u16, other: u16 },
+}
+
+impl fmt::Display for MixError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MixError::SampleRates { first, other } => {
+                write!(f, "Can't mix {other} Hz with {first} Hz")
+            }
+            MixError::Channels { first, other } => {
+                write!(f, "Can't mix {other} channels with {first}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MixError {}
+
+/// Sums the sources with each one scaled by 1/N, so the result can never clip
+pub fn mix(sources: &[&SampleBuffer]) -> Result<SampleBuffer, MixError> {
+    let gain = 1.0 / sources.len() as f64;
+    let sources: Vec<(&SampleBuffer, f64)> = sources.iter().map(|&s| (s, gain)).collect();
+    mix_with_gains(&sources)
+}
+
+/// Sums the sources, each multiplied by its gain. Integer results saturate at full scale.
+/// The output is as long as the longest source and uses the widest kind of sample among
+/// them. All sources need the same sample rate and channel count, and no sources give an
+/// empty mono buffer.
+pub fn mix_with_gains(sources: &[(&SampleBuffer, f64)]) -> Result<SampleBuffer, MixError> {
+    let Some((first, _)) = sources.first() else {
+        return Ok(SampleBuffer::new(0, 1));
+    };
+    for (source, _) in sources {
+        if source.sample_rate != first.sample_rate {
+            return Err(MixError::SampleRates {
+                first: first.sample_rate,
+                other: source.sample_rate,
+            });
+        }
+        if source.channels() != first.channels() {
+            return Err(MixError::Channels {
+                first: first.channels(),
+                other: source.channels(),
+            });
+        }
+    }
+    let kind = sources
+        .iter()
+        .filter_map(|(s, _)| s.bit_depth())
+        .reduce(BitDepth::wider)
+        .unwrap_or(BitDepth::U16(0));
+    let len = sources
+        .iter()
+        .map(|(s, _)| s.interleaved().len())
+        .max()
+        .unwrap_or(0);
+
+    // Summed as floats, so the order of the sources doesn't change where it clips
+    let mut sum = vec![0.0; len];
+    for (source, gain) in sources {
+        for (acc, s) in sum.iter_mut().zip(source.interleaved()) {
+            *acc += convert::to_f64(s) * gain;
+        }
+    }
+    let samples = sum.iter().map(|&v| convert::from_f64(v, kind)).collect();
+    Ok(SampleBuffer::from_interleaved(
+        first.sample_rate,
+        first.channels(),
+        samples,
+    ))
+}
+
+#[test]
+fn test_mix_headroom() -> Result<(), MixError> {
+    let loud = SampleBuffer::mono(8000, vec![BitDepth::U16(i16::MAX); 4]);
+    let mixed = mix(&[&loud, &loud, &loud])?;
+    assert!(mixed
+        .interleaved()
+        .iter()
+        .all(|s| matches!(s, BitDepth::U16(v) if *v >= i16::MAX - 1)));
+    assert!(mix(&[])?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_mix_with_gains_saturates_and_pads() -> Result<(), MixError> {
+    let a = SampleBuffer::mono(8000, vec![BitDepth::U8(100); 3]);
+    let b = SampleBuffer::mono(8000, vec![BitDepth::U16(i16::MAX / 2); 2]);
+    let mixed = mix_with_gains(&[(&a, 1.0), (&b, 1.0)])?;
+    assert!(matches!(
+        mixed.interleaved(),
+        [
+            BitDepth::U16(i16::MAX),
+            BitDepth::U16(i16::MAX),
+            BitDepth::U16(25600)
+        ]
+    ));
+
+    let c = SampleBuffer::mono(44100, vec![BitDepth::U16(0); 2]);
+    assert_eq!(
+        mix(&[&a, &c]).unwrap_err(),
+        MixError::SampleRates {
+            first: 8000,
+            other: 44100
+        }
+    );
+    Ok(())
+}
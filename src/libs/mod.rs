@@ -1,4 +1,19 @@
+pub mod adpcm;
+pub mod aiff;
 pub mod amdf;
+pub mod buffer;
+pub mod convert;
+pub mod flac;
+pub mod g711;
+pub mod md5;
+pub mod mix;
 pub mod notation;
+pub mod ogg;
+pub mod oscillator;
+pub mod raw;
+pub mod resample;
 pub mod sampling;
+pub mod source;
+pub mod vorbis;
 pub mod wav;
+pub mod waveform;
@@ -9,19 +9,11 @@ pub fn gen_notes() -> Vec<f64> {
     tones
 }
 
-pub fn note_to_freq(note: &str) -> f64 {
-    let letter: &str;
-    let octave: &str;
-    if note.len() == 2 {
-        letter = &note[0..1];
-        octave = &note[1..2];
-    } else if note.len() == 3 {
-        letter = &note[0..2];
-        octave = &note[2..3];
-    } else {
-        panic!();
-    }
-    let note_value = match letter {
+/// Frequency of a note name like "A4" or "C#3", or None for anything that isn't a note from A0
+/// up
+pub fn parse_note(note: &str) -> Option<f64> {
+    let (letter, octave) = note.split_at(note.find(|c: char| c.is_ascii_digit())?);
+    let semitone = match letter {
         "C" => 0,
         "C#" => 1,
         "D" => 2,
@@ -34,12 +26,21 @@ pub fn note_to_freq(note: &str) -> f64 {
         "A" => 9,
         "A#" => 10,
         "B" => 11,
-        _ => panic!(),
-    } + 12 * octave.parse::<usize>().unwrap();
+        _ => return None,
+    };
+    let note_value = octave
+        .parse::<usize>()
+        .ok()?
+        .checked_mul(12)?
+        .checked_add(semitone)?;
 
     let notes = gen_notes();
 
-    notes[note_value - 9] //We start at A0
+    notes.get(note_value.checked_sub(9)?).copied() //We start at A0
+}
+
+pub fn note_to_freq(note: &str) -> f64 {
+    parse_note(note).expect("Not a note name")
 }
 
 pub fn freq_to_note(freq: f64) -> String {
@@ -111,3 +112,21 @@ fn test_415_to_gsharp4() -> Result<(), String> {
     assert_eq!(freq_to_note(415.304697579946), "G#4");
     Ok(())
 }
+
+#[test]
+fn test_parse_bad_notes() {
+    for note in [
+        "",
+        "C",
+        "Cb4",
+        "c4",
+        "C10",
+        "G#0",
+        "4",
+        "C#-1",
+        "B1537228672809129301",
+    ] {
+        assert_eq!(parse_note(note), None, "{note}");
+    }
+    assert_eq!(parse_note("A#0"), Some(gen_notes()[1]));
+}
@@ -0,0 +1,263 @@
+// The Ogg container: pages with checksums, carrying the packets of one or more streams
+use std::{
+    fmt,
+    io::{ErrorKind, Read},
+};
+
+/// Header flag of a page that continues a packet from the previous page
+const CONTINUED: u8 = 0x01;
+/// First page of a stream
+const BEGIN: u8 = 0x02;
+/// Last page of a stream
+const END: u8 = 0x04;
+
+#[derive(Debug)]
+pub enum OggError {
+    Io(std::io::Error),
+    /// No page starts where one should
+    NotOgg,
+    UnsupportedVersion(u8),
+    /// A page doesn't match its CRC
+    Crc,
+    /// The stream ends in the middle of a page
+    Truncated,
+}
+
+impl fmt::Display for OggError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OggError::Io(e) => write!(f, "I/O error: {e}"),
+            OggError::NotOgg => write!(f, "Not an Ogg stream"),
+            OggError::UnsupportedVersion(v) => write!(f, "Unsupported Ogg version {v}"),
+            OggError::Crc => write!(f, "Page doesn't match its CRC"),
+            OggError::Truncated => write!(f, "Stream is truncated"),
+        }
+    }
+}
+
+impl std::error::Error for OggError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OggError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for OggError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            ErrorKind::UnexpectedEof => OggError::Truncated,
+            _ => OggError::Io(e),
+        }
+    }
+}
+
+pub struct Page {
+    pub flags: u8,
+    /// Codec defined position at the end of the last packet finished on this page, -1 if
+    /// none is
+    pub granule_position: i64,
+    pub serial: u32,
+    pub sequence: u32,
+    /// Lacing values, each packet is a run of 255s ended by a smaller value
+    pub segments: Vec<u8>,
+    pub body: Vec<u8>,
+}
+
+impl Page {
+    /// Reads the next page, `None` at the end of the stream
+    pub fn read<R: Read>(r: &mut R) -> Result<Option<Page>, OggError> {
+        let mut hdr = [0; 27];
+        match r.read(&mut hdr[..1])? {
+            0 => return Ok(None),
+            _ => r.read_exact(&mut hdr[1..])?,
+        }
+        if &hdr[0..4] != b"OggS" {
+            return Err(OggError::NotOgg);
+        }
+        if hdr[4] != 0 {
+            return Err(OggError::UnsupportedVersion(hdr[4]));
+        }
+        let mut segments = vec![0; hdr[26] as usize];
+        r.read_exact(&mut segments)?;
+        let mut body = vec![0; segments.iter().map(|&s| s as usize).sum()];
+        r.read_exact(&mut body)?;
+
+        let expected = u32::from_le_bytes([hdr[22], hdr[23], hdr[24], hdr[25]]);
+        hdr[22..26].fill(0);
+        let crc = [&hdr[..], &segments, &body]
+            .iter()
+            .fold(0, |crc, part| crc32(crc, part));
+        if crc != expected {
+            return Err(OggError::Crc);
+        }
+        Ok(Some(Page {
+            flags: hdr[5],
+            granule_position: i64::from_le_bytes(hdr[6..14].try_into().unwrap()),
+            serial: u32::from_le_bytes(hdr[14..18].try_into().unwrap()),
+            sequence: u32::from_le_bytes(hdr[18..22].try_into().unwrap()),
+            segments,
+            body,
+        }))
+    }
+    pub fn is_continued(&self) -> bool {
+        self.flags & CONTINUED != 0
+    }
+    pub fn is_first(&self) -> bool {
+        self.flags & BEGIN != 0
+    }
+    pub fn is_last(&self) -> bool {
+        self.flags & END != 0
+    }
+}
+
+/// Reassembles the packets of one logical stream, the first one in the file, skipping pages
+/// of any other stream multiplexed with it
+pub struct PacketReader<R> {
+    reader: R,
+    serial: Option<u32>,
+    /// Packets finished on the last page read, in order
+    ready: std::collections::VecDeque<Vec<u8>>,
+    partial: Vec<u8>,
+    /// Granule position of the last page read
+    pub granule_position: i64,
+    done: bool,
+}
+
+impl<R: Read> PacketReader<R> {
+    pub fn new(reader: R) -> PacketReader<R> {
+        PacketReader {
+            reader,
+            serial: None,
+            ready: Default::default(),
+            partial: Vec::new(),
+            granule_position: -1,
+            done: false,
+        }
+    }
+    /// Serial number of the stream being read, once its first page has been
+    pub fn serial(&self) -> Option<u32> {
+        self.serial
+    }
+    /// The next whole packet, `None` once the stream ends
+    pub fn next_packet(&mut self) -> Result<Option<Vec<u8>>, OggError> {
+        while self.ready.is_empty() && !self.done {
+            let Some(page) = Page::read(&mut self.reader)? else {
+                self.done = true;
+                break;
+            };
+            let serial = *self.serial.get_or_insert(page.serial);
+            if page.serial != serial {
+                continue;
+            }
+            if !page.is_continued() {
+                // A packet left unfinished by a lost page can't be used
+                self.partial.clear();
+            }
+            let mut pos = 0;
+            for &lacing in &page.segments {
+                self.partial
+                    .extend_from_slice(&page.body[pos..pos + lacing as usize]);
+                pos += lacing as usize;
+                if lacing < 255 {
+                    self.ready.push_back(std::mem::take(&mut self.partial));
+                }
+            }
+            self.granule_position = page.granule_position;
+            self.done = page.is_last();
+        }
+        Ok(self.ready.pop_front())
+    }
+}
+
+/// CRC-32 with polynomial 0x04C11DB7, not reflected, as Ogg uses it
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Builds a page around some packets, the ones in the middle whole. For tests and tools that
+/// need a stream without an encoder.
+pub fn page_bytes(
+    flags: u8,
+    granule_position: i64,
+    serial: u32,
+    sequence: u32,
+    packets: &[&[u8]],
+) -> Vec<u8> {
+    let mut segments = Vec::new();
+    for packet in packets {
+        segments.extend(std::iter::repeat_n(255, packet.len() / 255));
+        segments.push((packet.len() % 255) as u8);
+    }
+    let mut page = b"OggS\0".to_vec();
+    page.push(flags);
+    page.extend_from_slice(&granule_position.to_le_bytes());
+    page.extend_from_slice(&serial.to_le_bytes());
+    page.extend_from_slice(&sequence.to_le_bytes());
+    page.extend_from_slice(&[0; 4]);
+    page.push(segments.len() as u8);
+    page.extend(segments);
+    for packet in packets {
+        page.extend_from_slice(packet);
+    }
+    let crc = crc32(0, &page);
+    page[22..26].copy_from_slice(&crc.to_le_bytes());
+    page
+}
+
+#[test]
+fn test_crc32_check_value() {
+    // CRC-32/MPEG-2 without the final inversion or initial value, as Ogg uses it
+    assert_eq!(crc32(0, b"123456789"), 0x89A1_897F);
+}
+
+#[test]
+fn test_packets_across_pages() -> Result<(), OggError> {
+    let long = vec![7u8; 600];
+    let mut stream = page_bytes(BEGIN, 0, 1, 0, &[b"head"]);
+    // Another stream interleaved with the first
+    stream.extend(page_bytes(BEGIN, 0, 2, 0, &[b"other"]));
+    // The long packet is split after 510 bytes, which ends the page on a 255
+    let mut first = page_bytes(0, -1, 1, 1, &[&long[..510]]);
+    // Drop the trailing 0 lacing value, so the packet continues
+    first[26] -= 1;
+    first.remove(27 + 2);
+    first[22..26].fill(0);
+    let crc = crc32(0, &first);
+    first[22..26].copy_from_slice(&crc.to_le_bytes());
+    stream.extend(first);
+    stream.extend(page_bytes(
+        CONTINUED | END,
+        1000,
+        1,
+        2,
+        &[&long[510..], b"x"],
+    ));
+
+    let mut reader = PacketReader::new(&stream[..]);
+    assert_eq!(reader.next_packet()?.as_deref(), Some(&b"head"[..]));
+    assert_eq!(reader.next_packet()?.map(|p| p.len()), Some(600));
+    assert_eq!(reader.next_packet()?.as_deref(), Some(&b"x"[..]));
+    assert_eq!(reader.next_packet()?, None);
+    assert_eq!(reader.serial(), Some(1));
+    assert_eq!(reader.granule_position, 1000);
+
+    let mut corrupt = stream.clone();
+    corrupt[30] ^= 1;
+    assert!(matches!(
+        PacketReader::new(&corrupt[..]).next_packet(),
+        Err(OggError::Crc)
+    ));
+    Ok(())
+}
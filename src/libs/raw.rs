@@ -0,0 +1,306 @@
+// Headerless sample streams, like the .raw/.pcm dumps of embedded boards
+use crate::libs::buffer::SampleBuffer;
+use crate::libs::convert;
+use crate::libs::g711;
+use crate::libs::wav::{u8_from_wav, u8_to_wav, BitDepth, WavParams};
+use std::{
+    fmt,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+    str::FromStr,
+};
+
+#[derive(Debug)]
+pub enum RawError {
+    Io(std::io::Error),
+    /// A sample type or byte order that can't be parsed
+    UnknownFormat(String),
+    /// A format of 0 channels, which has no frames to decode
+    NoChannels,
+}
+
+impl fmt::Display for RawError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RawError::Io(e) => write!(f, "I/O error: {e}"),
+            RawError::UnknownFormat(s) => write!(f, "Unknown raw format `{s}`"),
+            RawError::NoChannels => write!(f, "A raw stream needs at least one channel"),
+        }
+    }
+}
+
+impl std::error::Error for RawError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RawError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for RawError {
+    fn from(e: std::io::Error) -> Self {
+        RawError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleType {
+    I8,
+    /// Unsigned with a 128 offset, like 8 bit WAV
+    U8,
+    I16,
+    /// Packed in 3 bytes
+    I24,
+    I32,
+    F32,
+    F64,
+    /// G.711, decoded to 16 bits
+    ALaw,
+    MuLaw,
+}
+
+impl SampleType {
+    pub fn bytes(&self) -> usize {
+        match self {
+            SampleType::I8 | SampleType::U8 | SampleType::ALaw | SampleType::MuLaw => 1,
+            SampleType::I16 => 2,
+            SampleType::I24 => 3,
+            SampleType::I32 | SampleType::F32 => 4,
+            SampleType::F64 => 8,
+        }
+    }
+    /// The kind of sample it decodes to
+    fn bit_depth(&self) -> BitDepth {
+        match self {
+            SampleType::I8 | SampleType::U8 => BitDepth::U8(0),
+            SampleType::I16 | SampleType::ALaw | SampleType::MuLaw => BitDepth::U16(0),
+            SampleType::I24 => BitDepth::U24(0),
+            SampleType::I32 => BitDepth::U32(0),
+            SampleType::F32 => BitDepth::F32(0.0),
+            SampleType::F64 => BitDepth::F64(0.0),
+        }
+    }
+}
+
+/// Parses the names sox and ffmpeg use, e.g. `s16`, `u8`, `f32` or `mulaw`
+impl FromStr for SampleType {
+    type Err = RawError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "s8" | "i8" => Ok(SampleType::I8),
+            "u8" => Ok(SampleType::U8),
+            "s16" | "i16" => Ok(SampleType::I16),
+            "s24" | "i24" => Ok(SampleType::I24),
+            "s32" | "i32" => Ok(SampleType::I32),
+            "f32" => Ok(SampleType::F32),
+            "f64" => Ok(SampleType::F64),
+            "alaw" => Ok(SampleType::ALaw),
+            "mulaw" | "ulaw" => Ok(SampleType::MuLaw),
+            _ => Err(RawError::UnknownFormat(s.into())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl FromStr for Endian {
+    type Err = RawError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "le" | "little" => Ok(Endian::Little),
+            "be" | "big" => Ok(Endian::Big),
+            _ => Err(RawError::UnknownFormat(s.into())),
+        }
+    }
+}
+
+/// Everything a header would say about a raw stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawFormat {
+    pub sample_type: SampleType,
+    pub endian: Endian,
+    pub params: WavParams,
+}
+
+impl RawFormat {
+    pub fn new(sample_type: SampleType, endian: Endian, params: WavParams) -> RawFormat {
+        RawFormat {
+            sample_type,
+            endian,
+            params,
+        }
+    }
+    /// Bytes in a frame, one sample for each channel
+    pub fn frame_size(&self) -> usize {
+        self.sample_type.bytes() * self.params.channels as usize
+    }
+    /// Decodes whole frames, ignoring a partial frame at the end
+    pub fn decode(&self, data: &[u8]) -> Result<SampleBuffer, RawError> {
+        if self.params.channels == 0 {
+            return Err(RawError::NoChannels);
+        }
+        let whole = data.len() / self.frame_size() * self.frame_size();
+        let samples = data[..whole]
+            .chunks_exact(self.sample_type.bytes())
+            .map(|b| self.decode_sample(b))
+            .collect();
+        Ok(SampleBuffer::from_interleaved(
+            self.params.sample_rate,
+            self.params.channels,
+            samples,
+        ))
+    }
+    fn decode_sample(&self, b: &[u8]) -> BitDepth {
+        // Work on little-endian bytes from here on
+        let mut le = [0; 8];
+        le[..b.len()].copy_from_slice(b);
+        if self.endian == Endian::Big {
+            le[..b.len()].reverse();
+        }
+        match self.sample_type {
+            SampleType::I8 => BitDepth::U8(le[0] as i8),
+            SampleType::U8 => BitDepth::U8(u8_from_wav(le[0])),
+            SampleType::I16 => BitDepth::U16(i16::from_le_bytes([le[0], le[1]])),
+            // Sign extended from the top byte
+            SampleType::I24 => BitDepth::U24(i32::from_le_bytes([0, le[0], le[1], le[2]]) >> 8),
+            SampleType::I32 => BitDepth::U32(i32::from_le_bytes([le[0], le[1], le[2], le[3]])),
+            SampleType::F32 => BitDepth::F32(f32::from_le_bytes([le[0], le[1], le[2], le[3]])),
+            SampleType::F64 => BitDepth::F64(f64::from_le_bytes(le)),
+            SampleType::ALaw => BitDepth::U16(g711::alaw_decode(le[0])),
+            SampleType::MuLaw => BitDepth::U16(g711::ulaw_decode(le[0])),
+        }
+    }
+    /// Encodes interleaved samples, converting them to the sample type first
+    pub fn encode(&self, samples: &[BitDepth]) -> Vec<u8> {
+        let mut data = Vec::with_capacity(samples.len() * self.sample_type.bytes());
+        for s in samples {
+            let mut le = match convert::convert(s, self.sample_type.bit_depth()) {
+                BitDepth::U8(v) => match self.sample_type {
+                    SampleType::U8 => vec![u8_to_wav(v)],
+                    _ => vec![v as u8],
+                },
+                BitDepth::U16(v) => match self.sample_type {
+                    SampleType::ALaw => vec![g711::alaw_encode(v)],
+                    SampleType::MuLaw => vec![g711::ulaw_encode(v)],
+                    _ => v.to_le_bytes().to_vec(),
+                },
+                BitDepth::U24(v) => v.to_le_bytes()[..3].to_vec(),
+                BitDepth::U32(v) => v.to_le_bytes().to_vec(),
+                BitDepth::F32(v) => v.to_le_bytes().to_vec(),
+                BitDepth::F64(v) => v.to_le_bytes().to_vec(),
+            };
+            if self.endian == Endian::Big {
+                le.reverse();
+            }
+            data.extend(le);
+        }
+        data
+    }
+}
+
+pub fn read(path: &Path, format: &RawFormat) -> Result<SampleBuffer, RawError> {
+    read_from(&mut BufReader::new(File::open(path)?), format)
+}
+
+pub fn read_from<R: Read>(r: &mut R, format: &RawFormat) -> Result<SampleBuffer, RawError> {
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+    format.decode(&data)
+}
+
+/// Writes the samples of `buffer` with nothing around them. Its rate and channel count aren't
+/// stored anywhere, so keep them with the file.
+pub fn write(path: &Path, buffer: &SampleBuffer, format: &RawFormat) -> Result<(), RawError> {
+    let mut w = BufWriter::new(File::create(path)?);
+    write_to(&mut w, buffer, format)?;
+    w.flush()?;
+    Ok(())
+}
+
+pub fn write_to<W: Write>(
+    w: &mut W,
+    buffer: &SampleBuffer,
+    format: &RawFormat,
+) -> Result<(), RawError> {
+    w.write_all(&format.encode(buffer.interleaved()))?;
+    Ok(())
+}
+
+#[test]
+fn test_raw_round_trip() -> Result<(), RawError> {
+    let params = WavParams {
+        sample_rate: 16000,
+        channels: 2,
+    };
+    let samples = vec![
+        BitDepth::U24(-3_000_000),
+        BitDepth::U24(8_000_000),
+        BitDepth::U24(-1),
+        BitDepth::U24(1 << 8),
+    ];
+    for (sample_type, endian) in [
+        (SampleType::I24, Endian::Big),
+        (SampleType::I24, Endian::Little),
+        (SampleType::I16, Endian::Big),
+        (SampleType::F32, Endian::Little),
+        (SampleType::U8, Endian::Little),
+        (SampleType::MuLaw, Endian::Little),
+    ] {
+        let format = RawFormat::new(sample_type, endian, params);
+        let buffer = SampleBuffer::from_interleaved(16000, 2, samples.clone());
+        let mut data = Vec::new();
+        write_to(&mut data, &buffer, &format)?;
+        assert_eq!(data.len(), 4 * sample_type.bytes());
+        // A partial frame at the end is dropped
+        data.push(0);
+        let read = read_from(&mut &data[..], &format)?;
+        assert_eq!(read.frames(), 2);
+        assert_eq!(read.channels(), 2);
+        // Converted the same way as the writer did
+        let expected: Vec<BitDepth> = samples
+            .iter()
+            .map(|s| convert::convert(s, sample_type.bit_depth()))
+            .collect();
+        if sample_type == SampleType::I24 {
+            assert_eq!(
+                format!("{:?}", read.interleaved()),
+                format!("{:?}", expected)
+            );
+        }
+        for (a, b) in read.interleaved().iter().zip(&expected) {
+            assert!((convert::to_f64(a) - convert::to_f64(b)).abs() < 0.03);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_raw_byte_order() {
+    let params = WavParams {
+        sample_rate: 8000,
+        channels: 1,
+    };
+    let big = RawFormat::new(SampleType::I16, Endian::Big, params);
+    let little = RawFormat::new(SampleType::I16, Endian::Little, params);
+    assert_eq!(big.encode(&[BitDepth::U16(0x0102)]), [1, 2]);
+    assert_eq!(little.encode(&[BitDepth::U16(0x0102)]), [2, 1]);
+    assert_eq!("S16".parse::<SampleType>().unwrap(), SampleType::I16);
+    assert_eq!("be".parse::<Endian>().unwrap(), Endian::Big);
+    assert!("s12".parse::<SampleType>().is_err());
+
+    let none = RawFormat::new(
+        SampleType::I16,
+        Endian::Little,
+        WavParams {
+            sample_rate: 8000,
+            channels: 0,
+        },
+    );
+    assert!(matches!(none.decode(&[0; 4]), Err(RawError::NoChannels)));
+}
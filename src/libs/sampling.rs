@@ -1,4 +1,63 @@
+use crate::libs::buffer::SampleBuffer;
+use crate::libs::convert;
+use crate::libs::oscillator::WaveOscillator;
 use crate::libs::wav::BitDepth;
+use crate::libs::waveform::{Antialias, Waveform};
+
+/// An endless wave at full scale, band-limited unless asked otherwise. The same oscillator
+/// plays live in a cpal callback.
+pub fn tone(waveform: Waveform, freq: f64, sample_rate: u32) -> WaveOscillator {
+    WaveOscillator::new(waveform, freq, sample_rate)
+}
+
+/// Frames in `duration` seconds, rounded down like the generators always have
+pub fn frames(duration: f64, sample_rate: u32) -> usize {
+    (duration * sample_rate as f64) as usize
+}
+
+/// Combinators for signals, any iterator of samples in [-1, 1]. Endless ones have to be cut
+/// with `seconds` or `fade_out` before rendering.
+pub trait Signal: Iterator<Item = f64> + Sized {
+    fn gain(self, gain: f64) -> impl Signal {
+        self.map(move |v| v * gain)
+    }
+    fn seconds(self, duration: f64, sample_rate: u32) -> impl Signal {
+        self.take(frames(duration, sample_rate))
+    }
+    /// The next `frames` samples, fading linearly from full to silence
+    fn fade_out(self, frames: usize) -> impl Signal {
+        self.take(frames)
+            .enumerate()
+            .map(move |(i, v)| v * (frames - i) as f64 / frames as f64)
+    }
+    /// This signal and then `next`
+    fn then(self, next: impl Signal) -> impl Signal {
+        self.chain(next)
+    }
+    /// Both signals summed, lasting as long as the longer one
+    fn plus(self, other: impl Signal) -> impl Signal {
+        let (mut a, mut b) = (self, other);
+        std::iter::from_fn(move || match (a.next(), b.next()) {
+            (None, None) => None,
+            (a, b) => Some(a.unwrap_or(0.0) + b.unwrap_or(0.0)),
+        })
+    }
+    /// Quantizes a mono buffer of the kind of `bit_depth`, which may be a float
+    fn render(self, sample_rate: u32, bit_depth: BitDepth) -> SampleBuffer {
+        let samples = self.map(|v| convert::from_f64(v, bit_depth)).collect();
+        SampleBuffer::mono(sample_rate, samples)
+    }
+}
+
+impl<I: Iterator<Item = f64>> Signal for I {}
+
+/// Notes of (frequency, seconds) one after another, each fading out over its length
+pub fn melody(notes: &[(f64, f64)], waveform: Waveform, sample_rate: u32) -> impl Signal {
+    let notes = notes.to_vec();
+    notes.into_iter().flat_map(move |(freq, duration)| {
+        tone(waveform, freq, sample_rate).fade_out(frames(duration, sample_rate))
+    })
+}
 
 pub fn sine_wave(
     freq: f64,
@@ -6,77 +65,67 @@ pub fn sine_wave(
     duration: f64,
     bit_depth: BitDepth,
     volume: f64,
-) -> Vec<BitDepth> {
-    let num_samples = (duration * sample_rate as f64) as usize;
-    let mut samples: Vec<BitDepth> = Vec::with_capacity(num_samples);
-    for i in 0..num_samples {
-        let sample_max = match bit_depth {
-            BitDepth::U8(_) => i8::MAX as f64,
-            BitDepth::U16(_) => i16::MAX as f64,
-            BitDepth::U32(_) => i32::MAX as f64,
-        } * volume;
-        let t = ((i as f64 / sample_rate as f64) * freq * 2.0 * std::f64::consts::PI).sin()
-            * sample_max;
-        samples.push(match bit_depth {
-            BitDepth::U8(_) => BitDepth::U8(t as i8),
-            BitDepth::U16(_) => BitDepth::U16(t as i16),
-            BitDepth::U32(_) => BitDepth::U32(t as i32),
-        });
-    }
-    samples
+) -> SampleBuffer {
+    tone(Waveform::Sine, freq, sample_rate)
+        .gain(volume)
+        .seconds(duration, sample_rate)
+        .render(sample_rate, bit_depth)
 }
 
-// TODO build sample builder
-
 pub fn sine_wave_truncated(
     freq: f64,
     sample_rate: u32,
     duration: f64,
     bit_depth: BitDepth,
     volume: f64,
-) -> Vec<BitDepth> {
-    let num_samples = (duration * sample_rate as f64) as usize;
-    let mut samples: Vec<BitDepth> = Vec::with_capacity(num_samples);
-    for i in 0..num_samples {
-        let sample_max = match bit_depth {
-            BitDepth::U8(_) => i8::MAX as f64,
-            BitDepth::U16(_) => i16::MAX as f64,
-            BitDepth::U32(_) => i32::MAX as f64,
-        } * volume;
-        let v = sample_max * (num_samples as f64 - i as f64) / num_samples as f64;
-        let t = ((i as f64 / sample_rate as f64) * freq * 2.0 * std::f64::consts::PI).sin() * v;
-        samples.push(match bit_depth {
-            BitDepth::U8(_) => BitDepth::U8(t as i8),
-            BitDepth::U16(_) => BitDepth::U16(t as i16),
-            BitDepth::U32(_) => BitDepth::U32(t as i32),
-        });
-    }
-    samples
+) -> SampleBuffer {
+    tone(Waveform::Sine, freq, sample_rate)
+        .gain(volume)
+        .fade_out(frames(duration, sample_rate))
+        .render(sample_rate, bit_depth)
 }
 
+/// A naive saw from -volume/2 to volume/2, as it always sounded
 pub fn saw_wave_truncated(
     freq: f64,
     sample_rate: u32,
     duration: f64,
     bit_depth: BitDepth,
     volume: f64,
-) -> Vec<BitDepth> {
-    let num_samples = (duration * sample_rate as f64) as usize;
-    let mut samples: Vec<BitDepth> = Vec::with_capacity(num_samples);
-    for i in 0..num_samples {
-        let sample_max = match bit_depth {
-            BitDepth::U8(_) => i8::MAX as f64,
-            BitDepth::U16(_) => i16::MAX as f64,
-            BitDepth::U32(_) => i32::MAX as f64,
-        } * volume;
-        let v = sample_max * (num_samples as f64 - i as f64) / num_samples as f64;
-        let t = (1.0f64 / freq) * sample_rate as f64;
-        let t = ((i as f64 % t) / t - 0.5) * v;
-        samples.push(match bit_depth {
-            BitDepth::U8(_) => BitDepth::U8(t as i8),
-            BitDepth::U16(_) => BitDepth::U16(t as i16),
-            BitDepth::U32(_) => BitDepth::U32(t as i32),
-        });
-    }
-    samples
+) -> SampleBuffer {
+    tone(Waveform::Saw, freq, sample_rate)
+        .antialias(Antialias::Naive)
+        .gain(volume / 2.0)
+        .fade_out(frames(duration, sample_rate))
+        .render(sample_rate, bit_depth)
+}
+
+#[test]
+fn test_signal_lengths() {
+    let rate = 1000;
+    let a = tone(Waveform::Sine, 10.0, rate).seconds(0.5, rate);
+    let b = tone(Waveform::Saw, 10.0, rate).fade_out(200);
+    assert_eq!(a.then(b).count(), 700);
+
+    // Summing pads the shorter signal with silence
+    let short = std::iter::repeat_n(0.25, 3);
+    let long = std::iter::repeat_n(0.5, 5).gain(0.5);
+    let sum: Vec<f64> = short.plus(long).collect();
+    assert_eq!(sum, [0.5, 0.5, 0.5, 0.25, 0.25]);
+}
+
+#[test]
+fn test_melody_renders() {
+    let rate = 8000;
+    let notes = [(440.0, 0.25), (330.0, 0.125)];
+    let lead = melody(&notes, Waveform::Sine, rate).gain(0.5);
+    let buffer = lead.render(rate, BitDepth::F32(0.0));
+    assert_eq!(buffer.frames(), 3000);
+    assert!(matches!(buffer.interleaved()[0], BitDepth::F32(v) if v == 0.0));
+
+    // Each note fades out, so it ends near silence and the next starts fresh
+    let samples = buffer.to_f64();
+    assert!(samples[1990..2000].iter().all(|v| v.abs() < 0.01));
+    assert!(samples[2000..2020].iter().any(|v| v.abs() > 0.1));
+    assert!(samples.iter().all(|v| v.abs() <= 0.5));
 }
@@ -0,0 +1,285 @@
+// Opening any supported file as a stream of sample blocks
+use crate::libs::aiff::{AiffError, AiffFile};
+use crate::libs::buffer::SampleBuffer;
+use crate::libs::convert::Quantizer;
+use crate::libs::flac::{self, FlacError, FlacFile};
+use crate::libs::vorbis::{VorbisError, VorbisFile};
+use crate::libs::wav::{BitDepth, SamplerInfo, WavError, WavFile, WavReader};
+use std::{
+    fmt,
+    fs::File,
+    io::{ErrorKind, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+#[derive(Debug)]
+pub enum SourceError {
+    Io(std::io::Error),
+    Wav(WavError),
+    Aiff(AiffError),
+    Flac(FlacError),
+    Vorbis(VorbisError),
+    /// None of the supported formats starts like this
+    UnknownFormat,
+    /// A seek past the end of the data
+    OutOfRange,
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceError::Io(e) => write!(f, "I/O error: {e}"),
+            SourceError::Wav(e) => write!(f, "WAV: {e}"),
+            SourceError::Aiff(e) => write!(f, "AIFF: {e}"),
+            SourceError::Flac(e) => write!(f, "FLAC: {e}"),
+            SourceError::Vorbis(e) => write!(f, "Vorbis: {e}"),
+            SourceError::UnknownFormat => write!(f, "Unknown file format"),
+            SourceError::OutOfRange => write!(f, "Position is past the end of the data"),
+        }
+    }
+}
+
+impl std::error::Error for SourceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SourceError::Io(e) => Some(e),
+            SourceError::Wav(e) => Some(e),
+            SourceError::Aiff(e) => Some(e),
+            SourceError::Flac(e) => Some(e),
+            SourceError::Vorbis(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for SourceError {
+    fn from(e: std::io::Error) -> Self {
+        SourceError::Io(e)
+    }
+}
+
+impl From<WavError> for SourceError {
+    fn from(e: WavError) -> Self {
+        match e {
+            WavError::OutOfRange => SourceError::OutOfRange,
+            e => SourceError::Wav(e),
+        }
+    }
+}
+
+impl From<AiffError> for SourceError {
+    fn from(e: AiffError) -> Self {
+        SourceError::Aiff(e)
+    }
+}
+
+impl From<FlacError> for SourceError {
+    fn from(e: FlacError) -> Self {
+        SourceError::Flac(e)
+    }
+}
+
+impl From<VorbisError> for SourceError {
+    fn from(e: VorbisError) -> Self {
+        SourceError::Vorbis(e)
+    }
+}
+
+/// Audio that can be read a block at a time, whatever file it comes from
+pub trait Source {
+    fn sample_rate(&self) -> u32;
+    fn channels(&self) -> u16;
+    /// Length in frames
+    fn len(&self) -> u64;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Frame that the next read starts at
+    fn position(&self) -> u64;
+    fn seek(&mut self, frame: u64) -> Result<(), SourceError>;
+    /// Reads up to `frames` frames, an empty block means the end was reached
+    fn read_block(&mut self, frames: usize) -> Result<SampleBuffer, SourceError>;
+    /// Loops and root note, for files that have them
+    fn sampler(&self) -> Option<&SamplerInfo> {
+        None
+    }
+}
+
+impl<R: Read + Seek> Source for WavReader<R> {
+    fn sample_rate(&self) -> u32 {
+        WavReader::sample_rate(self)
+    }
+    fn channels(&self) -> u16 {
+        WavReader::channels(self)
+    }
+    fn len(&self) -> u64 {
+        WavReader::len(self)
+    }
+    fn position(&self) -> u64 {
+        WavReader::position(self)
+    }
+    fn seek(&mut self, frame: u64) -> Result<(), SourceError> {
+        Ok(WavReader::seek(self, frame)?)
+    }
+    fn read_block(&mut self, frames: usize) -> Result<SampleBuffer, SourceError> {
+        Ok(WavReader::read_block(self, frames)?)
+    }
+    fn sampler(&self) -> Option<&SamplerInfo> {
+        self.sampler.as_ref()
+    }
+}
+
+/// A source for formats that are decoded all at once
+pub struct BufferSource {
+    buffer: SampleBuffer,
+    pos: usize,
+}
+
+impl BufferSource {
+    pub fn new(buffer: SampleBuffer) -> BufferSource {
+        BufferSource { buffer, pos: 0 }
+    }
+}
+
+impl Source for BufferSource {
+    fn sample_rate(&self) -> u32 {
+        self.buffer.sample_rate
+    }
+    fn channels(&self) -> u16 {
+        self.buffer.channels()
+    }
+    fn len(&self) -> u64 {
+        self.buffer.frames() as u64
+    }
+    fn position(&self) -> u64 {
+        self.pos as u64
+    }
+    fn seek(&mut self, frame: u64) -> Result<(), SourceError> {
+        if frame > self.len() {
+            return Err(SourceError::OutOfRange);
+        }
+        self.pos = frame as usize;
+        Ok(())
+    }
+    fn read_block(&mut self, frames: usize) -> Result<SampleBuffer, SourceError> {
+        let channels = self.buffer.channels() as usize;
+        let end = (self.pos + frames).min(self.buffer.frames());
+        let samples = self.buffer.interleaved()[self.pos * channels..end * channels].to_vec();
+        self.pos = end;
+        Ok(SampleBuffer::from_interleaved(
+            self.buffer.sample_rate,
+            self.buffer.channels(),
+            samples,
+        ))
+    }
+}
+
+/// Opens a file of any supported format, which is told by its first bytes rather than its
+/// extension
+pub fn open(path: &Path) -> Result<Box<dyn Source + Send>, SourceError> {
+    let mut f = File::open(path)?;
+    let mut magic = [0; 4];
+    f.read_exact(&mut magic)?;
+    // Some taggers put an ID3 tag in front of a FLAC stream, as they do in front of most MP3s,
+    // so it's what follows the tag that tells them apart
+    if magic.starts_with(b"ID3") {
+        let mut tag = [0; 10];
+        tag[..4].copy_from_slice(&magic);
+        let after_tag = f.read_exact(&mut tag[4..]).and_then(|_| {
+            f.seek(SeekFrom::Start(flac::id3_len(&tag) as u64))?;
+            f.read_exact(&mut magic)
+        });
+        match after_tag {
+            Ok(()) if &magic == b"fLaC" => (),
+            Ok(()) => return Err(SourceError::UnknownFormat),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                return Err(SourceError::UnknownFormat)
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    match &magic {
+        b"RIFF" | b"RF64" | b"BW64" => Ok(Box::new(WavReader::open(path)?)),
+        b"FORM" => Ok(Box::new(BufferSource::new(AiffFile::read(path)?.data))),
+        b"fLaC" => Ok(Box::new(BufferSource::new(FlacFile::read(path)?.data))),
+        b"OggS" => Ok(Box::new(BufferSource::new(VorbisFile::read(path)?.data))),
+        _ => Err(SourceError::UnknownFormat),
+    }
+}
+
+/// Reads the whole of a file of any supported format
+pub fn read(path: &Path) -> Result<SampleBuffer, SourceError> {
+    let mut source = open(path)?;
+    let len = source.len() as usize;
+    source.read_block(len)
+}
+
+/// Writes a buffer in the format its extension names, WAV when it names none of them.
+/// Float samples are quantized to 24 bits for FLAC, which only stores integers.
+pub fn write(path: &Path, buffer: SampleBuffer) -> Result<(), SourceError> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    match extension.to_ascii_lowercase().as_str() {
+        "aif" | "aiff" | "aifc" => AiffFile::from_buffer(buffer).write(path)?,
+        "flac" => {
+            let buffer = match buffer.bit_depth() {
+                Some(b) if b.is_float() => {
+                    buffer.convert(BitDepth::U24(0), &mut Quantizer::default())
+                }
+                _ => buffer,
+            };
+            FlacFile::from_buffer(buffer).write(path)?
+        }
+        _ => WavFile::from_buffer(buffer)?.write(path)?,
+    }
+    Ok(())
+}
+
+#[test]
+fn test_open_by_magic() -> Result<(), SourceError> {
+    let samples = vec![BitDepth::U16(1), BitDepth::U16(-1), BitDepth::U16(2)];
+    let wav_path = std::env::temp_dir().join("test_open_by_magic.wav");
+    // The extension is wrong on purpose
+    let aiff_path = std::env::temp_dir().join("test_open_by_magic.wav.aiff.wav");
+    let flac_path = std::env::temp_dir().join("test_open_by_magic.flac");
+    let paths = [&wav_path, &aiff_path, &flac_path];
+    let check = || -> Result<(), SourceError> {
+        WavFile::from_buffer(SampleBuffer::mono(8000, samples.clone()))?.write(&wav_path)?;
+        AiffFile::from_buffer(SampleBuffer::mono(8000, samples.clone())).write(&aiff_path)?;
+        write(&flac_path, SampleBuffer::mono(8000, samples.clone()))?;
+        for path in paths {
+            let mut source = open(path)?;
+            assert_eq!(source.len(), 3);
+            source.seek(1)?;
+            let block = source.read_block(10)?;
+            assert!(matches!(
+                block.interleaved(),
+                [BitDepth::U16(-1), BitDepth::U16(2)]
+            ));
+            assert!(source.read_block(10)?.is_empty());
+        }
+        Ok(())
+    };
+    let result = check();
+    // Whichever of the files got written, even if one of them failed
+    for path in paths {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
+
+#[test]
+fn test_id3_tags() -> Result<(), SourceError> {
+    let path = std::env::temp_dir().join("test_id3_tags.flac");
+    write(&path, SampleBuffer::mono(8000, vec![BitDepth::U16(7); 5]))?;
+    let flac = std::fs::read(&path)?;
+    // A 3 byte tag body, then either the FLAC stream or the sync word of an MP3 frame
+    let tag = [b'I', b'D', b'3', 4, 0, 0, 0, 0, 0, 3, 0, 0, 0];
+    std::fs::write(&path, [&tag[..], &flac].concat())?;
+    let tagged = open(&path).map(|s| s.len());
+    std::fs::write(&path, [&tag[..], &[0xFF, 0xFB, 0x90, 0x64]].concat())?;
+    let mp3 = open(&path).map(|s| s.len());
+    std::fs::remove_file(&path)?;
+    assert_eq!(tagged?, 5);
+    assert!(matches!(mp3, Err(SourceError::UnknownFormat)));
+    Ok(())
+}
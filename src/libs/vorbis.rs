@@ -0,0 +1,1429 @@
+// Vorbis I audio in Ogg, decoded to float samples
+use crate::libs::buffer::SampleBuffer;
+use crate::libs::ogg::{OggError, PacketReader};
+use crate::libs::wav::BitDepth;
+use std::{
+    f64::consts::PI,
+    fmt,
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+const IDENTIFICATION: u8 = 1;
+const COMMENT: u8 = 3;
+const SETUP: u8 = 5;
+/// Codewords this short are looked up in one go, longer ones walk the tree
+const FAST_BITS: u32 = 10;
+/// Most values a VQ codebook may expand to, far more than encoders use but few enough to
+/// allocate
+const MAX_VECTOR_VALUES: usize = 1 << 24;
+
+#[derive(Debug)]
+pub enum VorbisError {
+    Io(std::io::Error),
+    Ogg(OggError),
+    /// The stream doesn't start with Vorbis headers
+    NotVorbis,
+    /// The stream holds another codec, e.g. Opus
+    UnsupportedCodec(&'static str),
+    /// A part of Vorbis I this decoder doesn't implement
+    Unsupported(&'static str),
+    /// A header packet that doesn't follow the format
+    BadHeader,
+    /// The stream ends before its headers do
+    Truncated,
+}
+
+impl fmt::Display for VorbisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VorbisError::Io(e) => write!(f, "I/O error: {e}"),
+            VorbisError::Ogg(e) => write!(f, "Ogg: {e}"),
+            VorbisError::NotVorbis => write!(f, "Not a Vorbis stream"),
+            VorbisError::UnsupportedCodec(codec) => write!(f, "{codec} isn't supported"),
+            VorbisError::Unsupported(what) => write!(f, "Unsupported Vorbis {what}"),
+            VorbisError::BadHeader => write!(f, "Malformed header"),
+            VorbisError::Truncated => write!(f, "Stream is truncated"),
+        }
+    }
+}
+
+impl std::error::Error for VorbisError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VorbisError::Io(e) => Some(e),
+            VorbisError::Ogg(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for VorbisError {
+    fn from(e: std::io::Error) -> Self {
+        VorbisError::Io(e)
+    }
+}
+
+impl From<OggError> for VorbisError {
+    fn from(e: OggError) -> Self {
+        match e {
+            OggError::Io(e) => VorbisError::Io(e),
+            e => VorbisError::Ogg(e),
+        }
+    }
+}
+
+/// A packet ran out of bits
+#[derive(Debug)]
+struct EndOfPacket;
+
+impl From<EndOfPacket> for VorbisError {
+    fn from(_: EndOfPacket) -> Self {
+        VorbisError::BadHeader
+    }
+}
+
+/// Reads Vorbis' bit packing, least significant bit first
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader { data, pos: 0 }
+    }
+    fn remaining(&self) -> usize {
+        self.data.len() * 8 - self.pos
+    }
+    /// Up to 32 bits, zero padded past the end
+    fn peek(&self, bits: u32) -> u32 {
+        let mut v = 0u64;
+        let mut pos = self.pos;
+        let mut got = 0;
+        while got < bits && pos / 8 < self.data.len() {
+            let off = pos % 8;
+            let take = (8 - off as u32).min(bits - got);
+            let chunk = (self.data[pos / 8] >> off) as u64 & ((1 << take) - 1);
+            v |= chunk << got;
+            got += take;
+            pos += take as usize;
+        }
+        v as u32
+    }
+    fn read(&mut self, bits: u32) -> Result<u32, EndOfPacket> {
+        if bits as usize > self.remaining() {
+            self.pos = self.data.len() * 8;
+            return Err(EndOfPacket);
+        }
+        let v = self.peek(bits);
+        self.pos += bits as usize;
+        Ok(v)
+    }
+    fn flag(&mut self) -> Result<bool, EndOfPacket> {
+        Ok(self.read(1)? == 1)
+    }
+}
+
+/// Bits needed to hold `v`
+fn ilog(v: u32) -> u32 {
+    32 - v.leading_zeros()
+}
+
+/// The float format of codebook lookup values
+fn float32_unpack(x: u32) -> f32 {
+    let mantissa = (x & 0x1F_FFFF) as f64;
+    let exponent = ((x >> 21) & 0x3FF) as i32;
+    let v = mantissa * 2f64.powi(exponent - 788);
+    (if x & 0x8000_0000 != 0 { -v } else { v }) as f32
+}
+
+/// Largest `r` with `r^dimensions <= entries`
+fn lookup1_values(entries: usize, dimensions: usize) -> usize {
+    // A power that overflows is past any count of entries
+    let fits = |r: usize| {
+        (0..dimensions)
+            .try_fold(1usize, |acc, _| acc.checked_mul(r))
+            .is_some_and(|p| p <= entries)
+    };
+    let mut r = (entries as f64).powf(1.0 / dimensions as f64) as usize;
+    while fits(r + 1) {
+        r += 1;
+    }
+    while r > 0 && !fits(r) {
+        r -= 1;
+    }
+    r
+}
+
+struct Codebook {
+    dimensions: usize,
+    /// Entry and codeword length, indexed by the next `FAST_BITS` bits
+    fast: Vec<(u32, u8)>,
+    /// Binary tree of the codewords: 0 is no child, positive a node and negative an entry
+    tree: Vec<[i32; 2]>,
+    /// `dimensions` values for each entry, empty for scalar books
+    vectors: Vec<f32>,
+}
+
+impl Codebook {
+    fn read(r: &mut BitReader) -> Result<Codebook, VorbisError> {
+        if r.read(24)? != 0x564342 {
+            return Err(VorbisError::BadHeader);
+        }
+        let dimensions = r.read(16)? as usize;
+        let entries = r.read(24)? as usize;
+        let mut lengths = vec![0u8; entries];
+        if r.flag()? {
+            // Ordered, runs of entries with lengths increasing by one
+            let mut length = r.read(5)? + 1;
+            let mut entry = 0;
+            while entry < entries {
+                let count = r.read(ilog((entries - entry) as u32))? as usize;
+                if entry + count > entries || length > 32 {
+                    return Err(VorbisError::BadHeader);
+                }
+                lengths[entry..entry + count].fill(length as u8);
+                entry += count;
+                length += 1;
+            }
+        } else {
+            let sparse = r.flag()?;
+            for length in lengths.iter_mut() {
+                if !sparse || r.flag()? {
+                    *length = r.read(5)? as u8 + 1;
+                }
+            }
+        }
+
+        let vectors = match r.read(4)? {
+            0 => Vec::new(),
+            lookup @ (1 | 2) => {
+                // Without dimensions there's nothing to look up, and no root for lookup1_values
+                let size = entries
+                    .checked_mul(dimensions)
+                    .filter(|&size| dimensions > 0 && size <= MAX_VECTOR_VALUES)
+                    .ok_or(VorbisError::BadHeader)?;
+                let minimum = float32_unpack(r.read(32)?);
+                let delta = float32_unpack(r.read(32)?);
+                let value_bits = r.read(4)? + 1;
+                let sequence = r.flag()?;
+                let count = match lookup {
+                    1 => lookup1_values(entries, dimensions),
+                    _ => size,
+                };
+                let multiplicands = (0..count)
+                    .map(|_| r.read(value_bits))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if count == 0 && entries > 0 {
+                    return Err(VorbisError::BadHeader);
+                }
+                let mut vectors = Vec::with_capacity(size);
+                for entry in 0..entries {
+                    let mut last = 0.0;
+                    let mut divisor = 1;
+                    for i in 0..dimensions {
+                        let offset = match lookup {
+                            1 => entry / divisor % count,
+                            _ => entry * dimensions + i,
+                        };
+                        let v = multiplicands[offset] as f32 * delta + minimum + last;
+                        if sequence {
+                            last = v;
+                        }
+                        vectors.push(v);
+                        divisor = divisor.saturating_mul(count);
+                    }
+                }
+                vectors
+            }
+            _ => return Err(VorbisError::BadHeader),
+        };
+
+        let mut book = Codebook {
+            dimensions,
+            fast: vec![(0, 0); 1 << FAST_BITS],
+            tree: vec![[0, 0]],
+            vectors,
+        };
+        book.assign_codewords(&lengths)?;
+        Ok(book)
+    }
+    /// Gives each entry in turn the lowest codeword of its length still free
+    fn assign_codewords(&mut self, lengths: &[u8]) -> Result<(), VorbisError> {
+        // Free codewords of each length, left aligned
+        let mut available = [0u32; 33];
+        let mut first = true;
+        for (entry, &len) in lengths.iter().enumerate() {
+            let len = len as usize;
+            if len == 0 {
+                continue;
+            }
+            let code = if first {
+                first = false;
+                for (i, slot) in available.iter_mut().enumerate().take(len + 1).skip(1) {
+                    *slot = 1 << (32 - i);
+                }
+                0
+            } else {
+                let Some(z) = (1..=len).rev().find(|&z| available[z] != 0) else {
+                    return Err(VorbisError::BadHeader);
+                };
+                let code = available[z];
+                available[z] = 0;
+                for y in (z + 1..=len).rev() {
+                    available[y] = code + (1 << (32 - y));
+                }
+                code
+            };
+            self.insert(code >> (32 - len), len as u32, entry as u32);
+        }
+        Ok(())
+    }
+    fn insert(&mut self, code: u32, len: u32, entry: u32) {
+        if len <= FAST_BITS {
+            // Bits are read first to last, so the table is indexed by the codeword reversed
+            let reversed = code.reverse_bits() >> (32 - len);
+            for high in 0..1 << (FAST_BITS - len) {
+                self.fast[(reversed | high << len) as usize] = (entry, len as u8);
+            }
+        }
+        let mut node = 0;
+        for i in (0..len).rev() {
+            let bit = (code >> i & 1) as usize;
+            if i == 0 {
+                self.tree[node][bit] = -(entry as i32) - 1;
+            } else {
+                if self.tree[node][bit] <= 0 {
+                    self.tree.push([0, 0]);
+                    self.tree[node][bit] = self.tree.len() as i32 - 1;
+                }
+                node = self.tree[node][bit] as usize;
+            }
+        }
+    }
+    fn decode(&self, r: &mut BitReader) -> Result<usize, EndOfPacket> {
+        let (entry, len) = self.fast[r.peek(FAST_BITS) as usize];
+        if len > 0 && len as usize <= r.remaining() {
+            r.pos += len as usize;
+            return Ok(entry as usize);
+        }
+        let mut node = 0;
+        loop {
+            match self.tree[node][r.read(1)? as usize] {
+                // A codeword no entry has, as in a book with a single entry
+                0 => return Err(EndOfPacket),
+                child if child < 0 => return Ok((-child - 1) as usize),
+                child => node = child as usize,
+            }
+        }
+    }
+    fn decode_vector(&self, r: &mut BitReader) -> Result<&[f32], EndOfPacket> {
+        let entry = self.decode(r)?;
+        Ok(&self.vectors[entry * self.dimensions..(entry + 1) * self.dimensions])
+    }
+}
+
+struct Floor {
+    partition_classes: Vec<usize>,
+    class_dimensions: Vec<usize>,
+    class_subclasses: Vec<u32>,
+    class_masterbooks: Vec<usize>,
+    /// Book of each subclass, -1 for none
+    subclass_books: Vec<Vec<i32>>,
+    multiplier: i32,
+    x: Vec<i32>,
+    /// Indices of `x` in ascending order
+    sorted: Vec<usize>,
+    /// Low and high neighbours of each point after the first two
+    neighbors: Vec<(usize, usize)>,
+}
+
+impl Floor {
+    fn read(r: &mut BitReader, books: &[Codebook]) -> Result<Floor, VorbisError> {
+        match r.read(16)? {
+            0 => return Err(VorbisError::Unsupported("floor type 0")),
+            1 => {}
+            _ => return Err(VorbisError::BadHeader),
+        }
+        let book = |i: u32| match (i as usize) < books.len() {
+            true => Ok(i as usize),
+            false => Err(VorbisError::BadHeader),
+        };
+        let partitions = r.read(5)? as usize;
+        let partition_classes = (0..partitions)
+            .map(|_| r.read(4).map(|c| c as usize))
+            .collect::<Result<Vec<_>, _>>()?;
+        let classes = partition_classes.iter().max().map_or(0, |&c| c + 1);
+        let mut floor = Floor {
+            partition_classes,
+            class_dimensions: Vec::new(),
+            class_subclasses: Vec::new(),
+            class_masterbooks: Vec::new(),
+            subclass_books: Vec::new(),
+            multiplier: 0,
+            x: Vec::new(),
+            sorted: Vec::new(),
+            neighbors: Vec::new(),
+        };
+        for _ in 0..classes {
+            floor.class_dimensions.push(r.read(3)? as usize + 1);
+            let subclasses = r.read(2)?;
+            floor.class_subclasses.push(subclasses);
+            floor.class_masterbooks.push(match subclasses {
+                0 => 0,
+                _ => book(r.read(8)?)?,
+            });
+            let mut subclass_books = Vec::new();
+            for _ in 0..1 << subclasses {
+                subclass_books.push(match r.read(8)? {
+                    0 => -1,
+                    b => book(b - 1)? as i32,
+                });
+            }
+            floor.subclass_books.push(subclass_books);
+        }
+        floor.multiplier = r.read(2)? as i32 + 1;
+        let range_bits = r.read(4)?;
+        floor.x = vec![0, 1 << range_bits];
+        for &class in &floor.partition_classes {
+            for _ in 0..floor.class_dimensions[class] {
+                floor.x.push(r.read(range_bits)? as i32);
+            }
+        }
+        if floor.x.len() > 65 {
+            return Err(VorbisError::BadHeader);
+        }
+        floor.sorted = (0..floor.x.len()).collect();
+        floor.sorted.sort_by_key(|&i| floor.x[i]);
+        if floor
+            .sorted
+            .windows(2)
+            .any(|w| floor.x[w[0]] == floor.x[w[1]])
+        {
+            return Err(VorbisError::BadHeader);
+        }
+        for i in 2..floor.x.len() {
+            let below = (0..i).filter(|&j| floor.x[j] < floor.x[i]);
+            let above = (0..i).filter(|&j| floor.x[j] > floor.x[i]);
+            floor.neighbors.push((
+                below.max_by_key(|&j| floor.x[j]).unwrap(),
+                above.min_by_key(|&j| floor.x[j]).unwrap(),
+            ));
+        }
+        Ok(floor)
+    }
+    fn range(&self) -> i32 {
+        [256, 128, 86, 64][self.multiplier as usize - 1]
+    }
+    /// The packed Y values of the curve, `None` if the channel is unused in this packet
+    fn decode(
+        &self,
+        r: &mut BitReader,
+        books: &[Codebook],
+    ) -> Result<Option<Vec<i32>>, EndOfPacket> {
+        if !r.flag()? {
+            return Ok(None);
+        }
+        let bits = ilog(self.range() as u32 - 1);
+        let mut y = vec![r.read(bits)? as i32, r.read(bits)? as i32];
+        for &class in &self.partition_classes {
+            let subclasses = self.class_subclasses[class];
+            let mut selector = match subclasses {
+                0 => 0,
+                _ => books[self.class_masterbooks[class]].decode(r)?,
+            };
+            for _ in 0..self.class_dimensions[class] {
+                let book = self.subclass_books[class][selector & ((1 << subclasses) - 1)];
+                selector >>= subclasses;
+                y.push(match book {
+                    -1 => 0,
+                    b => books[b as usize].decode(r)? as i32,
+                });
+            }
+        }
+        Ok(Some(y))
+    }
+    /// Multiplies `spectrum` by the curve the Y values describe
+    fn apply(&self, y: &[i32], spectrum: &mut [f32]) {
+        let range = self.range();
+        // Y values are codebook entries, which can be far outside the range and overflow the
+        // interpolation, so every final value is clamped to it
+        let mut final_y: Vec<i32> = y.iter().map(|&v| v.clamp(0, range - 1)).collect();
+        let mut used = vec![true; y.len()];
+        for (i, &(low, high)) in self.neighbors.iter().enumerate() {
+            let i = i + 2;
+            let predicted = render_point(
+                self.x[low],
+                final_y[low],
+                self.x[high],
+                final_y[high],
+                self.x[i],
+            );
+            let (high_room, low_room) = (range - predicted, predicted);
+            let room = high_room.min(low_room) * 2;
+            let v = y[i];
+            if v == 0 {
+                used[i] = false;
+                final_y[i] = predicted;
+                continue;
+            }
+            used[low] = true;
+            used[high] = true;
+            final_y[i] = if v >= room {
+                if high_room > low_room {
+                    v - low_room + predicted
+                } else {
+                    predicted - v + high_room - 1
+                }
+            } else if v % 2 == 1 {
+                predicted - (v + 1) / 2
+            } else {
+                predicted + v / 2
+            }
+            .clamp(0, range - 1);
+        }
+
+        let n = spectrum.len() as i32;
+        let mut curve = vec![0; spectrum.len()];
+        let (mut lx, mut ly) = (0, final_y[self.sorted[0]] * self.multiplier);
+        for &i in &self.sorted[1..] {
+            if used[i] {
+                let (hx, hy) = (self.x[i], final_y[i] * self.multiplier);
+                render_line(lx, ly, hx, hy, &mut curve);
+                (lx, ly) = (hx, hy);
+            }
+        }
+        if lx < n {
+            render_line(lx, ly, n, ly, &mut curve);
+        }
+        for (s, &c) in spectrum.iter_mut().zip(&curve) {
+            *s *= inverse_db(c);
+        }
+    }
+}
+
+fn render_point(x0: i32, y0: i32, x1: i32, y1: i32, x: i32) -> i32 {
+    let dy = y1 - y0;
+    let offset = dy.abs() * (x - x0) / (x1 - x0);
+    if dy < 0 {
+        y0 - offset
+    } else {
+        y0 + offset
+    }
+}
+
+/// Bresenham's line from (x0, y0) up to x1, as the floor defines it
+fn render_line(x0: i32, y0: i32, x1: i32, y1: i32, v: &mut [i32]) {
+    let dy = y1 - y0;
+    let adx = x1 - x0;
+    let base = dy / adx;
+    let sy = if dy < 0 { base - 1 } else { base + 1 };
+    let ady = dy.abs() - base.abs() * adx;
+    let (mut y, mut err) = (y0, 0);
+    for x in x0..x1.min(v.len() as i32) {
+        if x > x0 {
+            err += ady;
+            if err >= adx {
+                err -= adx;
+                y += sy;
+            } else {
+                y += base;
+            }
+        }
+        v[x as usize] = y;
+    }
+}
+
+/// The floor's 256 step amplitude scale, from -140 dB to 0 dB
+fn inverse_db(i: i32) -> f32 {
+    const MIN: f64 = 1.0649863e-07;
+    MIN.powf((255 - i.clamp(0, 255)) as f64 / 255.0) as f32
+}
+
+struct Residue {
+    kind: u32,
+    begin: usize,
+    end: usize,
+    partition_size: usize,
+    classifications: usize,
+    classbook: usize,
+    /// Book for each classification and pass, -1 for none
+    books: Vec<[i32; 8]>,
+}
+
+impl Residue {
+    fn read(r: &mut BitReader, books: &[Codebook]) -> Result<Residue, VorbisError> {
+        let kind = r.read(16)?;
+        if kind > 2 {
+            return Err(VorbisError::BadHeader);
+        }
+        let mut residue = Residue {
+            kind,
+            begin: r.read(24)? as usize,
+            end: r.read(24)? as usize,
+            partition_size: r.read(24)? as usize + 1,
+            classifications: r.read(6)? as usize + 1,
+            classbook: r.read(8)? as usize,
+            books: Vec::new(),
+        };
+        let mut cascades = Vec::new();
+        for _ in 0..residue.classifications {
+            let low = r.read(3)?;
+            let high = if r.flag()? { r.read(5)? } else { 0 };
+            cascades.push(high << 3 | low);
+        }
+        for cascade in cascades {
+            let mut passes = [-1; 8];
+            for (pass, book) in passes.iter_mut().enumerate() {
+                if cascade >> pass & 1 == 1 {
+                    let b = r.read(8)? as usize;
+                    if books.get(b).is_none_or(|b| b.vectors.is_empty()) {
+                        return Err(VorbisError::BadHeader);
+                    }
+                    *book = b as i32;
+                }
+            }
+            residue.books.push(passes);
+        }
+        if residue.classbook >= books.len() {
+            return Err(VorbisError::BadHeader);
+        }
+        Ok(residue)
+    }
+    /// Adds the decoded residue to each vector not marked `skip`
+    fn decode(
+        &self,
+        r: &mut BitReader,
+        books: &[Codebook],
+        vectors: &mut [Vec<f32>],
+        skip: &[bool],
+    ) {
+        if self.kind != 2 {
+            // Running out of bits just ends the residue
+            let _ = self.decode_format(r, books, vectors, skip);
+            return;
+        }
+        if skip.iter().all(|&s| s) {
+            return;
+        }
+        // All channels interleaved into one vector
+        let channels = vectors.len();
+        let mut joined = vec![vec![0.0; vectors[0].len() * channels]];
+        let _ = self.decode_format(r, books, &mut joined, &[false]);
+        for (i, v) in joined[0].iter().enumerate() {
+            vectors[i % channels][i / channels] = *v;
+        }
+    }
+    fn decode_format(
+        &self,
+        r: &mut BitReader,
+        books: &[Codebook],
+        vectors: &mut [Vec<f32>],
+        skip: &[bool],
+    ) -> Result<(), EndOfPacket> {
+        let size = vectors.first().map_or(0, |v| v.len());
+        let begin = self.begin.min(size);
+        let end = self.end.min(size);
+        let partitions = end.saturating_sub(begin) / self.partition_size;
+        let classbook = &books[self.classbook];
+        let per_word = classbook.dimensions.max(1);
+        let mut classes = vec![vec![0; partitions]; vectors.len()];
+        for pass in 0..8 {
+            let mut partition = 0;
+            while partition < partitions {
+                if pass == 0 {
+                    for (ch, classes) in classes.iter_mut().enumerate() {
+                        if skip[ch] {
+                            continue;
+                        }
+                        let mut word = classbook.decode(r)?;
+                        for i in (0..per_word).rev() {
+                            if let Some(class) = classes.get_mut(partition + i) {
+                                *class = word % self.classifications;
+                            }
+                            word /= self.classifications;
+                        }
+                    }
+                }
+                for _ in 0..per_word {
+                    if partition >= partitions {
+                        break;
+                    }
+                    for (ch, v) in vectors.iter_mut().enumerate() {
+                        if skip[ch] {
+                            continue;
+                        }
+                        let book = self.books[classes[ch][partition]][pass];
+                        if book < 0 {
+                            continue;
+                        }
+                        let book = &books[book as usize];
+                        let offset = begin + partition * self.partition_size;
+                        let v = &mut v[offset..offset + self.partition_size];
+                        self.decode_partition(r, book, v)?;
+                    }
+                    partition += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+    fn decode_partition(
+        &self,
+        r: &mut BitReader,
+        book: &Codebook,
+        v: &mut [f32],
+    ) -> Result<(), EndOfPacket> {
+        let dimensions = book.dimensions;
+        if self.kind == 0 {
+            // Each vector's values are spread a step apart
+            let step = v.len() / dimensions;
+            for i in 0..step {
+                for (j, value) in book.decode_vector(r)?.iter().enumerate() {
+                    v[i + j * step] += value;
+                }
+            }
+        } else {
+            for chunk in v.chunks_mut(dimensions) {
+                for (s, value) in chunk.iter_mut().zip(book.decode_vector(r)?) {
+                    *s += value;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Mapping {
+    /// Magnitude and angle channels
+    coupling: Vec<(usize, usize)>,
+    /// Submap of each channel
+    mux: Vec<usize>,
+    /// Floor and residue of each submap
+    submaps: Vec<(usize, usize)>,
+}
+
+impl Mapping {
+    fn read(
+        r: &mut BitReader,
+        channels: usize,
+        floors: usize,
+        residues: usize,
+    ) -> Result<Mapping, VorbisError> {
+        if r.read(16)? != 0 {
+            return Err(VorbisError::BadHeader);
+        }
+        let submaps = if r.flag()? {
+            r.read(4)? as usize + 1
+        } else {
+            1
+        };
+        let mut coupling = Vec::new();
+        if r.flag()? {
+            let bits = ilog(channels as u32 - 1);
+            for _ in 0..r.read(8)? + 1 {
+                let (magnitude, angle) = (r.read(bits)? as usize, r.read(bits)? as usize);
+                if magnitude == angle || magnitude >= channels || angle >= channels {
+                    return Err(VorbisError::BadHeader);
+                }
+                coupling.push((magnitude, angle));
+            }
+        }
+        if r.read(2)? != 0 {
+            return Err(VorbisError::BadHeader);
+        }
+        let mut mux = vec![0; channels];
+        if submaps > 1 {
+            for m in mux.iter_mut() {
+                *m = r.read(4)? as usize;
+                if *m >= submaps {
+                    return Err(VorbisError::BadHeader);
+                }
+            }
+        }
+        let mut mapping = Mapping {
+            coupling,
+            mux,
+            submaps: Vec::new(),
+        };
+        for _ in 0..submaps {
+            // An unused time configuration
+            r.read(8)?;
+            let (floor, residue) = (r.read(8)? as usize, r.read(8)? as usize);
+            if floor >= floors || residue >= residues {
+                return Err(VorbisError::BadHeader);
+            }
+            mapping.submaps.push((floor, residue));
+        }
+        Ok(mapping)
+    }
+}
+
+struct Mode {
+    long: bool,
+    mapping: usize,
+}
+
+/// Inverse MDCT through a complex FFT of the block size
+struct Imdct {
+    /// Twiddles of the FFT, e^(-2πik/n)
+    twiddles: Vec<(f64, f64)>,
+    /// e^(-iπk/n) applied before the FFT and e^(-iπ(k+½)/n) after
+    pre: Vec<(f64, f64)>,
+    post: Vec<(f64, f64)>,
+}
+
+fn mul(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    (a.0 * b.0 - a.1 * b.1, a.0 * b.1 + a.1 * b.0)
+}
+
+fn expi(angle: f64) -> (f64, f64) {
+    (angle.cos(), angle.sin())
+}
+
+impl Imdct {
+    fn new(n: usize) -> Imdct {
+        let m = n / 2;
+        Imdct {
+            twiddles: (0..n / 2)
+                .map(|k| expi(-2.0 * PI * k as f64 / n as f64))
+                .collect(),
+            pre: (0..m).map(|k| expi(-PI * k as f64 / n as f64)).collect(),
+            post: (0..m)
+                .map(|k| expi(-PI * (k as f64 + 0.5) / n as f64))
+                .collect(),
+        }
+    }
+    /// `n` samples from `n / 2` coefficients
+    fn run(&self, x: &[f32]) -> Vec<f32> {
+        let m = x.len();
+        let n = 2 * m;
+        let mut buf = vec![(0.0, 0.0); n];
+        for (k, &v) in x.iter().enumerate() {
+            buf[k] = (v as f64 * self.pre[k].0, v as f64 * self.pre[k].1);
+        }
+        self.fft(&mut buf);
+        // A DCT-IV of the coefficients, which the MDCT output folds out of
+        let u: Vec<f64> = (0..m).map(|k| mul(buf[k], self.post[k]).0).collect();
+        (0..n)
+            .map(|i| match i {
+                i if i < m / 2 => u[i + m / 2],
+                i if i < 3 * m / 2 => -u[3 * m / 2 - 1 - i],
+                i => -u[i - 3 * m / 2],
+            } as f32)
+            .collect()
+    }
+    fn fft(&self, buf: &mut [(f64, f64)]) {
+        let n = buf.len();
+        let bits = n.trailing_zeros();
+        for i in 0..n {
+            let j = i.reverse_bits() >> (usize::BITS - bits);
+            if i < j {
+                buf.swap(i, j);
+            }
+        }
+        let mut len = 2;
+        while len <= n {
+            let stride = n / len;
+            for start in (0..n).step_by(len) {
+                for k in 0..len / 2 {
+                    let t = mul(buf[start + k + len / 2], self.twiddles[k * stride]);
+                    let a = buf[start + k];
+                    buf[start + k] = (a.0 + t.0, a.1 + t.1);
+                    buf[start + k + len / 2] = (a.0 - t.0, a.1 - t.1);
+                }
+            }
+            len *= 2;
+        }
+    }
+}
+
+struct Decoder {
+    channels: usize,
+    block_sizes: [usize; 2],
+    books: Vec<Codebook>,
+    floors: Vec<Floor>,
+    residues: Vec<Residue>,
+    mappings: Vec<Mapping>,
+    modes: Vec<Mode>,
+    imdct: [Imdct; 2],
+    /// Rising half of the window for each block size
+    slopes: [Vec<f32>; 2],
+    /// Second half of the last block and whether it was long
+    overlap: Option<(Vec<Vec<f32>>, bool)>,
+}
+
+/// Checks a header's type and `vorbis` signature
+fn header<'a>(packet: &'a [u8], kind: u8) -> Result<BitReader<'a>, VorbisError> {
+    if packet.len() < 7 || packet[0] != kind || &packet[1..7] != b"vorbis" {
+        return Err(VorbisError::BadHeader);
+    }
+    Ok(BitReader::new(&packet[7..]))
+}
+
+impl Decoder {
+    fn new(channels: usize, block_sizes: [usize; 2], setup: &[u8]) -> Result<Decoder, VorbisError> {
+        let mut r = header(setup, SETUP)?;
+        let books = (0..r.read(8)? + 1)
+            .map(|_| Codebook::read(&mut r))
+            .collect::<Result<Vec<_>, _>>()?;
+        for _ in 0..r.read(6)? + 1 {
+            // Time domain transforms, placeholders that must be 0
+            if r.read(16)? != 0 {
+                return Err(VorbisError::BadHeader);
+            }
+        }
+        let floors = (0..r.read(6)? + 1)
+            .map(|_| Floor::read(&mut r, &books))
+            .collect::<Result<Vec<_>, _>>()?;
+        let residues = (0..r.read(6)? + 1)
+            .map(|_| Residue::read(&mut r, &books))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mappings = (0..r.read(6)? + 1)
+            .map(|_| Mapping::read(&mut r, channels, floors.len(), residues.len()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut modes = Vec::new();
+        for _ in 0..r.read(6)? + 1 {
+            let long = r.flag()?;
+            let (window, transform) = (r.read(16)?, r.read(16)?);
+            let mapping = r.read(8)? as usize;
+            if window != 0 || transform != 0 || mapping >= mappings.len() {
+                return Err(VorbisError::BadHeader);
+            }
+            modes.push(Mode { long, mapping });
+        }
+        if !r.flag()? {
+            return Err(VorbisError::BadHeader);
+        }
+        let slope = |n: usize| {
+            let len = n / 2;
+            (0..len)
+                .map(|i| {
+                    let s = ((i as f64 + 0.5) / len as f64 * PI / 2.0).sin();
+                    (PI / 2.0 * s * s).sin() as f32
+                })
+                .collect()
+        };
+        Ok(Decoder {
+            channels,
+            block_sizes,
+            books,
+            floors,
+            residues,
+            mappings,
+            modes,
+            imdct: [Imdct::new(block_sizes[0]), Imdct::new(block_sizes[1])],
+            slopes: [slope(block_sizes[0]), slope(block_sizes[1])],
+            overlap: None,
+        })
+    }
+    /// Decodes an audio packet, giving the samples of each channel it finishes. Packets that
+    /// can't be decoded give none, as the format says.
+    fn decode(&mut self, packet: &[u8]) -> Vec<Vec<f32>> {
+        let mut r = BitReader::new(packet);
+        let Some((long, prev_long, next_long, mapping)) = self.read_mode(&mut r) else {
+            return Vec::new();
+        };
+        let n = self.block_sizes[long as usize];
+        let mapping = &self.mappings[mapping];
+
+        let mut floors = Vec::with_capacity(self.channels);
+        for ch in 0..self.channels {
+            let floor = &self.floors[mapping.submaps[mapping.mux[ch]].0];
+            // A channel cut off by the end of the packet is silent
+            floors.push(floor.decode(&mut r, &self.books).ok().flatten());
+        }
+        let mut skip: Vec<bool> = floors.iter().map(|f| f.is_none()).collect();
+        for &(magnitude, angle) in &mapping.coupling {
+            if !skip[magnitude] || !skip[angle] {
+                skip[magnitude] = false;
+                skip[angle] = false;
+            }
+        }
+
+        let mut spectra = vec![vec![0.0f32; n / 2]; self.channels];
+        for (submap, &(_, residue)) in mapping.submaps.iter().enumerate() {
+            let members: Vec<usize> = (0..self.channels)
+                .filter(|&ch| mapping.mux[ch] == submap)
+                .collect();
+            let mut vectors: Vec<Vec<f32>> = members
+                .iter()
+                .map(|&ch| std::mem::take(&mut spectra[ch]))
+                .collect();
+            let member_skip: Vec<bool> = members.iter().map(|&ch| skip[ch]).collect();
+            self.residues[residue].decode(&mut r, &self.books, &mut vectors, &member_skip);
+            for (&ch, v) in members.iter().zip(vectors) {
+                spectra[ch] = v;
+            }
+        }
+
+        for &(magnitude, angle) in mapping.coupling.iter().rev() {
+            let mut angles = std::mem::take(&mut spectra[angle]);
+            for (m, a) in spectra[magnitude].iter_mut().zip(angles.iter_mut()) {
+                (*m, *a) = match (*m > 0.0, *a > 0.0) {
+                    (true, true) => (*m, *m - *a),
+                    (true, false) => (*m + *a, *m),
+                    (false, true) => (*m, *m + *a),
+                    (false, false) => (*m - *a, *m),
+                };
+            }
+            spectra[angle] = angles;
+        }
+
+        let mut blocks = Vec::with_capacity(self.channels);
+        for (ch, spectrum) in spectra.iter_mut().enumerate() {
+            match &floors[ch] {
+                Some(y) => self.floors[mapping.submaps[mapping.mux[ch]].0].apply(y, spectrum),
+                None => spectrum.fill(0.0),
+            }
+            let mut block = self.imdct[long as usize].run(spectrum);
+            self.window(&mut block, long, prev_long, next_long);
+            blocks.push(block);
+        }
+        self.overlap_add(blocks, long)
+    }
+    /// Block size flags and mapping of an audio packet
+    fn read_mode(&self, r: &mut BitReader) -> Option<(bool, bool, bool, usize)> {
+        if r.read(1).ok()? != 0 {
+            return None;
+        }
+        let mode = self
+            .modes
+            .get(r.read(ilog(self.modes.len() as u32 - 1)).ok()? as usize)?;
+        let (prev, next) = match mode.long {
+            true => (r.flag().ok()?, r.flag().ok()?),
+            false => (false, false),
+        };
+        Some((mode.long, prev, next, mode.mapping))
+    }
+    /// Shapes a block with slopes that match the neighbouring blocks' sizes
+    fn window(&self, block: &mut [f32], long: bool, prev_long: bool, next_long: bool) {
+        let n = block.len();
+        let short = self.block_sizes[0];
+        let (left, left_slope) = match long && !prev_long {
+            true => (n / 4 - short / 4, &self.slopes[0]),
+            false => (0, &self.slopes[long as usize]),
+        };
+        let (right, right_slope) = match long && !next_long {
+            true => (n * 3 / 4 - short / 4, &self.slopes[0]),
+            false => (n / 2, &self.slopes[long as usize]),
+        };
+        block[..left].fill(0.0);
+        for (s, w) in block[left..].iter_mut().zip(left_slope) {
+            *s *= w;
+        }
+        for (s, w) in block[right..].iter_mut().zip(right_slope.iter().rev()) {
+            *s *= w;
+        }
+        block[right + right_slope.len()..].fill(0.0);
+    }
+    /// Joins the first half of this block to the second half of the last, giving the samples
+    /// between their centres
+    fn overlap_add(&mut self, blocks: Vec<Vec<f32>>, long: bool) -> Vec<Vec<f32>> {
+        let n = self.block_sizes[long as usize];
+        let tails = blocks.iter().map(|b| b[n / 2..].to_vec()).collect();
+        let Some((prev, prev_long)) = self.overlap.replace((tails, long)) else {
+            return vec![Vec::new(); self.channels];
+        };
+        let prev_n = self.block_sizes[prev_long as usize];
+        let len = prev_n / 4 + n / 4;
+        prev.iter()
+            .zip(&blocks)
+            .map(|(prev, block)| {
+                (0..len)
+                    .map(|t| {
+                        let i = (t + n / 4).checked_sub(prev_n / 4);
+                        prev.get(t).unwrap_or(&0.0)
+                            + i.filter(|&i| i < n / 2).map_or(0.0, |i| block[i])
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+pub struct VorbisFile {
+    pub data: SampleBuffer,
+    pub vendor: String,
+    /// Tags like `TITLE=...`, split at the first `=`
+    pub comments: Vec<(String, String)>,
+}
+
+impl VorbisFile {
+    pub fn read(path: &Path) -> Result<VorbisFile, VorbisError> {
+        VorbisFile::read_from(&mut BufReader::new(File::open(path)?))
+    }
+    /// Decodes the first logical stream of an Ogg file
+    pub fn read_from<R: Read>(r: &mut R) -> Result<VorbisFile, VorbisError> {
+        let mut packets = PacketReader::new(r);
+        let id = packets.next_packet()?.ok_or(VorbisError::NotVorbis)?;
+        if id.starts_with(b"OpusHead") {
+            return Err(VorbisError::UnsupportedCodec("Opus"));
+        }
+        let mut h = header(&id, IDENTIFICATION).map_err(|_| VorbisError::NotVorbis)?;
+        if h.read(32)? != 0 {
+            return Err(VorbisError::Unsupported("version"));
+        }
+        let channels = h.read(8)? as usize;
+        let sample_rate = h.read(32)?;
+        // Maximum, nominal and minimum bitrates
+        for _ in 0..3 {
+            h.read(32)?;
+        }
+        let block_sizes = [1 << h.read(4)?, 1 << h.read(4)?];
+        if channels == 0
+            || sample_rate == 0
+            || block_sizes[0] < 64
+            || block_sizes[0] > block_sizes[1]
+            || block_sizes[1] > 8192
+            || !h.flag()?
+        {
+            return Err(VorbisError::BadHeader);
+        }
+
+        let comment = packets.next_packet()?.ok_or(VorbisError::Truncated)?;
+        let (vendor, comments) = read_comments(&comment)?;
+        let setup = packets.next_packet()?.ok_or(VorbisError::Truncated)?;
+        let mut decoder = Decoder::new(channels, block_sizes, &setup)?;
+
+        let mut planes = vec![Vec::new(); channels];
+        while let Some(packet) = packets.next_packet()? {
+            for (plane, samples) in planes.iter_mut().zip(decoder.decode(&packet)) {
+                plane.extend(samples);
+            }
+        }
+        // The last page's position says where the stream really ends
+        if let Ok(end) = usize::try_from(packets.granule_position) {
+            for plane in planes.iter_mut() {
+                plane.truncate(end);
+            }
+        }
+        let planes = planes
+            .into_iter()
+            .map(|p| p.into_iter().map(BitDepth::F32).collect())
+            .collect();
+        Ok(VorbisFile {
+            data: SampleBuffer::from_planar(sample_rate, planes),
+            vendor,
+            comments,
+        })
+    }
+}
+
+fn read_comments(packet: &[u8]) -> Result<(String, Vec<(String, String)>), VorbisError> {
+    let mut r = header(packet, COMMENT)?;
+    let string = |r: &mut BitReader| -> Result<String, VorbisError> {
+        let len = r.read(32)? as usize;
+        if len * 8 > r.remaining() {
+            return Err(VorbisError::BadHeader);
+        }
+        let bytes = (0..len)
+            .map(|_| r.read(8).map(|b| b as u8))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    };
+    let vendor = string(&mut r)?;
+    let mut comments = Vec::new();
+    for _ in 0..r.read(32)? {
+        let comment = string(&mut r)?;
+        let (key, value) = comment.split_once('=').unwrap_or((&comment, ""));
+        comments.push((key.to_ascii_uppercase(), value.to_string()));
+    }
+    Ok((vendor, comments))
+}
+
+/// Packs bits the way `BitReader` reads them
+#[cfg(test)]
+struct BitWriter {
+    data: Vec<u8>,
+    bits: usize,
+}
+
+#[cfg(test)]
+impl BitWriter {
+    fn header(kind: u8) -> BitWriter {
+        let mut w = BitWriter {
+            data: Vec::new(),
+            bits: 0,
+        };
+        for b in [kind].iter().chain(b"vorbis") {
+            w.write(*b as u32, 8);
+        }
+        w
+    }
+    fn write(&mut self, v: u32, bits: u32) {
+        for i in 0..bits {
+            if self.bits.is_multiple_of(8) {
+                self.data.push(0);
+            }
+            self.data[self.bits / 8] |= ((v >> i & 1) as u8) << (self.bits % 8);
+            self.bits += 1;
+        }
+    }
+}
+
+/// Headers of a mono stream of 64 sample blocks, with a flat floor at `FLOOR` and a residue
+/// of ±1 for each bin
+#[cfg(test)]
+fn test_headers() -> [Vec<u8>; 3] {
+    let mut id = BitWriter::header(IDENTIFICATION);
+    for (v, bits) in [
+        (0, 32),
+        (1, 8),
+        (8000, 32),
+        (0, 32),
+        (0, 32),
+        (0, 32),
+        (6, 4),
+        (6, 4),
+        (1, 1),
+    ] {
+        id.write(v, bits);
+    }
+
+    let mut comment = BitWriter::header(COMMENT);
+    for s in [&b"test"[..], b"title=Tone"] {
+        comment.write(s.len() as u32, 32);
+        for &b in s {
+            comment.write(b as u32, 8);
+        }
+        if s == b"test" {
+            comment.write(1, 32);
+        }
+    }
+
+    let mut setup = BitWriter::header(SETUP);
+    setup.write(1, 8);
+    // A scalar book and a vector book of -1 and 1, both with codewords 0 and 1
+    for lookup in [0, 1] {
+        setup.write(0x564342, 24);
+        setup.write(1, 16);
+        setup.write(2, 24);
+        setup.write(0, 1);
+        setup.write(0, 1);
+        setup.write(0, 5);
+        setup.write(0, 5);
+        setup.write(lookup, 4);
+        if lookup == 1 {
+            setup.write(0x8000_0000 | 768 << 21 | 1 << 20, 32);
+            setup.write(769 << 21 | 1 << 20, 32);
+            setup.write(0, 4);
+            setup.write(0, 1);
+            setup.write(0, 1);
+            setup.write(1, 1);
+        }
+    }
+    // Time domain, then a floor 1 with no partitions and a range of 32
+    for (v, bits) in [(0, 6), (0, 16), (0, 6), (1, 16), (0, 5), (0, 2), (5, 4)] {
+        setup.write(v, bits);
+    }
+    // Residue 1 over the whole block in one partition, with the vector book on pass 0
+    for (v, bits) in [
+        (0, 6),
+        (1, 16),
+        (0, 24),
+        (32, 24),
+        (31, 24),
+        (0, 6),
+        (0, 8),
+        (1, 3),
+        (0, 1),
+        (1, 8),
+    ] {
+        setup.write(v, bits);
+    }
+    // One mapping and one short block mode
+    for (v, bits) in [
+        (0, 6),
+        (0, 16),
+        (0, 1),
+        (0, 1),
+        (0, 2),
+        (0, 8),
+        (0, 8),
+        (0, 8),
+    ] {
+        setup.write(v, bits);
+    }
+    for (v, bits) in [(0, 6), (0, 1), (0, 16), (0, 16), (0, 8), (1, 1)] {
+        setup.write(v, bits);
+    }
+    [id.data, comment.data, setup.data]
+}
+
+#[cfg(test)]
+const FLOOR: u32 = 200;
+
+#[cfg(test)]
+fn test_signs() -> Vec<f32> {
+    (0..32)
+        .map(|i| if i % 3 == 0 { 1.0 } else { -1.0 })
+        .collect()
+}
+
+#[test]
+fn test_imdct_matches_definition() {
+    let n = 64;
+    let x: Vec<f32> = (0..n / 2)
+        .map(|i| ((i * 7 % 5) as f32 - 2.0) / 3.0)
+        .collect();
+    let fast = Imdct::new(n).run(&x);
+    for (i, v) in fast.iter().enumerate() {
+        let direct: f64 = x
+            .iter()
+            .enumerate()
+            .map(|(j, &x)| {
+                let angle =
+                    PI / 2.0 / n as f64 * (2 * i + 1) as f64 + PI / 2.0 / n as f64 * (n / 2) as f64;
+                x as f64 * (angle * (2 * j + 1) as f64).cos()
+            })
+            .sum();
+        assert!((*v as f64 - direct).abs() < 1e-4, "{i}: {v} != {direct}");
+    }
+}
+
+#[test]
+fn test_decode_stream() -> Result<(), VorbisError> {
+    use crate::libs::ogg::page_bytes;
+    let [id, comment, setup] = test_headers();
+    let mut audio = BitWriter {
+        data: Vec::new(),
+        bits: 0,
+    };
+    // Audio packet, floor used, then the class and each bin's sign
+    for (v, bits) in [(0, 1), (1, 1), (FLOOR, 8), (FLOOR, 8), (0, 1)] {
+        audio.write(v, bits);
+    }
+    for s in test_signs() {
+        audio.write((s > 0.0) as u32, 1);
+    }
+    let audio = audio.data;
+    let mut stream = page_bytes(2, 0, 9, 0, &[&id]);
+    stream.extend(page_bytes(0, 0, 9, 1, &[&comment, &setup]));
+    // Four blocks give three blocks of samples, of which the last page keeps 70
+    stream.extend(page_bytes(4, 70, 9, 2, &[&audio, &audio, &audio, &audio]));
+
+    let file = VorbisFile::read_from(&mut &stream[..])?;
+    assert_eq!(file.vendor, "test");
+    assert_eq!(file.comments, [("TITLE".to_string(), "Tone".to_string())]);
+    assert_eq!(file.data.sample_rate, 8000);
+    assert_eq!(file.data.frames(), 70);
+
+    // Each output sample is the end of one windowed block added to the start of the next
+    let spectrum: Vec<f64> = test_signs()
+        .iter()
+        .map(|&s| s as f64 * inverse_db(FLOOR as i32) as f64)
+        .collect();
+    let block: Vec<f64> = (0..64)
+        .map(|i| {
+            let s = ((i as f64 + 0.5) / 64.0 * PI).sin();
+            let w = (PI / 2.0 * s * s).sin();
+            w * spectrum
+                .iter()
+                .enumerate()
+                .map(|(k, x)| x * (PI / 32.0 * (i as f64 + 0.5 + 16.0) * (k as f64 + 0.5)).cos())
+                .sum::<f64>()
+        })
+        .collect();
+    for (t, s) in file.data.to_f64().iter().enumerate() {
+        let expected = block[t % 32 + 32] + block[t % 32];
+        assert!((s - expected).abs() < 1e-5, "{t}: {s} != {expected}");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_floor_clamps_y() {
+    // The point at x = 1 is pushed far out of range and then predicts the one at x = 32767
+    let floor = Floor {
+        partition_classes: Vec::new(),
+        class_dimensions: Vec::new(),
+        class_subclasses: Vec::new(),
+        class_masterbooks: Vec::new(),
+        subclass_books: Vec::new(),
+        multiplier: 1,
+        x: vec![0, 32768, 1, 32767],
+        sorted: vec![0, 2, 3, 1],
+        neighbors: vec![(0, 1), (2, 1)],
+    };
+    let mut spectrum = vec![1.0; 64];
+    floor.apply(&[10, 20, (1 << 24) - 1, 5], &mut spectrum);
+    assert_eq!(spectrum[0], inverse_db(10));
+    assert_eq!(spectrum[1], inverse_db(255));
+}
+
+#[test]
+fn test_opus_is_reported() {
+    let head = b"OpusHead\x01\x02\x38\x01\x80\xbb\0\0\0\0\0";
+    let stream = crate::libs::ogg::page_bytes(2, 0, 1, 0, &[head]);
+    assert!(matches!(
+        VorbisFile::read_from(&mut &stream[..]),
+        Err(VorbisError::UnsupportedCodec("Opus"))
+    ));
+}
+
+#[test]
+fn test_bad_vq_codebooks() {
+    // (dimensions, entries), all of one bit codewords with a lookup table of type 1
+    for (dimensions, entries) in [(0, 2), (65535, (1 << 24) - 1)] {
+        let mut w = BitWriter {
+            data: Vec::new(),
+            bits: 0,
+        };
+        for (v, bits) in [
+            (0x564342, 24),
+            (dimensions, 16),
+            (entries, 24),
+            (1, 1),
+            (0, 5),
+            (entries, ilog(entries)),
+            (1, 4),
+            (0, 32),
+            (0, 32),
+            (0, 4),
+            (0, 1),
+        ] {
+            w.write(v, bits);
+        }
+        w.write(0, 32);
+        let result = Codebook::read(&mut BitReader::new(&w.data));
+        assert!(
+            matches!(result, Err(VorbisError::BadHeader)),
+            "{dimensions}"
+        );
+    }
+}
+
+#[test]
+fn test_lookup1_values_overflow() {
+    assert_eq!(lookup1_values(2, 91), 1);
+    assert_eq!(lookup1_values(1 << 20, 64), 1);
+    assert_eq!(lookup1_values(10, 2), 3);
+
+    // A type 1 book whose root overflows usize reads like any other
+    let mut w = BitWriter {
+        data: Vec::new(),
+        bits: 0,
+    };
+    for (v, bits) in [
+        (0x564342, 24),
+        (91, 16),
+        (2, 24),
+        (0, 1),
+        (0, 1),
+        (0, 5),
+        (0, 5),
+        (1, 4),
+        (0, 32),
+        (0, 32),
+        (0, 4),
+        (0, 1),
+        (0, 1),
+    ] {
+        w.write(v, bits);
+    }
+    let book = Codebook::read(&mut BitReader::new(&w.data)).unwrap();
+    assert_eq!(book.vectors.len(), 2 * 91);
+}
@@ -1,17 +1,179 @@
-use byteorder::{LittleEndian, ReadBytesExt};
-use std::str;
+use crate::libs::adpcm;
+use crate::libs::buffer::SampleBuffer;
+use crate::libs::convert::{self, Quantizer};
+use crate::libs::g711;
+use crate::libs::notation;
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::{
-    fs::{read, File},
-    io::{Error, Read, Seek, Write},
-    ops::Add,
+    collections::BTreeMap,
+    fmt,
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    ops::{Add, BitOr},
     path::Path,
 };
 
-type Fourcc = [u8; 4];
+pub type Fourcc = [u8; 4];
+
+pub const WAVE_FORMAT_PCM: u16 = 0x0001;
+pub const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+pub const WAVE_FORMAT_ALAW: u16 = 0x0006;
+pub const WAVE_FORMAT_MULAW: u16 = 0x0007;
+pub const WAVE_FORMAT_IMA_ADPCM: u16 = 0x0011;
+pub const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Tail shared by every `KSDATAFORMAT_SUBTYPE_*` GUID, the format tag goes in the first two bytes
+const SUBTYPE_GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
+#[derive(Debug)]
+pub enum WavError {
+    Io(std::io::Error),
+    /// The file doesn't start with a RIFF header
+    NotRiff,
+    /// The RIFF form isn't WAVE
+    NotWave,
+    /// A sample format this module can't decode
+    UnsupportedFormat {
+        fmt_tag: u16,
+        bits_per_sample: u16,
+    },
+    /// The file ends before a header or chunk does
+    Truncated,
+    MissingChunk(Fourcc),
+    /// A chunk that is too short or has invalid fields
+    MalformedChunk(Fourcc),
+    /// A sample that isn't of the kind the file holds
+    SampleMismatch,
+    /// A seek past the end of the data
+    OutOfRange,
+}
+
+impl fmt::Display for WavError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WavError::Io(e) => write!(f, "I/O error: {e}"),
+            WavError::NotRiff => write!(f, "Not a RIFF file"),
+            WavError::NotWave => write!(f, "Not a WAVE file"),
+            WavError::UnsupportedFormat {
+                fmt_tag,
+                bits_per_sample,
+            } => write!(
+                f,
+                "Unsupported format {fmt_tag:#06x} with {bits_per_sample} bits per sample"
+            ),
+            WavError::Truncated => write!(f, "File is truncated"),
+            WavError::MissingChunk(id) => {
+                write!(f, "Missing `{}` chunk", String::from_utf8_lossy(id))
+            }
+            WavError::MalformedChunk(id) => {
+                write!(f, "Malformed `{}` chunk", String::from_utf8_lossy(id))
+            }
+            WavError::SampleMismatch => write!(f, "Sample doesn't match the format of the file"),
+            WavError::OutOfRange => write!(f, "Position is past the end of the data"),
+        }
+    }
+}
+
+impl std::error::Error for WavError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WavError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for WavError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            ErrorKind::UnexpectedEof => WavError::Truncated,
+            _ => WavError::Io(e),
+        }
+    }
+}
+
+/// Checks the header at the start of the stream and returns the form, which is RIFF, RF64 or
+/// BW64, and the 32 bit size it declares
+fn read_riff_hdr<R: Read + Seek>(r: &mut R) -> Result<(Fourcc, u32), WavError> {
+    r.seek(SeekFrom::Start(0))?;
+    let mut riff_hdr = [0; 12];
+    r.read_exact(&mut riff_hdr)?;
+    let id: Fourcc = riff_hdr[0..4].try_into().unwrap();
+    if !matches!(&id, b"RIFF" | b"RF64" | b"BW64") {
+        return Err(WavError::NotRiff);
+    }
+    if &riff_hdr[8..12] != b"WAVE" {
+        return Err(WavError::NotWave);
+    }
+    Ok((id, LittleEndian::read_u32(&riff_hdr[4..8])))
+}
+
+/// Value of a 32 bit size field whose real value is in the `ds64` chunk
+const SIZE_IN_DS64: u32 = u32::MAX;
+
+/// The `ds64` chunk of RF64 and BW64 files (EBU Tech 3306, ITU-R BS.2088). It comes first and
+/// holds the 64 bit sizes of everything that doesn't fit in a 32 bit size field.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ds64 {
+    pub riff_size: u64,
+    pub data_size: u64,
+    /// Frames in the data chunk, stands in for the fact chunk
+    pub sample_count: u64,
+    /// Sizes of other chunks too big for their header
+    pub table: Vec<(Fourcc, u64)>,
+}
+
+impl Ds64 {
+    /// Bytes taken in the file by a ds64 chunk without a table, counting its header
+    const FILE_SIZE: u64 = 36;
+
+    fn parse(body: &[u8]) -> Result<Ds64, WavError> {
+        if body.len() < 28 {
+            return Err(WavError::MalformedChunk(*b"ds64"));
+        }
+        let table_len = LittleEndian::read_u32(&body[24..28]) as usize;
+        let table = body[28..]
+            .chunks_exact(12)
+            .take(table_len)
+            .map(|e| (e[..4].try_into().unwrap(), LittleEndian::read_u64(&e[4..])))
+            .collect();
+        Ok(Ds64 {
+            riff_size: LittleEndian::read_u64(&body[0..8]),
+            data_size: LittleEndian::read_u64(&body[8..16]),
+            sample_count: LittleEndian::read_u64(&body[16..24]),
+            table,
+        })
+    }
+    /// Reads the chunk that has to follow the header of RF64 and BW64 files
+    fn read<R: Read + Seek>(r: &mut R) -> Result<Ds64, WavError> {
+        let mut iter = RiffChunks::new(r, 12, u64::MAX)?;
+        match iter.next().transpose()? {
+            Some(hdr) if &hdr.id == b"ds64" => Ds64::parse(&iter.read_body(&hdr)?),
+            _ => Err(WavError::MissingChunk(*b"ds64")),
+        }
+    }
+    /// Writes the chunk with the given id, a `JUNK` chunk of the same size saves room for it
+    fn write_to<W: Write>(&self, w: &mut W, id: &Fourcc) -> std::io::Result<()> {
+        w.write_all(id)?;
+        w.write_u32::<LittleEndian>(28 + 12 * self.table.len() as u32)?;
+        w.write_u64::<LittleEndian>(self.riff_size)?;
+        w.write_u64::<LittleEndian>(self.data_size)?;
+        w.write_u64::<LittleEndian>(self.sample_count)?;
+        w.write_u32::<LittleEndian>(self.table.len() as u32)?;
+        for (id, size) in &self.table {
+            w.write_all(id)?;
+            w.write_u64::<LittleEndian>(*size)?;
+        }
+        Ok(())
+    }
+}
 
 pub struct RiffHdr {
     id: Fourcc,
-    pub size: u32,
+    /// Written to the ds64 chunk when it doesn't fit in 32 bits
+    pub size: u64,
     block_type: Fourcc,
 }
 
@@ -24,24 +186,838 @@ pub struct FmtHdr {
     pub byte_rate: u32,
     pub block_align: u16,
     pub bits_per_sample: u16,
+    /// Present when `fmt_tag` is `WAVE_FORMAT_EXTENSIBLE`
+    pub extensible: Option<FmtExtensible>,
 }
 
-pub struct DataHdr {
+/// The 22 extra bytes of a `WAVE_FORMAT_EXTENSIBLE` fmt chunk
+#[derive(Debug, Clone, Copy)]
+pub struct FmtExtensible {
+    pub valid_bits_per_sample: u16,
+    pub channel_mask: ChannelMask,
+    pub sub_format: [u8; 16],
+}
+
+impl FmtExtensible {
+    pub fn new(fmt_tag: u16, valid_bits_per_sample: u16, channel_mask: ChannelMask) -> Self {
+        let mut sub_format = [0; 16];
+        sub_format[0..2].copy_from_slice(&fmt_tag.to_le_bytes());
+        sub_format[2..].copy_from_slice(&SUBTYPE_GUID_TAIL);
+        FmtExtensible {
+            valid_bits_per_sample,
+            channel_mask,
+            sub_format,
+        }
+    }
+    /// Format tag carried by the sub-format GUID
+    pub fn sub_format_tag(&self) -> u16 {
+        u16::from_le_bytes([self.sub_format[0], self.sub_format[1]])
+    }
+}
+
+/// Speaker positions of the channels in a file, in the order they are interleaved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelMask(pub u32);
+
+impl ChannelMask {
+    pub const FRONT_LEFT: ChannelMask = ChannelMask(0x1);
+    pub const FRONT_RIGHT: ChannelMask = ChannelMask(0x2);
+    pub const FRONT_CENTER: ChannelMask = ChannelMask(0x4);
+    pub const LOW_FREQUENCY: ChannelMask = ChannelMask(0x8);
+    pub const BACK_LEFT: ChannelMask = ChannelMask(0x10);
+    pub const BACK_RIGHT: ChannelMask = ChannelMask(0x20);
+    pub const FRONT_LEFT_OF_CENTER: ChannelMask = ChannelMask(0x40);
+    pub const FRONT_RIGHT_OF_CENTER: ChannelMask = ChannelMask(0x80);
+    pub const BACK_CENTER: ChannelMask = ChannelMask(0x100);
+    pub const SIDE_LEFT: ChannelMask = ChannelMask(0x200);
+    pub const SIDE_RIGHT: ChannelMask = ChannelMask(0x400);
+    pub const TOP_CENTER: ChannelMask = ChannelMask(0x800);
+    pub const TOP_FRONT_LEFT: ChannelMask = ChannelMask(0x1000);
+    pub const TOP_FRONT_CENTER: ChannelMask = ChannelMask(0x2000);
+    pub const TOP_FRONT_RIGHT: ChannelMask = ChannelMask(0x4000);
+    pub const TOP_BACK_LEFT: ChannelMask = ChannelMask(0x8000);
+    pub const TOP_BACK_CENTER: ChannelMask = ChannelMask(0x10000);
+    pub const TOP_BACK_RIGHT: ChannelMask = ChannelMask(0x20000);
+
+    pub const MONO: ChannelMask = ChannelMask::FRONT_CENTER;
+    pub const STEREO: ChannelMask = ChannelMask(0x3);
+    pub const QUAD: ChannelMask = ChannelMask(0x33);
+    pub const SURROUND_5_1: ChannelMask = ChannelMask(0x60F);
+    pub const SURROUND_7_1: ChannelMask = ChannelMask(0x63F);
+
+    /// Usual layout for a number of channels, or no positions if there isn't one
+    pub fn for_channels(channels: u16) -> ChannelMask {
+        match channels {
+            1 => ChannelMask::MONO,
+            2 => ChannelMask::STEREO,
+            3 => ChannelMask(0x7),
+            4 => ChannelMask::QUAD,
+            5 => ChannelMask(0x37),
+            6 => ChannelMask::SURROUND_5_1,
+            8 => ChannelMask::SURROUND_7_1,
+            _ => ChannelMask(0),
+        }
+    }
+    pub fn contains(self, other: ChannelMask) -> bool {
+        self.0 & other.0 == other.0
+    }
+    pub fn count(self) -> u32 {
+        self.0.count_ones()
+    }
+}
+
+impl BitOr for ChannelMask {
+    type Output = ChannelMask;
+    fn bitor(self, rhs: Self) -> Self::Output {
+        ChannelMask(self.0 | rhs.0)
+    }
+}
+
+/// Required by every format other than integer PCM
+pub struct FactHdr {
     id: Fourcc,
     pub size: u32,
+    /// Length of the data in sample frames, `u32::MAX` if it doesn't fit
+    pub sample_length: u32,
+}
+
+/// How a `WavFile` stores its samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Integer or float samples, as they are in the buffer
+    Pcm,
+    /// G.711 A-law, 8 bits a sample
+    ALaw,
+    /// G.711 mu-law, 8 bits a sample
+    MuLaw,
+    /// IMA ADPCM, 4 bits a sample in blocks of `block_align` bytes
+    ImaAdpcm { block_align: u16 },
+}
+
+impl Encoding {
+    /// IMA ADPCM with the block size other encoders use for the sample rate
+    pub fn ima_adpcm(sample_rate: u32, channels: u16) -> Encoding {
+        let per_channel = match sample_rate {
+            0..=11025 => 256,
+            11026..=22050 => 512,
+            _ => 1024,
+        };
+        Encoding::ImaAdpcm {
+            block_align: per_channel * channels,
+        }
+    }
+}
+
+pub struct DataHdr {
+    id: Fourcc,
+    /// Written to the ds64 chunk when it doesn't fit in 32 bits
+    pub size: u64,
 }
 
 pub struct WavHdr {
     pub riff_hdr: RiffHdr,
     pub fmt_ck: FmtHdr,
+    pub fact_ck: Option<FactHdr>,
     pub data_hdr: DataHdr,
 }
 
 pub struct WavFile {
     pub hdr: WavHdr,
-    pub data: Vec<BitDepth>,
+    pub data: SampleBuffer,
+    pub info: Info,
+    pub cues: Vec<CuePoint>,
+    pub sampler: Option<SamplerInfo>,
+    pub bext: Option<BroadcastExt>,
+    /// Contents of the `iXML` chunk, left as text
+    pub ixml: Option<String>,
+    /// Chunks the codec doesn't interpret, kept so they are written back unchanged
+    pub chunks: Vec<Chunk>,
+}
+
+/// Fields of a `LIST`/`INFO` chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum InfoTag {
+    /// INAM
+    Title,
+    /// IART
+    Artist,
+    /// IPRD, the album or product the file belongs to
+    Album,
+    /// ITRK
+    Track,
+    /// IGNR
+    Genre,
+    /// ICMT
+    Comment,
+    /// ICOP
+    Copyright,
+    /// ICRD, usually a date like 2024-01-31
+    CreationDate,
+    /// IENG
+    Engineer,
+    /// ISFT, the program that made the file
+    Software,
+    /// IKEY
+    Keywords,
+    /// ISBJ
+    Subject,
+    /// ISRC, the name of the person or organization that supplied the original
+    Source,
+    /// Any other sub-chunk, kept by its id
+    Other(Fourcc),
+}
+
+impl InfoTag {
+    pub fn from_fourcc(id: Fourcc) -> InfoTag {
+        match &id {
+            b"INAM" => InfoTag::Title,
+            b"IART" => InfoTag::Artist,
+            b"IPRD" => InfoTag::Album,
+            b"ITRK" => InfoTag::Track,
+            b"IGNR" => InfoTag::Genre,
+            b"ICMT" => InfoTag::Comment,
+            b"ICOP" => InfoTag::Copyright,
+            b"ICRD" => InfoTag::CreationDate,
+            b"IENG" => InfoTag::Engineer,
+            b"ISFT" => InfoTag::Software,
+            b"IKEY" => InfoTag::Keywords,
+            b"ISBJ" => InfoTag::Subject,
+            b"ISRC" => InfoTag::Source,
+            _ => InfoTag::Other(id),
+        }
+    }
+    pub fn fourcc(&self) -> Fourcc {
+        match self {
+            InfoTag::Title => *b"INAM",
+            InfoTag::Artist => *b"IART",
+            InfoTag::Album => *b"IPRD",
+            InfoTag::Track => *b"ITRK",
+            InfoTag::Genre => *b"IGNR",
+            InfoTag::Comment => *b"ICMT",
+            InfoTag::Copyright => *b"ICOP",
+            InfoTag::CreationDate => *b"ICRD",
+            InfoTag::Engineer => *b"IENG",
+            InfoTag::Software => *b"ISFT",
+            InfoTag::Keywords => *b"IKEY",
+            InfoTag::Subject => *b"ISBJ",
+            InfoTag::Source => *b"ISRC",
+            InfoTag::Other(id) => *id,
+        }
+    }
 }
 
+/// Text metadata of a file, e.g. `info.insert(InfoTag::Title, "Stem 1".into())`
+pub type Info = BTreeMap<InfoTag, String>;
+
+/// Reads the sub-chunks of a `LIST` chunk body that starts with `INFO`. Values are
+/// NUL-terminated text.
+fn parse_info(body: &[u8], info: &mut Info) {
+    list_sub_chunks(body, |id, value| {
+        info.insert(InfoTag::from_fourcc(id), zstr(value));
+    });
+}
+
+/// Builds the `LIST`/`INFO` chunk for `info`, `None` when there is nothing to write
+fn info_chunk(info: &Info) -> Option<Chunk> {
+    if info.is_empty() {
+        return None;
+    }
+    let mut data = b"INFO".to_vec();
+    for (tag, value) in info {
+        push_sub_chunk(&mut data, &tag.fourcc(), &[value.as_bytes(), &[0]].concat());
+    }
+    Some(Chunk { id: *b"LIST", data })
+}
+
+/// A marker from the `cue ` chunk, with its text from the `LIST`/`adtl` chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CuePoint {
+    pub id: u32,
+    /// Frame the marker is at
+    pub position: u32,
+    /// From a `labl` sub-chunk
+    pub label: Option<String>,
+    /// From a `note` sub-chunk, a longer comment
+    pub note: Option<String>,
+    /// From an `ltxt` sub-chunk, when the marker starts a region
+    pub region: Option<CueRegion>,
+}
+
+impl CuePoint {
+    pub fn new(id: u32, position: u32) -> CuePoint {
+        CuePoint {
+            id,
+            position,
+            label: None,
+            note: None,
+            region: None,
+        }
+    }
+}
+
+/// Stretch of audio starting at a cue point
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CueRegion {
+    /// Length in frames
+    pub length: u32,
+    /// What the region is for, usually `rgn `
+    pub purpose: Fourcc,
+    pub text: String,
+}
+
+/// Text of a sub-chunk, up to the first NUL
+fn zstr(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Appends a sub-chunk to the body of a `LIST` chunk, padded to an even size
+fn push_sub_chunk(data: &mut Vec<u8>, id: &Fourcc, body: &[u8]) {
+    data.extend_from_slice(id);
+    data.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    data.extend_from_slice(body);
+    if !body.len().is_multiple_of(2) {
+        data.push(0);
+    }
+}
+
+/// Calls `f` with the id and body of each sub-chunk of a `LIST` chunk body. A sub-chunk cut
+/// short by the end of the list ends it.
+fn list_sub_chunks(body: &[u8], mut f: impl FnMut(Fourcc, &[u8])) {
+    let mut rest = body.get(4..).unwrap_or(&[]);
+    while rest.len() >= 8 {
+        let id: Fourcc = rest[..4].try_into().unwrap();
+        let size = LittleEndian::read_u32(&rest[4..8]) as usize;
+        if rest.len() - 8 < size {
+            break;
+        }
+        f(id, &rest[8..8 + size]);
+        rest = &rest[(8 + size + size % 2).min(rest.len())..];
+    }
+}
+
+/// Reads the points of a `cue ` chunk. Points cut short by the end of the chunk are dropped.
+fn parse_cue(body: &[u8]) -> Vec<CuePoint> {
+    let count = body.get(..4).map_or(0, LittleEndian::read_u32) as usize;
+    body[body.len().min(4)..]
+        .chunks_exact(24)
+        .take(count)
+        // Only the offset into the data chunk matters for PCM, the rest is for compressed files
+        .map(|p| {
+            CuePoint::new(
+                LittleEndian::read_u32(&p[..4]),
+                LittleEndian::read_u32(&p[20..]),
+            )
+        })
+        .collect()
+}
+
+/// Attaches the texts of a `LIST`/`adtl` chunk body to the cue points they refer to
+fn parse_adtl(body: &[u8], cues: &mut [CuePoint]) {
+    list_sub_chunks(body, |id, data| {
+        if data.len() < 4 {
+            return;
+        }
+        let cue_id = LittleEndian::read_u32(&data[..4]);
+        let Some(cue) = cues.iter_mut().find(|c| c.id == cue_id) else {
+            return;
+        };
+        match &id {
+            b"labl" => cue.label = Some(zstr(&data[4..])),
+            b"note" => cue.note = Some(zstr(&data[4..])),
+            // Country, language, dialect and code page are skipped
+            b"ltxt" if data.len() >= 20 => {
+                cue.region = Some(CueRegion {
+                    length: LittleEndian::read_u32(&data[4..8]),
+                    purpose: data[8..12].try_into().unwrap(),
+                    text: zstr(&data[20..]),
+                })
+            }
+            _ => (),
+        }
+    });
+}
+
+/// Builds the `cue ` and `LIST`/`adtl` chunks for `cues`
+fn cue_chunks(cues: &[CuePoint]) -> Vec<Chunk> {
+    if cues.is_empty() {
+        return Vec::new();
+    }
+    let mut cue = (cues.len() as u32).to_le_bytes().to_vec();
+    let mut adtl = b"adtl".to_vec();
+    for c in cues {
+        for field in [c.id, c.position] {
+            cue.extend_from_slice(&field.to_le_bytes());
+        }
+        cue.extend_from_slice(b"data");
+        cue.extend_from_slice(&[0; 8]);
+        cue.extend_from_slice(&c.position.to_le_bytes());
+
+        let text = |id: u32, s: &str| [&id.to_le_bytes()[..], s.as_bytes(), &[0]].concat();
+        if let Some(label) = &c.label {
+            push_sub_chunk(&mut adtl, b"labl", &text(c.id, label));
+        }
+        if let Some(note) = &c.note {
+            push_sub_chunk(&mut adtl, b"note", &text(c.id, note));
+        }
+        if let Some(region) = &c.region {
+            let mut body = c.id.to_le_bytes().to_vec();
+            body.extend_from_slice(&region.length.to_le_bytes());
+            body.extend_from_slice(&region.purpose);
+            body.extend_from_slice(&[0; 8]);
+            body.extend_from_slice(region.text.as_bytes());
+            body.push(0);
+            push_sub_chunk(&mut adtl, b"ltxt", &body);
+        }
+    }
+    let mut chunks = vec![Chunk {
+        id: *b"cue ",
+        data: cue,
+    }];
+    if adtl.len() > 4 {
+        chunks.push(Chunk {
+            id: *b"LIST",
+            data: adtl,
+        });
+    }
+    chunks
+}
+
+/// Contents of the Broadcast Wave `bext` chunk (EBU Tech 3285)
+#[derive(Debug, Clone, PartialEq)]
+pub struct BroadcastExt {
+    /// Up to 256 characters
+    pub description: String,
+    /// Name of the organization or program that made the file, up to 32 characters
+    pub originator: String,
+    /// Identifier given by the originator, up to 32 characters
+    pub originator_reference: String,
+    /// yyyy-mm-dd
+    pub origination_date: String,
+    /// hh:mm:ss
+    pub origination_time: String,
+    /// Frames since midnight at the first sample, which places the file on a timeline
+    pub time_reference: u64,
+    pub version: u16,
+    /// SMPTE 330M material id, zeroes if unused
+    pub umid: [u8; 64],
+    /// Integrated loudness in LUFS. Loudness fields are only in version 2 and up.
+    pub loudness_value: Option<f32>,
+    /// In LU
+    pub loudness_range: Option<f32>,
+    /// In dBTP
+    pub max_true_peak_level: Option<f32>,
+    /// In LUFS
+    pub max_momentary_loudness: Option<f32>,
+    /// In LUFS
+    pub max_short_term_loudness: Option<f32>,
+    /// Lines describing the processing the audio went through, e.g. "A=PCM,F=48000,W=24,M=stereo"
+    pub coding_history: String,
+}
+
+/// Size of the fixed part of the `bext` chunk, the coding history follows it
+const BEXT_SIZE: usize = 602;
+/// Loudness field that holds no value
+const LOUDNESS_UNSET: i16 = 0x7fff;
+
+impl Default for BroadcastExt {
+    fn default() -> Self {
+        BroadcastExt {
+            description: String::new(),
+            originator: String::new(),
+            originator_reference: String::new(),
+            origination_date: String::new(),
+            origination_time: String::new(),
+            time_reference: 0,
+            version: 2,
+            umid: [0; 64],
+            loudness_value: None,
+            loudness_range: None,
+            max_true_peak_level: None,
+            max_momentary_loudness: None,
+            max_short_term_loudness: None,
+            coding_history: String::new(),
+        }
+    }
+}
+
+impl BroadcastExt {
+    /// Stamps the current UTC date and time, with the time reference of a recording at
+    /// `sample_rate` that starts now
+    pub fn now(sample_rate: u32) -> BroadcastExt {
+        let since_epoch = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        BroadcastExt::at(since_epoch, sample_rate)
+    }
+    /// Stamps the UTC date and time `since_epoch` after 1970-01-01
+    pub fn at(since_epoch: std::time::Duration, sample_rate: u32) -> BroadcastExt {
+        let secs = since_epoch.as_secs();
+        let (year, month, day) = civil_date(secs / 86400);
+        let of_day = secs % 86400;
+        let since_midnight = of_day as f64 + since_epoch.subsec_nanos() as f64 / 1e9;
+        BroadcastExt {
+            origination_date: format!("{year:04}-{month:02}-{day:02}"),
+            origination_time: format!(
+                "{:02}:{:02}:{:02}",
+                of_day / 3600,
+                of_day / 60 % 60,
+                of_day % 60
+            ),
+            time_reference: (since_midnight * sample_rate as f64) as u64,
+            ..Default::default()
+        }
+    }
+
+    fn parse(body: &[u8]) -> Result<BroadcastExt, WavError> {
+        if body.len() < BEXT_SIZE {
+            return Err(WavError::MalformedChunk(*b"bext"));
+        }
+        let version = LittleEndian::read_u16(&body[346..348]);
+        let loudness = |i: usize| {
+            let v = LittleEndian::read_i16(&body[412 + i * 2..414 + i * 2]);
+            (version >= 2 && v != LOUDNESS_UNSET).then_some(v as f32 / 100.0)
+        };
+        Ok(BroadcastExt {
+            description: zstr(&body[..256]),
+            originator: zstr(&body[256..288]),
+            originator_reference: zstr(&body[288..320]),
+            origination_date: zstr(&body[320..330]),
+            origination_time: zstr(&body[330..338]),
+            time_reference: LittleEndian::read_u64(&body[338..346]),
+            version,
+            umid: body[348..412].try_into().unwrap(),
+            loudness_value: loudness(0),
+            loudness_range: loudness(1),
+            max_true_peak_level: loudness(2),
+            max_momentary_loudness: loudness(3),
+            max_short_term_loudness: loudness(4),
+            coding_history: zstr(&body[BEXT_SIZE..]),
+        })
+    }
+    pub fn to_chunk(&self) -> Chunk {
+        // Text fields are NUL padded and cut to fit
+        let text = |data: &mut Vec<u8>, s: &str, len: usize| {
+            let bytes = &s.as_bytes()[..s.len().min(len)];
+            data.extend_from_slice(bytes);
+            data.resize(data.len() + len - bytes.len(), 0);
+        };
+        let mut data = Vec::with_capacity(BEXT_SIZE + self.coding_history.len());
+        text(&mut data, &self.description, 256);
+        text(&mut data, &self.originator, 32);
+        text(&mut data, &self.originator_reference, 32);
+        text(&mut data, &self.origination_date, 10);
+        text(&mut data, &self.origination_time, 8);
+        data.extend_from_slice(&self.time_reference.to_le_bytes());
+        data.extend_from_slice(&self.version.to_le_bytes());
+        data.extend_from_slice(&self.umid);
+        for v in [
+            self.loudness_value,
+            self.loudness_range,
+            self.max_true_peak_level,
+            self.max_momentary_loudness,
+            self.max_short_term_loudness,
+        ] {
+            let v = v.map_or(LOUDNESS_UNSET, |v| (v * 100.0).round() as i16);
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        data.resize(BEXT_SIZE, 0);
+        data.extend_from_slice(self.coding_history.as_bytes());
+        Chunk { id: *b"bext", data }
+    }
+}
+
+/// Year, month and day of a day counted from 1970-01-01
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    // Howard Hinnant's days_from_civil in reverse, with years starting in March
+    let days = days + 719468;
+    let era = days / 146097;
+    let doe = days % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+    (year, month, day)
+}
+
+/// How a sampler plays a loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopKind {
+    Forward,
+    /// Back and forth
+    Alternating,
+    Backward,
+    Other(u32),
+}
+
+/// A loop of the `smpl` chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampleLoop {
+    /// Cue point that marks the loop, 0 if none
+    pub cue_id: u32,
+    pub kind: LoopKind,
+    /// First frame of the loop
+    pub start: u32,
+    /// Last frame of the loop, which is played
+    pub end: u32,
+    /// Fraction of a frame to add to `end`, in units of 1/2^32
+    pub fraction: u32,
+    /// Times to play the loop, 0 loops for as long as the note is held
+    pub play_count: u32,
+}
+
+impl SampleLoop {
+    /// Sustain loops repeat until the note is released
+    pub fn is_sustain(&self) -> bool {
+        self.play_count == 0
+    }
+}
+
+/// Contents of the `smpl` chunk, which tells samplers how to play the file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SamplerInfo {
+    /// MMA manufacturer code, 0 for none
+    pub manufacturer: u32,
+    pub product: u32,
+    /// Length of a frame in nanoseconds
+    pub sample_period: u32,
+    /// MIDI note that plays the file at its recorded pitch, 69 is A4
+    pub root_note: u32,
+    /// Semitones above `root_note` the file is tuned, in units of 1/2^32
+    pub fine_tune: u32,
+    pub smpte_format: u32,
+    pub smpte_offset: u32,
+    pub loops: Vec<SampleLoop>,
+    /// Sampler specific data that follows the loops
+    pub sampler_data: Vec<u8>,
+}
+
+impl SamplerInfo {
+    /// Playback settings for a file at `sample_rate` whose pitch is the note `root`, e.g. "C4".
+    /// None if `root` isn't a note.
+    pub fn new(sample_rate: u32, root: &str) -> Option<SamplerInfo> {
+        let mut info = SamplerInfo {
+            manufacturer: 0,
+            product: 0,
+            sample_period: (1e9 / sample_rate as f64).round() as u32,
+            root_note: 69,
+            fine_tune: 0,
+            smpte_format: 0,
+            smpte_offset: 0,
+            loops: Vec::new(),
+            sampler_data: Vec::new(),
+        };
+        info.set_root_note(root)?;
+        Some(info)
+    }
+    /// Frequency the file plays at, with the fine tuning
+    pub fn root_freq(&self) -> f64 {
+        let semitones = self.root_note as f64 - 69.0 + self.fine_tune as f64 / 2f64.powi(32);
+        440.0 * 2f64.powf(semitones / 12.0)
+    }
+    /// Name of the closest note, as given by `notation::freq_to_note`
+    pub fn root_note_name(&self) -> String {
+        notation::freq_to_note(self.root_freq())
+    }
+    /// Takes a note name as understood by `notation::parse_note`, leaving the root as it was
+    /// if it isn't one
+    pub fn set_root_note(&mut self, note: &str) -> Option<()> {
+        self.set_root_freq(notation::parse_note(note)?)
+    }
+    /// Sets the root note and the fine tuning that best describe `freq`, or leaves them as they
+    /// were if it's outside the MIDI notes
+    pub fn set_root_freq(&mut self, freq: f64) -> Option<()> {
+        let semitones = 69.0 + 12.0 * (freq / 440.0).log2();
+        // Also catches frequencies that aren't positive, whose log is NaN or -inf
+        if !(0.0..128.0).contains(&semitones) {
+            return None;
+        }
+        let note = semitones.floor();
+        let fine_tune = ((semitones - note) * 2f64.powi(32)).round();
+        // A tuning that rounds up to a whole semitone is the next note
+        if fine_tune >= 2f64.powi(32) {
+            self.root_note = note as u32 + 1;
+            self.fine_tune = 0;
+        } else {
+            self.root_note = note as u32;
+            self.fine_tune = fine_tune as u32;
+        }
+        Some(())
+    }
+    /// The first loop that repeats until the note is released
+    pub fn sustain_loop(&self) -> Option<&SampleLoop> {
+        self.loops.iter().find(|l| l.is_sustain())
+    }
+
+    fn parse(body: &[u8]) -> Result<SamplerInfo, WavError> {
+        if body.len() < 36 {
+            return Err(WavError::MalformedChunk(*b"smpl"));
+        }
+        let field = |i: usize| LittleEndian::read_u32(&body[i * 4..i * 4 + 4]);
+        let loops_end = (36 + field(7) as usize * 24).min(body.len());
+        let loops = body[36..loops_end]
+            .chunks_exact(24)
+            .map(|l| {
+                let field = |i: usize| LittleEndian::read_u32(&l[i * 4..i * 4 + 4]);
+                SampleLoop {
+                    cue_id: field(0),
+                    kind: match field(1) {
+                        0 => LoopKind::Forward,
+                        1 => LoopKind::Alternating,
+                        2 => LoopKind::Backward,
+                        k => LoopKind::Other(k),
+                    },
+                    start: field(2),
+                    end: field(3),
+                    fraction: field(4),
+                    play_count: field(5),
+                }
+            })
+            .collect();
+        let data_end = (loops_end + field(8) as usize).min(body.len());
+        Ok(SamplerInfo {
+            manufacturer: field(0),
+            product: field(1),
+            sample_period: field(2),
+            root_note: field(3),
+            fine_tune: field(4),
+            smpte_format: field(5),
+            smpte_offset: field(6),
+            loops,
+            sampler_data: body[loops_end..data_end].to_vec(),
+        })
+    }
+    fn to_chunk(&self) -> Chunk {
+        let mut data = Vec::with_capacity(36 + self.loops.len() * 24 + self.sampler_data.len());
+        for field in [
+            self.manufacturer,
+            self.product,
+            self.sample_period,
+            self.root_note,
+            self.fine_tune,
+            self.smpte_format,
+            self.smpte_offset,
+            self.loops.len() as u32,
+            self.sampler_data.len() as u32,
+        ] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        for l in &self.loops {
+            let kind = match l.kind {
+                LoopKind::Forward => 0,
+                LoopKind::Alternating => 1,
+                LoopKind::Backward => 2,
+                LoopKind::Other(k) => k,
+            };
+            for field in [l.cue_id, kind, l.start, l.end, l.fraction, l.play_count] {
+                data.extend_from_slice(&field.to_le_bytes());
+            }
+        }
+        data.extend_from_slice(&self.sampler_data);
+        Chunk { id: *b"smpl", data }
+    }
+}
+
+/// A RIFF sub-chunk kept as raw bytes
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub id: Fourcc,
+    pub data: Vec<u8>,
+}
+
+impl Chunk {
+    /// Bytes taken in the file, counting the header and the pad byte
+    pub fn file_size(&self) -> u32 {
+        let size = self.data.len() as u32;
+        8 + size + size % 2
+    }
+}
+
+/// Header of a sub-chunk found while walking a RIFF stream
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkHdr {
+    pub id: Fourcc,
+    /// Taken from the ds64 chunk for chunks too big for their header
+    pub size: u64,
+    /// Position of the chunk body in the stream
+    pub offset: u64,
+}
+
+/// Iterates over the sub-chunks of a RIFF form, wherever they are and whatever their order.
+/// Bodies are skipped unless requested with `read_body`.
+pub struct RiffChunks<'a, R> {
+    reader: &'a mut R,
+    pos: u64,
+    end: u64,
+    /// 64 bit sizes from a ds64 chunk
+    sizes: Vec<(Fourcc, u64)>,
+}
+
+impl<'a, R: Read + Seek> RiffChunks<'a, R> {
+    /// Walks the chunks between `start` and `end`. `end` is clamped to the stream length so
+    /// truncated files don't send us past EOF.
+    pub fn new(reader: &'a mut R, start: u64, end: u64) -> std::io::Result<RiffChunks<'a, R>> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        Ok(RiffChunks {
+            reader,
+            pos: start,
+            end: end.min(len),
+            sizes: Vec::new(),
+        })
+    }
+    /// Takes the sizes of chunks whose header says `0xFFFFFFFF` from `ds64`
+    pub fn set_ds64(&mut self, ds64: &Ds64) {
+        self.sizes = ds64.table.clone();
+        self.sizes.push((*b"data", ds64.data_size));
+    }
+
+    /// Reads the body of a chunk returned by this iterator. Truncated chunks are cut at the end
+    /// of the stream.
+    pub fn read_body(&mut self, hdr: &ChunkHdr) -> std::io::Result<Vec<u8>> {
+        let mut body = vec![0; self.available(hdr) as usize];
+        self.reader.seek(SeekFrom::Start(hdr.offset))?;
+        self.reader.read_exact(&mut body)?;
+        Ok(body)
+    }
+
+    /// Bytes of the chunk body actually present in the stream
+    pub fn available(&self, hdr: &ChunkHdr) -> u64 {
+        self.end.saturating_sub(hdr.offset).min(hdr.size)
+    }
+
+    fn next_hdr(&mut self) -> std::io::Result<Option<ChunkHdr>> {
+        if self.pos.saturating_add(8) > self.end {
+            return Ok(None);
+        }
+        self.reader.seek(SeekFrom::Start(self.pos))?;
+        let mut id = [0; 4];
+        self.reader.read_exact(&mut id)?;
+        let size = match self.reader.read_u32::<LittleEndian>()? {
+            SIZE_IN_DS64 => self
+                .sizes
+                .iter()
+                .find(|(ck, _)| *ck == id)
+                .map_or(SIZE_IN_DS64 as u64, |&(_, size)| size),
+            size => size as u64,
+        };
+        let offset = self.pos + 8;
+        // Chunks are word aligned, odd sizes are followed by a pad byte. Sizes from ds64 can be
+        // anything, one past the end of the stream stops the walk.
+        self.pos = offset.saturating_add(size).saturating_add(size % 2);
+        Ok(Some(ChunkHdr { id, size, offset }))
+    }
+}
+
+impl<'a, R: Read + Seek> Iterator for RiffChunks<'a, R> {
+    type Item = std::io::Result<ChunkHdr>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_hdr().transpose()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct WavParams {
     pub sample_rate: u32,
     pub channels: u16,
@@ -49,178 +1025,1960 @@ pub struct WavParams {
 
 #[derive(Debug, Clone, Copy)]
 pub enum BitDepth {
+    /// 8 bit sample, centered on 0 like every other depth. WAV stores these unsigned with a 128
+    /// offset, which is added and removed by the codec.
     U8(i8),
     U16(i16),
+    /// 24 bit sample, sign extended. Stored packed in 3 bytes on disk.
+    U24(i32),
     U32(i32),
+    /// IEEE float sample, full scale is [-1.0, 1.0]
+    F32(f32),
+    F64(f64),
+}
+
+pub const U24_MAX: i32 = (1 << 23) - 1;
+
+impl BitDepth {
+    pub fn bits(&self) -> u16 {
+        match self {
+            BitDepth::U8(_) => 8,
+            BitDepth::U16(_) => 16,
+            BitDepth::U24(_) => 24,
+            BitDepth::U32(_) => 32,
+            BitDepth::F32(_) => 32,
+            BitDepth::F64(_) => 64,
+        }
+    }
+    pub fn is_float(&self) -> bool {
+        matches!(self, BitDepth::F32(_) | BitDepth::F64(_))
+    }
+    /// Format tag used in the `fmt ` chunk for this kind of sample
+    pub fn fmt_tag(&self) -> u16 {
+        if self.is_float() {
+            WAVE_FORMAT_IEEE_FLOAT
+        } else {
+            WAVE_FORMAT_PCM
+        }
+    }
+    /// The kind of sample that can hold both operands: floats win over integers,
+    /// otherwise the one with more bits
+    pub fn wider(self, other: BitDepth) -> BitDepth {
+        match (self, other) {
+            (BitDepth::F64(_), _) | (_, BitDepth::F64(_)) => BitDepth::F64(0.0),
+            (BitDepth::F32(_), _) | (_, BitDepth::F32(_)) => BitDepth::F32(0.0),
+            _ if other.bits() > self.bits() => other,
+            _ => self,
+        }
+    }
+    /// Sum clamped to full scale. Operands of different depths are converted by value to
+    /// the wider one first, so 0.5 of an 8 bit sample plus 0.25 of a 16 bit one is 0.75.
+    pub fn saturating_add(self, rhs: BitDepth) -> BitDepth {
+        let kind = self.wider(rhs);
+        match (convert::convert(&self, kind), convert::convert(&rhs, kind)) {
+            (BitDepth::U8(l), BitDepth::U8(r)) => BitDepth::U8(l.saturating_add(r)),
+            (BitDepth::U16(l), BitDepth::U16(r)) => BitDepth::U16(l.saturating_add(r)),
+            (BitDepth::U24(l), BitDepth::U24(r)) => {
+                BitDepth::U24((l + r).clamp(-U24_MAX - 1, U24_MAX))
+            }
+            (BitDepth::U32(l), BitDepth::U32(r)) => BitDepth::U32(l.saturating_add(r)),
+            (BitDepth::F32(l), BitDepth::F32(r)) => BitDepth::F32(l + r),
+            (BitDepth::F64(l), BitDepth::F64(r)) => BitDepth::F64(l + r),
+            _ => unreachable!("Both operands were converted to the same kind"),
+        }
+    }
+    /// Multiplies by `gain`, saturating integers
+    pub fn scale(self, gain: f64) -> BitDepth {
+        convert::from_f64(convert::to_f64(&self) * gain, self)
+    }
+    /// Adds `rhs` scaled by `gain`, e.g. a send or a fader on a mixer input
+    pub fn add_scaled(self, rhs: BitDepth, gain: f64) -> BitDepth {
+        let kind = self.wider(rhs);
+        let v = convert::to_f64(&self) + convert::to_f64(&rhs) * gain;
+        convert::from_f64(v, kind)
+    }
+    /// Writes the sample as it is stored in the data chunk
+    fn write_to<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        match *self {
+            BitDepth::U8(d) => w.write_u8(u8_to_wav(d)),
+            BitDepth::U16(d) => w.write_i16::<LittleEndian>(d),
+            BitDepth::U24(d) => w.write_i24::<LittleEndian>(d),
+            BitDepth::U32(d) => w.write_i32::<LittleEndian>(d),
+            BitDepth::F32(d) => w.write_f32::<LittleEndian>(d),
+            BitDepth::F64(d) => w.write_f64::<LittleEndian>(d),
+        }
+    }
+}
+
+/// Removes the 128 offset of unsigned 8 bit WAV samples
+pub fn u8_from_wav(b: u8) -> i8 {
+    (b ^ 0x80) as i8
+}
+
+/// Adds the 128 offset of unsigned 8 bit WAV samples
+pub fn u8_to_wav(s: i8) -> u8 {
+    s as u8 ^ 0x80
 }
 
+/// Saturating, see `BitDepth::saturating_add`
 impl Add for BitDepth {
     type Output = BitDepth;
     fn add(self, rhs: Self) -> Self::Output {
-        match self {
-            BitDepth::U8(l) => match rhs {
-                BitDepth::U8(r) => BitDepth::U8(l + r),
-                BitDepth::U16(r) => BitDepth::U16(l as i16 + r),
-                BitDepth::U32(r) => BitDepth::U32(l as i32 + r),
-            },
-            BitDepth::U16(l) => match rhs {
-                BitDepth::U8(r) => BitDepth::U16(l + r as i16),
-                BitDepth::U16(r) => BitDepth::U16(l + r),
-                BitDepth::U32(r) => BitDepth::U32(l as i32 + r),
-            },
-            BitDepth::U32(l) => match rhs {
-                BitDepth::U8(r) => BitDepth::U32(l + r as i32),
-                BitDepth::U16(r) => BitDepth::U32(l + r as i32),
-                BitDepth::U32(r) => BitDepth::U32(l + r),
-            },
-        }
+        self.saturating_add(rhs)
     }
 }
 
-impl WavFile {
-    pub fn new(params: WavParams, data: Vec<BitDepth>) -> WavFile {
-        let bit_depth = match data[0] {
-            BitDepth::U8(_) => 8,
-            BitDepth::U16(_) => 16,
-            BitDepth::U32(_) => 32,
+impl WavHdr {
+    /// Header for `samples` interleaved samples of the same kind as `bit_depth`. No channels,
+    /// or a frame or byte rate too big for its field, is a malformed `fmt ` chunk.
+    pub fn new(
+        params: &WavParams,
+        bit_depth: BitDepth,
+        samples: usize,
+    ) -> Result<WavHdr, WavError> {
+        if params.channels == 0 {
+            return Err(WavError::MalformedChunk(*b"fmt "));
+        }
+        let fmt_tag = bit_depth.fmt_tag();
+        let bit_depth = bit_depth.bits();
+        let block_align = params
+            .channels
+            .checked_mul(bit_depth / 8)
+            .ok_or(WavError::MalformedChunk(*b"fmt "))?;
+        let byte_rate = params
+            .sample_rate
+            .checked_mul(block_align as u32)
+            .ok_or(WavError::MalformedChunk(*b"fmt "))?;
+        let frames = samples as u64 / params.channels as u64;
+        // The data chunk size doesn't count the pad byte, the RIFF size does
+        let size = frames * block_align as u64;
+        // More than two channels or more than 16 bit integers can't be described by a plain fmt chunk
+        let extensible = params.channels > 2 || (fmt_tag == WAVE_FORMAT_PCM && bit_depth > 16);
+        // Non-PCM formats carry an (empty) extension size and a fact chunk
+        let fact_ck = FactHdr {
+            id: *b"fact",
+            size: 4,
+            sample_length: frames.min(u32::MAX as u64) as u32,
         };
-        println!("bit_depth: {}", bit_depth);
-        let mut size = bit_depth as u32 * data.len() as u32;
-        if size % 2 != 0 {
-            size += 1;
-        }
-        WavFile {
-            hdr: WavHdr {
-                riff_hdr: RiffHdr {
-                    id: *b"RIFF",
-                    size: 36 + size,
-                    block_type: *b"WAVE",
-                },
-                fmt_ck: FmtHdr {
-                    id: *b"fmt ",
-                    size: 16,
-                    fmt_tag: 1,
-                    channels: params.channels,
-                    sample_rate: params.sample_rate,
-                    byte_rate: params.sample_rate * params.channels as u32 * bit_depth as u32 / 8,
-                    block_align: params.channels * (bit_depth as u16),
-                    bits_per_sample: bit_depth as u16,
+        let (fmt_size, fact_ck) = match (extensible, fmt_tag) {
+            (true, WAVE_FORMAT_PCM) => (40, None),
+            (true, _) => (40, Some(fact_ck)),
+            (false, WAVE_FORMAT_PCM) => (16, None),
+            (false, _) => (18, Some(fact_ck)),
+        };
+        let extensible = match extensible {
+            true => Some(FmtExtensible::new(
+                fmt_tag,
+                bit_depth,
+                ChannelMask::for_channels(params.channels),
+            )),
+            false => None,
+        };
+        let fact_size = fact_ck.as_ref().map_or(0, |ck| 8 + ck.size);
+        Ok(WavHdr {
+            riff_hdr: RiffHdr {
+                id: *b"RIFF",
+                size: (20 + fmt_size + fact_size) as u64 + size + size % 2,
+                block_type: *b"WAVE",
+            },
+            fmt_ck: FmtHdr {
+                id: *b"fmt ",
+                size: fmt_size,
+                fmt_tag: match extensible {
+                    Some(_) => WAVE_FORMAT_EXTENSIBLE,
+                    None => fmt_tag,
                 },
-                data_hdr: DataHdr { id: *b"data", size },
+                channels: params.channels,
+                sample_rate: params.sample_rate,
+                byte_rate,
+                block_align,
+                bits_per_sample: bit_depth,
+                extensible,
             },
-            data: data,
-        }
+            fact_ck,
+            data_hdr: DataHdr { id: *b"data", size },
+        })
     }
-    pub fn write(self, path: &Path) -> std::io::Result<()> {
-        let mut f = File::create(path).expect("Unable to create file");
+    /// Header for `frames` frames stored with one of the compressed encodings, which always
+    /// have a fact chunk
+    fn encoded(params: &WavParams, encoding: Encoding, frames: u64) -> Result<WavHdr, WavError> {
+        let channels = params.channels;
+        let (fmt_tag, bits_per_sample, fmt_size, block_align, frames_per_block) = match encoding {
+            Encoding::ALaw => (WAVE_FORMAT_ALAW, 8, 18, channels, 1),
+            Encoding::MuLaw => (WAVE_FORMAT_MULAW, 8, 18, channels, 1),
+            Encoding::ImaAdpcm { block_align } => {
+                let frames_per_block = adpcm::samples_per_block(block_align, channels)
+                    .ok_or(WavError::MalformedChunk(*b"fmt "))?;
+                // Two extra bytes for the samples per block
+                (
+                    WAVE_FORMAT_IMA_ADPCM,
+                    4,
+                    20,
+                    block_align,
+                    frames_per_block as u64,
+                )
+            }
+            Encoding::Pcm => panic!("PCM headers are made by `WavHdr::new`"),
+        };
+        // Every block is written whole, the fact chunk has the real length
+        let size = frames.div_ceil(frames_per_block) * block_align as u64;
+        Ok(WavHdr {
+            riff_hdr: RiffHdr {
+                id: *b"RIFF",
+                size: (20 + fmt_size + 12) as u64 + size + size % 2,
+                block_type: *b"WAVE",
+            },
+            fmt_ck: FmtHdr {
+                id: *b"fmt ",
+                size: fmt_size,
+                fmt_tag,
+                channels,
+                sample_rate: params.sample_rate,
+                byte_rate: (params.sample_rate as u64 * block_align as u64 / frames_per_block)
+                    as u32,
+                block_align,
+                bits_per_sample,
+                extensible: None,
+            },
+            fact_ck: Some(FactHdr {
+                id: *b"fact",
+                size: 4,
+                sample_length: frames.min(u32::MAX as u64) as u32,
+            }),
+            data_hdr: DataHdr { id: *b"data", size },
+        })
+    }
+    /// Writes everything up to the start of the samples, with `chunks` before the data chunk.
+    /// Files too big for 32 bit sizes are written as RF64. `reserve_ds64` saves room for a ds64
+    /// chunk in smaller files, so a writer can switch to RF64 once they grow.
+    fn write_to<W: Write>(
+        &self,
+        w: &mut W,
+        chunks: &[Chunk],
+        reserve_ds64: bool,
+    ) -> std::io::Result<()> {
+        let riff_size =
+            self.riff_hdr.size + chunks.iter().map(|c| c.file_size() as u64).sum::<u64>();
+        let rf64 = riff_size + Ds64::FILE_SIZE > u32::MAX as u64;
+        let has_ds64 = rf64 || reserve_ds64;
+        let riff_size = riff_size + if has_ds64 { Ds64::FILE_SIZE } else { 0 };
         // RIFF header
-        f.write_all(&self.hdr.riff_hdr.id).unwrap();
-        f.write_all(&self.hdr.riff_hdr.size.to_le_bytes()).unwrap();
-        f.write_all(&self.hdr.riff_hdr.block_type).unwrap();
+        if rf64 {
+            w.write_all(b"RF64")?;
+            w.write_u32::<LittleEndian>(SIZE_IN_DS64)?;
+        } else {
+            w.write_all(&self.riff_hdr.id)?;
+            w.write_u32::<LittleEndian>(riff_size as u32)?;
+        }
+        w.write_all(&self.riff_hdr.block_type)?;
+        if has_ds64 {
+            let ds64 = Ds64 {
+                riff_size,
+                data_size: self.data_hdr.size,
+                sample_count: match &self.fact_ck {
+                    Some(fact_ck) if fact_ck.sample_length != u32::MAX => {
+                        fact_ck.sample_length as u64
+                    }
+                    _ => self.fmt_ck.frames_in(self.data_hdr.size),
+                },
+                table: Vec::new(),
+            };
+            ds64.write_to(w, if rf64 { b"ds64" } else { b"JUNK" })?;
+        }
         // fmt chunk
-        f.write_all(&self.hdr.fmt_ck.id).unwrap();
-        f.write_all(&self.hdr.fmt_ck.size.to_le_bytes()).unwrap();
-        f.write_all(&self.hdr.fmt_ck.fmt_tag.to_le_bytes()).unwrap();
-        f.write_all(&self.hdr.fmt_ck.channels.to_le_bytes())
-            .unwrap();
-        f.write_all(&self.hdr.fmt_ck.sample_rate.to_le_bytes())
-            .unwrap();
-        f.write_all(&self.hdr.fmt_ck.byte_rate.to_le_bytes())
-            .unwrap();
-        f.write_all(&self.hdr.fmt_ck.block_align.to_le_bytes())
-            .unwrap();
-        f.write_all(&self.hdr.fmt_ck.bits_per_sample.to_le_bytes())
-            .unwrap();
+        self.fmt_ck.write_to(w)?;
+        // fact chunk
+        if let Some(fact_ck) = &self.fact_ck {
+            w.write_all(&fact_ck.id)?;
+            w.write_u32::<LittleEndian>(fact_ck.size)?;
+            w.write_u32::<LittleEndian>(fact_ck.sample_length)?;
+        }
+        // Unknown chunks, as they were read
+        for ck in chunks {
+            w.write_all(&ck.id)?;
+            w.write_u32::<LittleEndian>(ck.data.len() as u32)?;
+            w.write_all(&ck.data)?;
+            if ck.data.len() % 2 != 0 {
+                w.write_all(&[0x00])?;
+            }
+        }
         // data chunk
-        f.write_all(&self.hdr.data_hdr.id).unwrap();
-        f.write_all(&self.hdr.data_hdr.size.to_le_bytes()).unwrap();
+        w.write_all(&self.data_hdr.id)?;
+        w.write_u32::<LittleEndian>(match rf64 {
+            true => SIZE_IN_DS64,
+            false => self.data_hdr.size as u32,
+        })
+    }
+}
+
+impl WavFile {
+    /// Takes interleaved samples
+    pub fn new(params: WavParams, data: Vec<BitDepth>) -> Result<WavFile, WavError> {
+        if params.channels == 0 {
+            return Err(WavError::MalformedChunk(*b"fmt "));
+        }
+        WavFile::from_buffer(SampleBuffer::from_interleaved(
+            params.sample_rate,
+            params.channels,
+            data,
+        ))
+    }
+    /// Empty buffers are written as 16 bit
+    pub fn from_buffer(data: SampleBuffer) -> Result<WavFile, WavError> {
+        let bit_depth = data.bit_depth().unwrap_or(BitDepth::U16(0));
+        let params = WavParams {
+            sample_rate: data.sample_rate,
+            channels: data.channels(),
+        };
+        Ok(WavFile {
+            hdr: WavHdr::new(&params, bit_depth, data.interleaved().len())?,
+            data,
+            info: Info::new(),
+            cues: Vec::new(),
+            sampler: None,
+            bext: None,
+            ixml: None,
+            chunks: Vec::new(),
+        })
+    }
+    /// Every sample must be of the same kind, which is what the header describes
+    pub fn write(self, path: &Path) -> Result<(), WavError> {
+        if let Some(first) = self.data.interleaved().first() {
+            let kind = std::mem::discriminant(first);
+            if self
+                .data
+                .interleaved()
+                .iter()
+                .any(|s| std::mem::discriminant(s) != kind)
+            {
+                return Err(WavError::SampleMismatch);
+            }
+        }
+        let mut f = BufWriter::new(File::create(path)?);
+        self.hdr.write_to(&mut f, &self.all_chunks(), false)?;
         // data
-        let pos_data_start = f.stream_position()?;
-        for d in self.data {
-            match d {
-                BitDepth::U8(d) => {
-                    f.write_all(&d.to_le_bytes()).unwrap();
-                }
-                BitDepth::U16(d) => {
-                    f.write_all(&d.to_le_bytes()).unwrap();
-                }
-                BitDepth::U32(d) => {
-                    f.write_all(&d.to_le_bytes()).unwrap();
+        let mut data_size = 0;
+        match self.encoding() {
+            Encoding::Pcm => {
+                for d in self.data.interleaved() {
+                    d.write_to(&mut f)?;
+                    data_size += d.bits() as usize / 8;
                 }
             }
+            _ => {
+                let data = self.hdr.fmt_ck.encode(self.data.interleaved())?;
+                f.write_all(&data)?;
+                data_size = data.len();
+            }
         }
-        let pos_end = f.stream_position()?;
         // Pad with zeroes to make the file size a multiple of 2
-        let chunk_size_data: u32 = (pos_end - pos_data_start) as u32;
-        if chunk_size_data % 2 != 0 {
+        if data_size % 2 != 0 {
             f.write_all(&[0x00])?;
         }
 
-        f.sync_all().unwrap();
+        f.into_inner().map_err(|e| e.into_error())?.sync_all()?;
         Ok(())
     }
-    pub fn read(path: &Path) -> Result<WavFile, Error> {
-        // Reads the whole file into an in memory vector
-        let file = match read(path) {
-            Ok(v) => v,
-            Err(e) => return Err(e),
-        };
+    /// The chunks to write before the data, metadata included
+    fn all_chunks(&self) -> Vec<Chunk> {
+        let mut chunks = Vec::new();
+        chunks.extend(self.bext.as_ref().map(BroadcastExt::to_chunk));
+        chunks.extend(self.chunks.iter().cloned());
+        chunks.extend(info_chunk(&self.info));
+        chunks.extend(cue_chunks(&self.cues));
+        chunks.extend(self.sampler.as_ref().map(SamplerInfo::to_chunk));
+        chunks.extend(self.ixml.as_ref().map(|xml| Chunk {
+            id: *b"iXML",
+            data: xml.as_bytes().to_vec(),
+        }));
+        chunks
+    }
+    pub fn read(path: &Path) -> Result<WavFile, WavError> {
+        let mut f = BufReader::new(File::open(path)?);
+        WavFile::read_from(&mut f)
+    }
+    pub fn read_from<R: Read + Seek>(r: &mut R) -> Result<WavFile, WavError> {
+        let mut reader = WavReader::new(r)?;
+        let size = reader.len();
+        let data = reader.read_block(size as usize)?;
+        let WavReader {
+            fmt_ck,
+            info,
+            cues,
+            sampler,
+            bext,
+            ixml,
+            chunks,
+            ..
+        } = reader;
 
-        let invalid_file_error =
-            Error::new(std::io::ErrorKind::InvalidInput, "Not a valid WAV file");
-        let header = match str::from_utf8(&file[0..4]) {
-            Ok(v) => v,
-            Err(e) => return Err(invalid_file_error),
+        let mut wav = WavFile::from_buffer(data)?;
+        match fmt_ck.encoding() {
+            Encoding::Pcm => (),
+            encoding => wav.set_encoding(encoding)?,
+        }
+        if let Some(ext) = fmt_ck.extensible {
+            wav.set_channel_mask(ext.channel_mask);
+            if let Some(wav_ext) = wav.hdr.fmt_ck.extensible.as_mut() {
+                wav_ext.valid_bits_per_sample = ext.valid_bits_per_sample;
+            }
+        }
+        wav.info = info;
+        wav.cues = cues;
+        wav.sampler = sampler;
+        wav.bext = bext;
+        wav.ixml = ixml;
+        wav.chunks = chunks;
+        Ok(wav)
+    }
+    /// Converts the samples to another depth, keeping the metadata of the file
+    pub fn convert(
+        self,
+        bit_depth: BitDepth,
+        quantizer: &mut Quantizer,
+    ) -> Result<WavFile, WavError> {
+        let mask = self.channel_mask();
+        let mut wav = WavFile::from_buffer(self.data.convert(bit_depth, quantizer))?;
+        if self.hdr.fmt_ck.extensible.is_some() {
+            wav.set_channel_mask(mask);
+        }
+        wav.info = self.info;
+        wav.cues = self.cues;
+        wav.sampler = self.sampler;
+        wav.bext = self.bext;
+        wav.ixml = self.ixml;
+        wav.chunks = self.chunks;
+        Ok(wav)
+    }
+    /// Reports the ways in which the header of a file is inconsistent, an empty list if it's fine
+    pub fn validate(path: &Path) -> Result<Vec<HeaderIssue>, WavError> {
+        Ok(WavReader::open(path)?.issues)
+    }
+    pub fn encoding(&self) -> Encoding {
+        self.hdr.fmt_ck.encoding()
+    }
+    /// Chooses how the samples are stored when the file is written. The compressed encodings
+    /// are lossy and hold 16 bit samples, anything else is converted on write. An IMA ADPCM
+    /// block size that can't hold a sample of every channel is a malformed `fmt ` chunk.
+    pub fn set_encoding(&mut self, encoding: Encoding) -> Result<(), WavError> {
+        let params = WavParams {
+            sample_rate: self.data.sample_rate,
+            channels: self.data.channels(),
         };
-
-        match header {
-            "RIFF" => (),
-            _ => return Err(invalid_file_error),
+        self.hdr = match encoding {
+            Encoding::Pcm => WavHdr::new(
+                &params,
+                self.data.bit_depth().unwrap_or(BitDepth::U16(0)),
+                self.data.interleaved().len(),
+            )?,
+            _ => WavHdr::encoded(&params, encoding, self.data.frames() as u64)?,
         };
+        Ok(())
+    }
+    /// Speaker positions of the channels. Files without an extensible header get the usual
+    /// layout for their channel count.
+    pub fn channel_mask(&self) -> ChannelMask {
+        match &self.hdr.fmt_ck.extensible {
+            Some(ext) => ext.channel_mask,
+            None => ChannelMask::for_channels(self.hdr.fmt_ck.channels),
+        }
+    }
+    /// Sets the speaker positions, switching the header to `WAVE_FORMAT_EXTENSIBLE` if needed
+    pub fn set_channel_mask(&mut self, mask: ChannelMask) {
+        let fmt_ck = &mut self.hdr.fmt_ck;
+        match fmt_ck.extensible.as_mut() {
+            Some(ext) => ext.channel_mask = mask,
+            None => {
+                fmt_ck.extensible = Some(FmtExtensible::new(
+                    fmt_ck.fmt_tag,
+                    fmt_ck.bits_per_sample,
+                    mask,
+                ));
+                fmt_ck.fmt_tag = WAVE_FORMAT_EXTENSIBLE;
+                self.hdr.riff_hdr.size += (40 - fmt_ck.size) as u64;
+                fmt_ck.size = 40;
+            }
+        }
+    }
+}
 
-        let channels = (&file[22..24]).read_u16::<LittleEndian>().unwrap();
-        let sample_size = (&file[32..36]).read_u16::<LittleEndian>().unwrap();
-        let sample_rate = (&file[24..28]).read_u32::<LittleEndian>().unwrap();
-        let size = (&file[40..44]).read_u32::<LittleEndian>().unwrap();
-        println!("File size: {size}, sample size: {sample_size}, sample size: {sample_rate}, {channels} channels");
-        // let samples = &file[44..];
-        let mut data = Vec::new();
+/// An inconsistency found in a WAV header. Readers work around these, but they are a sign of
+/// a broken writer or a damaged file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderIssue {
+    /// The RIFF size doesn't match the length of the file
+    RiffSize { header: u64, actual: u64 },
+    /// The data chunk claims more bytes than the file has
+    DataSize { header: u64, actual: u64 },
+    /// `block_align` isn't the size of a frame
+    BlockAlign { header: u16, expected: u16 },
+    /// `byte_rate` isn't `sample_rate * block_align`
+    ByteRate { header: u32, expected: u32 },
+    /// The data chunk ends in the middle of a frame
+    PartialFrame { data_size: u64, frame_size: u16 },
+    /// The fact chunk's sample length isn't the number of frames in the data chunk
+    FactLength { header: u32, frames: u64 },
+    /// A `bext` or `smpl` chunk too short to parse, kept with the unknown chunks instead
+    MalformedChunk(Fourcc),
+}
 
-        match sample_size {
-            8 => {
-                for s in 0..(size / sample_size as u32) {
-                    let start = (44 + s * 1) as usize;
-                    let end = start + 1;
-                    data.push(BitDepth::U8((&file[start..end]).read_i8().unwrap()));
+/// Reads a WAV file incrementally. The header is parsed up front and the samples are decoded
+/// as they are requested, so the whole file never needs to be in memory.
+pub struct WavReader<R> {
+    reader: R,
+    pub fmt_ck: FmtHdr,
+    /// Contents of every `LIST`/`INFO` chunk
+    pub info: Info,
+    /// Markers with their labels
+    pub cues: Vec<CuePoint>,
+    pub sampler: Option<SamplerInfo>,
+    pub bext: Option<BroadcastExt>,
+    pub ixml: Option<String>,
+    /// Chunks the codec doesn't interpret, from anywhere in the file
+    pub chunks: Vec<Chunk>,
+    /// Ways in which the header disagrees with itself or the file
+    pub issues: Vec<HeaderIssue>,
+    data_start: u64,
+    data_size: u64,
+    /// Bytes in a block, the smallest unit that can be decoded on its own
+    block_size: usize,
+    /// Frames in a block, 1 for everything but ADPCM
+    block_frames: usize,
+    frames: u64,
+    pos: u64,
+}
+
+impl WavReader<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self, WavError> {
+        WavReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> WavReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, WavError> {
+        let (form, riff_size) = read_riff_hdr(&mut reader)?;
+        let ds64 = match &form {
+            b"RIFF" => None,
+            _ => Some(Ds64::read(&mut reader)?),
+        };
+        let riff_size = match &ds64 {
+            Some(ds64) if riff_size == SIZE_IN_DS64 => ds64.riff_size,
+            _ => riff_size as u64,
+        };
+
+        let mut fmt_ck = None;
+        let mut fact_length = None;
+        let mut data = None;
+        let mut info = Info::new();
+        let mut cues = Vec::new();
+        let mut adtl = Vec::new();
+        let mut sampler = None;
+        let mut bext = None;
+        let mut ixml = None;
+        let mut chunks = Vec::new();
+        let mut iter = RiffChunks::new(&mut reader, 12, riff_size.saturating_add(8))?;
+        if let Some(ds64) = &ds64 {
+            iter.set_ds64(ds64);
+        }
+        let mut issues = Vec::new();
+        while let Some(hdr) = iter.next() {
+            let hdr = hdr?;
+            match &hdr.id {
+                // Only remember where the samples are
+                b"data" => {
+                    let available = iter.available(&hdr);
+                    if available < hdr.size {
+                        issues.push(HeaderIssue::DataSize {
+                            header: hdr.size,
+                            actual: available,
+                        });
+                    }
+                    data = Some((hdr.offset, available));
                 }
-            }
-            16 => {
-                for s in 0..(size / sample_size as u32) {
-                    let start = (44 + s * 2) as usize;
-                    let end = start + 2;
-                    data.push(BitDepth::U16(
-                        (&file[start..end]).read_i16::<LittleEndian>().unwrap(),
-                    ))
+                b"fmt " => fmt_ck = Some(FmtHdr::parse(&iter.read_body(&hdr)?)?),
+                // Rebuilt on write, along with the RF64 header if needed
+                b"ds64" => (),
+                // Rebuilt from the data on write
+                b"fact" => {
+                    let body = iter.read_body(&hdr)?;
+                    fact_length = (&body[..]).read_u32::<LittleEndian>().ok();
                 }
-            }
-            32 => {
-                for s in 0..(size / sample_size as u32) {
-                    let start = (44 + s * 4) as usize;
-                    let end = start + 4;
-                    data.push(BitDepth::U32(
-                        (&file[start..end]).read_i32::<LittleEndian>().unwrap(),
-                    ))
+                b"LIST" => {
+                    let body = iter.read_body(&hdr)?;
+                    match body.get(..4) {
+                        Some(b"INFO") => parse_info(&body, &mut info),
+                        // Applied once all the cue points are known
+                        Some(b"adtl") => adtl.push(body),
+                        _ => chunks.push(Chunk {
+                            id: hdr.id,
+                            data: body,
+                        }),
+                    }
                 }
+                b"cue " => cues.extend(parse_cue(&iter.read_body(&hdr)?)),
+                // A damaged smpl or bext chunk shouldn't cost the audio
+                b"smpl" | b"bext" => {
+                    let body = iter.read_body(&hdr)?;
+                    let parsed = match &hdr.id {
+                        b"smpl" => SamplerInfo::parse(&body).map(|s| sampler = Some(s)),
+                        _ => BroadcastExt::parse(&body).map(|b| bext = Some(b)),
+                    };
+                    if parsed.is_err() {
+                        issues.push(HeaderIssue::MalformedChunk(hdr.id));
+                        chunks.push(Chunk {
+                            id: hdr.id,
+                            data: body,
+                        });
+                    }
+                }
+                b"iXML" => ixml = Some(zstr(&iter.read_body(&hdr)?)),
+                _ => chunks.push(Chunk {
+                    id: hdr.id,
+                    data: iter.read_body(&hdr)?,
+                }),
+            }
+        }
+        for body in adtl {
+            parse_adtl(&body, &mut cues);
+        }
+        let fmt_ck = fmt_ck.ok_or(WavError::MissingChunk(*b"fmt "))?;
+        let (data_start, data_size) = data.ok_or(WavError::MissingChunk(*b"data"))?;
+        let (block_size, block_frames) = fmt_ck.block_layout()?;
+        // Compressed formats pad the last block, the fact chunk tells how much of it is used
+        let frames = match fact_length {
+            Some(fact_length) if block_frames > 1 => {
+                (fact_length as u64).min(fmt_ck.frames_in(data_size))
             }
-            _ => return Err(invalid_file_error),
+            _ => fmt_ck.frames_in(data_size),
         };
 
-        return Ok(WavFile::new(
-            WavParams {
-                channels,
-                sample_rate,
+        let len = reader.seek(SeekFrom::End(0))?;
+        if riff_size.checked_add(8) != Some(len) {
+            issues.push(HeaderIssue::RiffSize {
+                header: riff_size,
+                actual: len - 8,
+            });
+        }
+        if fmt_ck.block_align as usize != block_size {
+            issues.push(HeaderIssue::BlockAlign {
+                header: fmt_ck.block_align,
+                expected: block_size as u16,
+            });
+        }
+        let byte_rate =
+            (fmt_ck.sample_rate as u64 * block_size as u64 / block_frames as u64) as u32;
+        if fmt_ck.byte_rate != byte_rate {
+            issues.push(HeaderIssue::ByteRate {
+                header: fmt_ck.byte_rate,
+                expected: byte_rate,
+            });
+        }
+        // A short last block is fine for ADPCM
+        if block_frames == 1 && data_size % block_size as u64 != 0 {
+            issues.push(HeaderIssue::PartialFrame {
+                data_size,
+                frame_size: block_size as u16,
+            });
+        }
+        // RF64 files count the frames in the ds64 chunk instead
+        let fact_length = fact_length.filter(|&l| !(ds64.is_some() && l == SIZE_IN_DS64));
+        if let Some(fact_length) = fact_length.filter(|&l| l as u64 != frames) {
+            issues.push(HeaderIssue::FactLength {
+                header: fact_length,
+                frames,
+            });
+        }
+
+        reader.seek(SeekFrom::Start(data_start))?;
+        Ok(WavReader {
+            reader,
+            fmt_ck,
+            info,
+            cues,
+            sampler,
+            bext,
+            ixml,
+            chunks,
+            issues,
+            data_start,
+            data_size,
+            block_size,
+            block_frames,
+            frames,
+            pos: 0,
+        })
+    }
+    pub fn sample_rate(&self) -> u32 {
+        self.fmt_ck.sample_rate
+    }
+    pub fn channels(&self) -> u16 {
+        self.fmt_ck.channels
+    }
+    /// Length of the file in frames
+    pub fn len(&self) -> u64 {
+        self.frames
+    }
+    pub fn is_empty(&self) -> bool {
+        self.frames == 0
+    }
+    /// Frame that the next read starts at
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+    /// Moves to a frame, reads continue from there
+    pub fn seek(&mut self, frame: u64) -> Result<(), WavError> {
+        if frame > self.frames {
+            return Err(WavError::OutOfRange);
+        }
+        // Blocks of compressed formats are found when they are read
+        if self.block_frames == 1 {
+            self.reader.seek(SeekFrom::Start(
+                self.data_start + frame * self.block_size as u64,
+            ))?;
+        }
+        self.pos = frame;
+        Ok(())
+    }
+    /// Reads up to `frames` frames. The block is shorter at the end of the data, and empty once
+    /// it is reached.
+    pub fn read_block(&mut self, frames: usize) -> Result<SampleBuffer, WavError> {
+        let frames = (frames as u64).min(self.frames - self.pos);
+        let samples = if self.block_frames == 1 {
+            let mut bytes = vec![0; frames as usize * self.block_size];
+            self.reader.read_exact(&mut bytes)?;
+            self.fmt_ck.decode(&bytes)?
+        } else {
+            self.read_blocks(frames)?
+        };
+        self.pos += frames;
+        Ok(SampleBuffer::from_interleaved(
+            self.sample_rate(),
+            self.channels(),
+            samples,
+        ))
+    }
+    /// Decodes the blocks that hold the next `frames` frames, for formats with more than one
+    /// frame in a block
+    fn read_blocks(&mut self, frames: u64) -> Result<Vec<BitDepth>, WavError> {
+        if frames == 0 {
+            return Ok(Vec::new());
+        }
+        let block_frames = self.block_frames as u64;
+        let first = self.pos / block_frames;
+        let start = first * self.block_size as u64;
+        let end = (self.pos + frames).div_ceil(block_frames) * self.block_size as u64;
+        self.reader.seek(SeekFrom::Start(self.data_start + start))?;
+        let mut bytes = vec![0; (end.min(self.data_size) - start) as usize];
+        self.reader.read_exact(&mut bytes)?;
+        let channels = self.channels() as usize;
+        let skip = (self.pos - first * block_frames) as usize * channels;
+        Ok(self
+            .fmt_ck
+            .decode(&bytes)?
+            .into_iter()
+            .skip(skip)
+            .take(frames as usize * channels)
+            .collect())
+    }
+    /// Reads the samples of the next frame, one for each channel
+    pub fn read_frame(&mut self) -> Result<Option<Vec<BitDepth>>, WavError> {
+        let frame = self.read_block(1)?;
+        Ok(if frame.is_empty() {
+            None
+        } else {
+            Some(frame.into_interleaved())
+        })
+    }
+    /// Iterates over the remaining data in blocks of `frames` frames
+    pub fn blocks(&mut self, frames: usize) -> Blocks<'_, R> {
+        Blocks {
+            reader: self,
+            frames,
+        }
+    }
+}
+
+pub struct Blocks<'a, R> {
+    reader: &'a mut WavReader<R>,
+    frames: usize,
+}
+
+impl<'a, R: Read + Seek> Iterator for Blocks<'a, R> {
+    type Item = Result<SampleBuffer, WavError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.reader.read_block(self.frames) {
+            Ok(block) if block.is_empty() => None,
+            block => Some(block),
+        }
+    }
+}
+
+/// Writes a WAV file incrementally. The sizes in the header are patched when the writer is
+/// flushed, finalized or dropped, so samples can be written as they arrive.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    bit_depth: BitDepth,
+    channels: u16,
+    fields: SizeFields,
+    data_size: u64,
+    finalized: bool,
+}
+
+/// Where the sizes of a file are, so they can be patched once the data is written
+struct SizeFields {
+    /// Position of the RIFF header
+    start: u64,
+    /// Whether the first chunk is a ds64 chunk, or a JUNK chunk that can become one
+    ds64: bool,
+    /// Position of the fact chunk's sample length, for formats that have one
+    fact_pos: Option<u64>,
+    data_start: u64,
+}
+
+impl SizeFields {
+    /// Writes the sizes for `data_size` bytes of data plus `pad`, switching between RIFF and
+    /// RF64 as needed. Without room for a ds64 chunk, sizes that don't fit are clamped.
+    fn patch<W: Write + Seek>(
+        &self,
+        w: &mut W,
+        data_size: u64,
+        frames: u64,
+        pad: u64,
+    ) -> std::io::Result<()> {
+        let riff_size = self.data_start - self.start - 8 + data_size + pad;
+        let rf64 = self.ds64 && riff_size > u32::MAX as u64;
+        let clamp = |size: u64| match rf64 {
+            true => SIZE_IN_DS64,
+            false => size.min(u32::MAX as u64) as u32,
+        };
+        w.seek(SeekFrom::Start(self.start))?;
+        w.write_all(if rf64 { b"RF64" } else { b"RIFF" })?;
+        w.write_u32::<LittleEndian>(clamp(riff_size))?;
+        if self.ds64 {
+            w.seek(SeekFrom::Start(self.start + 12))?;
+            let ds64 = Ds64 {
+                riff_size,
+                data_size,
+                sample_count: frames,
+                table: Vec::new(),
+            };
+            ds64.write_to(w, if rf64 { b"ds64" } else { b"JUNK" })?;
+        }
+        if let Some(fact_pos) = self.fact_pos {
+            w.seek(SeekFrom::Start(fact_pos))?;
+            w.write_u32::<LittleEndian>(clamp(frames))?;
+        }
+        w.seek(SeekFrom::Start(self.data_start - 4))?;
+        w.write_u32::<LittleEndian>(clamp(data_size))?;
+        w.seek(SeekFrom::Start(self.data_start + data_size + pad))?;
+        Ok(())
+    }
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: &Path, params: WavParams, bit_depth: BitDepth) -> Result<Self, WavError> {
+        WavWriter::new(BufWriter::new(File::create(path)?), params, bit_depth)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Writes the header for an empty file. Every sample must then be of the same kind as
+    /// `bit_depth`.
+    pub fn new(writer: W, params: WavParams, bit_depth: BitDepth) -> Result<Self, WavError> {
+        WavWriter::with_chunks(writer, params, bit_depth, &[])
+    }
+    /// Like `new`, with `chunks` written before the samples, e.g. a `bext` chunk stamped with
+    /// `BroadcastExt::now` when a recording starts. Room is kept for a ds64 chunk, so the file
+    /// becomes RF64 if it outgrows 4 GiB.
+    pub fn with_chunks(
+        mut writer: W,
+        params: WavParams,
+        bit_depth: BitDepth,
+        chunks: &[Chunk],
+    ) -> Result<Self, WavError> {
+        let hdr = WavHdr::new(&params, bit_depth, 0)?;
+        let start = writer.stream_position()?;
+        hdr.write_to(&mut writer, chunks, true)?;
+        let data_start = writer.stream_position()?;
+        let fmt_end = start + 12 + Ds64::FILE_SIZE + 8 + hdr.fmt_ck.size as u64;
+        Ok(WavWriter {
+            writer,
+            bit_depth,
+            channels: params.channels,
+            fields: SizeFields {
+                start,
+                ds64: true,
+                // The fact chunk follows the fmt chunk
+                fact_pos: hdr.fact_ck.map(|_| fmt_end + 8),
+                data_start,
             },
-            data,
+            data_size: 0,
+            finalized: false,
+        })
+    }
+    pub fn write_sample(&mut self, sample: BitDepth) -> Result<(), WavError> {
+        if std::mem::discriminant(&sample) != std::mem::discriminant(&self.bit_depth) {
+            return Err(WavError::SampleMismatch);
+        }
+        sample.write_to(&mut self.writer)?;
+        self.data_size += sample.bits() as u64 / 8;
+        Ok(())
+    }
+    /// Writes interleaved samples
+    pub fn write_samples(&mut self, samples: &[BitDepth]) -> Result<(), WavError> {
+        for s in samples {
+            self.write_sample(*s)?;
+        }
+        Ok(())
+    }
+    pub fn write_buffer(&mut self, buffer: &SampleBuffer) -> Result<(), WavError> {
+        if buffer.channels() != self.channels {
+            return Err(WavError::SampleMismatch);
+        }
+        self.write_samples(buffer.interleaved())
+    }
+    /// Frames written so far
+    pub fn len(&self) -> u64 {
+        self.data_size / (self.bit_depth.bits() as u64 / 8 * self.channels as u64)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.data_size == 0
+    }
+    /// Writes the current sizes to the header so the file is readable even if the writer never
+    /// gets finalized
+    pub fn flush(&mut self) -> Result<(), WavError> {
+        self.update_sizes(0)
+    }
+    /// Pads the data chunk and writes the final sizes
+    pub fn finalize(mut self) -> Result<(), WavError> {
+        self.finish()
+    }
+    fn finish(&mut self) -> Result<(), WavError> {
+        self.finalized = true;
+        let pad = self.data_size % 2;
+        if pad != 0 {
+            self.writer.write_all(&[0x00])?;
+        }
+        self.update_sizes(pad)
+    }
+    fn update_sizes(&mut self, pad: u64) -> Result<(), WavError> {
+        let frames = self.len();
+        self.fields
+            .patch(&mut self.writer, self.data_size, frames, pad)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Drop for WavWriter<W> {
+    fn drop(&mut self) {
+        if !self.finalized {
+            let _ = self.finish();
+        }
+    }
+}
+
+/// Fixes the sizes in the header of a file that was never finalized, e.g. because the recorder
+/// crashed. Everything from the start of the data chunk to the end of the stream is taken as
+/// samples. Returns the number of frames recovered.
+pub fn recover<F: Read + Write + Seek>(f: &mut F) -> Result<u64, WavError> {
+    read_riff_hdr(f)?;
+
+    let len = f.seek(SeekFrom::End(0))?;
+    let mut fmt_ck = None;
+    let mut ds64 = false;
+    let mut fact_pos = None;
+    let mut data_start = None;
+    // The sizes can't be trusted, so walk up to the data chunk and stop there
+    let mut iter = RiffChunks::new(f, 12, len)?;
+    while let Some(hdr) = iter.next() {
+        let hdr = hdr?;
+        match &hdr.id {
+            // A ds64 chunk without a table, or the room a writer saved for one. Anything else is
+            // left as it is, rewriting its size would break the chunks after it.
+            b"ds64" | b"JUNK" if hdr.offset == 20 && hdr.size == 28 => ds64 = true,
+            b"fmt " => fmt_ck = Some(FmtHdr::parse(&iter.read_body(&hdr)?)?),
+            b"fact" => fact_pos = Some(hdr.offset),
+            b"data" => {
+                data_start = Some(hdr.offset);
+                break;
+            }
+            _ => (),
+        }
+    }
+    let fmt_ck = fmt_ck.ok_or(WavError::MissingChunk(*b"fmt "))?;
+    let data_start = data_start.ok_or(WavError::MissingChunk(*b"data"))?;
+    let (block_size, _) = fmt_ck.block_layout()?;
+    let data_size = (len - data_start) / block_size as u64 * block_size as u64;
+    let frames = fmt_ck.frames_in(data_size);
+
+    let fields = SizeFields {
+        start: 0,
+        ds64,
+        fact_pos,
+        data_start,
+    };
+    fields.patch(f, data_size, frames, data_size % 2)?;
+    f.flush()?;
+    Ok(frames)
+}
+
+impl FmtHdr {
+    /// Parses a `fmt ` chunk body, including the extensible header when there is one
+    fn parse(mut body: &[u8]) -> Result<FmtHdr, WavError> {
+        if body.len() < 16 {
+            return Err(WavError::MalformedChunk(*b"fmt "));
+        }
+        let size = body.len() as u32;
+        let mut fmt_ck = FmtHdr {
+            id: *b"fmt ",
+            size,
+            fmt_tag: body.read_u16::<LittleEndian>()?,
+            channels: body.read_u16::<LittleEndian>()?,
+            sample_rate: body.read_u32::<LittleEndian>()?,
+            byte_rate: body.read_u32::<LittleEndian>()?,
+            block_align: body.read_u16::<LittleEndian>()?,
+            bits_per_sample: body.read_u16::<LittleEndian>()?,
+            extensible: None,
+        };
+        // Nothing can be played or resampled at 0 Hz
+        if fmt_ck.sample_rate == 0 {
+            return Err(WavError::MalformedChunk(*b"fmt "));
+        }
+        if fmt_ck.fmt_tag == WAVE_FORMAT_EXTENSIBLE {
+            if body.len() < 24 || body.read_u16::<LittleEndian>()? < 22 {
+                return Err(WavError::MalformedChunk(*b"fmt "));
+            }
+            let valid_bits_per_sample = body.read_u16::<LittleEndian>()?;
+            let channel_mask = ChannelMask(body.read_u32::<LittleEndian>()?);
+            let mut sub_format = [0; 16];
+            body.read_exact(&mut sub_format)?;
+            fmt_ck.extensible = Some(FmtExtensible {
+                valid_bits_per_sample,
+                channel_mask,
+                sub_format,
+            });
+        }
+        Ok(fmt_ck)
+    }
+    /// Bytes taken by one sample, if the format is one we can decode
+    fn sample_size(&self) -> Option<usize> {
+        match (self.format(), self.bits_per_sample) {
+            (WAVE_FORMAT_PCM, 8 | 16 | 24 | 32)
+            | (WAVE_FORMAT_IEEE_FLOAT, 32 | 64)
+            | (WAVE_FORMAT_ALAW | WAVE_FORMAT_MULAW, 8) => Some(self.bits_per_sample as usize / 8),
+            _ => None,
+        }
+    }
+    /// Bytes in a block and the frames it holds. A block is one frame, one sample for each
+    /// channel, except in ADPCM.
+    fn block_layout(&self) -> Result<(usize, usize), WavError> {
+        if self.channels == 0 {
+            return Err(WavError::MalformedChunk(*b"fmt "));
+        }
+        if (self.format(), self.bits_per_sample) == (WAVE_FORMAT_IMA_ADPCM, 4) {
+            return match adpcm::samples_per_block(self.block_align, self.channels) {
+                Some(frames) => Ok((self.block_align as usize, frames)),
+                None => Err(WavError::MalformedChunk(*b"fmt ")),
+            };
+        }
+        match self.sample_size() {
+            Some(size) => Ok((size * self.channels as usize, 1)),
+            None => Err(self.unsupported()),
+        }
+    }
+    /// Frames in `data_size` bytes of data, counting a short last block
+    fn frames_in(&self, data_size: u64) -> u64 {
+        let Ok((block_size, block_frames)) = self.block_layout() else {
+            return 0;
+        };
+        let whole = data_size / block_size as u64 * block_frames as u64;
+        let rest = (data_size % block_size as u64) as usize;
+        match block_frames {
+            1 => whole,
+            _ => whole + adpcm::frames_in_block(rest, self.channels) as u64,
+        }
+    }
+    pub fn encoding(&self) -> Encoding {
+        match self.format() {
+            WAVE_FORMAT_ALAW => Encoding::ALaw,
+            WAVE_FORMAT_MULAW => Encoding::MuLaw,
+            WAVE_FORMAT_IMA_ADPCM => Encoding::ImaAdpcm {
+                block_align: self.block_align,
+            },
+            _ => Encoding::Pcm,
+        }
+    }
+    fn unsupported(&self) -> WavError {
+        WavError::UnsupportedFormat {
+            fmt_tag: self.format(),
+            bits_per_sample: self.bits_per_sample,
+        }
+    }
+    /// Decodes the bytes of whole frames from the data chunk
+    fn decode(&self, data: &[u8]) -> Result<Vec<BitDepth>, WavError> {
+        let data = match (self.format(), self.bits_per_sample) {
+            (WAVE_FORMAT_PCM, 8) => data.iter().map(|&b| BitDepth::U8(u8_from_wav(b))).collect(),
+            (WAVE_FORMAT_PCM, 16) => data
+                .chunks_exact(2)
+                .map(|s| BitDepth::U16(LittleEndian::read_i16(s)))
+                .collect(),
+            (WAVE_FORMAT_PCM, 24) => data
+                .chunks_exact(3)
+                .map(|s| BitDepth::U24(LittleEndian::read_i24(s)))
+                .collect(),
+            (WAVE_FORMAT_PCM, 32) => data
+                .chunks_exact(4)
+                .map(|s| BitDepth::U32(LittleEndian::read_i32(s)))
+                .collect(),
+            (WAVE_FORMAT_IEEE_FLOAT, 32) => data
+                .chunks_exact(4)
+                .map(|s| BitDepth::F32(LittleEndian::read_f32(s)))
+                .collect(),
+            (WAVE_FORMAT_IEEE_FLOAT, 64) => data
+                .chunks_exact(8)
+                .map(|s| BitDepth::F64(LittleEndian::read_f64(s)))
+                .collect(),
+            (WAVE_FORMAT_ALAW, 8) => data
+                .iter()
+                .map(|&b| BitDepth::U16(g711::alaw_decode(b)))
+                .collect(),
+            (WAVE_FORMAT_MULAW, 8) => data
+                .iter()
+                .map(|&b| BitDepth::U16(g711::ulaw_decode(b)))
+                .collect(),
+            (WAVE_FORMAT_IMA_ADPCM, 4) => adpcm::decode(data, self.channels, self.block_align)
+                .into_iter()
+                .map(BitDepth::U16)
+                .collect(),
+            _ => return Err(self.unsupported()),
+        };
+        Ok(data)
+    }
+    /// Encodes samples for one of the compressed formats, converting them to 16 bits first
+    fn encode(&self, samples: &[BitDepth]) -> Result<Vec<u8>, WavError> {
+        let samples: Vec<i16> = samples
+            .iter()
+            .map(|s| match convert::convert(s, BitDepth::U16(0)) {
+                BitDepth::U16(v) => v,
+                _ => unreachable!(),
+            })
+            .collect();
+        match self.format() {
+            WAVE_FORMAT_ALAW => Ok(samples.iter().map(|&s| g711::alaw_encode(s)).collect()),
+            WAVE_FORMAT_MULAW => Ok(samples.iter().map(|&s| g711::ulaw_encode(s)).collect()),
+            WAVE_FORMAT_IMA_ADPCM => adpcm::encode(&samples, self.channels, self.block_align)
+                .ok_or(WavError::MalformedChunk(*b"fmt ")),
+            _ => Err(self.unsupported()),
+        }
+    }
+    /// Format of the samples, looking through the extensible sub-format
+    pub fn format(&self) -> u16 {
+        match &self.extensible {
+            Some(ext) => ext.sub_format_tag(),
+            None => self.fmt_tag,
+        }
+    }
+    fn write_to<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        w.write_all(&self.id)?;
+        w.write_u32::<LittleEndian>(self.size)?;
+        w.write_u16::<LittleEndian>(self.fmt_tag)?;
+        w.write_u16::<LittleEndian>(self.channels)?;
+        w.write_u32::<LittleEndian>(self.sample_rate)?;
+        w.write_u32::<LittleEndian>(self.byte_rate)?;
+        w.write_u16::<LittleEndian>(self.block_align)?;
+        w.write_u16::<LittleEndian>(self.bits_per_sample)?;
+        if self.size >= 18 {
+            w.write_u16::<LittleEndian>((self.size - 18) as u16)?;
+        }
+        if self.fmt_tag == WAVE_FORMAT_IMA_ADPCM && self.size >= 20 {
+            let frames = adpcm::samples_per_block(self.block_align, self.channels).unwrap_or(0);
+            w.write_u16::<LittleEndian>(frames as u16)?;
+        }
+        if let Some(ext) = &self.extensible {
+            w.write_u16::<LittleEndian>(ext.valid_bits_per_sample)?;
+            w.write_u32::<LittleEndian>(ext.channel_mask.0)?;
+            w.write_all(&ext.sub_format)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn riff_bytes(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    let mut body = b"WAVE".to_vec();
+    for (id, data) in chunks {
+        body.extend_from_slice(*id);
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(data);
+        if data.len() % 2 != 0 {
+            body.push(0);
+        }
+    }
+    let mut file = b"RIFF".to_vec();
+    file.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    file.extend(body);
+    file
+}
+
+#[test]
+fn test_read_chunks_anywhere() -> Result<(), String> {
+    // 18 byte fmt chunk (cbSize = 0), 16 bit mono
+    let fmt = [
+        1, 0, 1, 0, 0x44, 0xac, 0, 0, 0x88, 0x58, 1, 0, 2, 0, 16, 0, 0, 0,
+    ];
+    let samples: Vec<u8> = [1i16, -2, 300]
+        .iter()
+        .flat_map(|s| s.to_le_bytes())
+        .collect();
+    let file = riff_bytes(&[
+        (b"JUNK", &[0; 3]),
+        (b"fmt ", &fmt),
+        (b"LIST", b"INFOISFT\x05\x00\x00\x00test\x00\x00"),
+        (b"data", &samples),
+    ]);
+    let wav = WavFile::read_from(&mut std::io::Cursor::new(file)).map_err(|e| e.to_string())?;
+    assert_eq!(wav.hdr.fmt_ck.sample_rate, 44100);
+    assert_eq!(wav.data.frames(), 3);
+    assert!(matches!(wav.data.interleaved()[2], BitDepth::U16(300)));
+    let ids: Vec<&Fourcc> = wav.chunks.iter().map(|c| &c.id).collect();
+    assert_eq!(ids, [b"JUNK"]);
+    assert_eq!(wav.chunks[0].data.len(), 3);
+    assert_eq!(wav.info[&InfoTag::Software], "test");
+    Ok(())
+}
+
+#[test]
+fn test_read_missing_data() {
+    let fmt = [1, 0, 1, 0, 0x44, 0xac, 0, 0, 0x88, 0x58, 1, 0, 2, 0, 16, 0];
+    let file = riff_bytes(&[(b"fmt ", &fmt)]);
+    assert!(matches!(
+        WavFile::read_from(&mut std::io::Cursor::new(file)),
+        Err(WavError::MissingChunk(id)) if &id == b"data"
+    ));
+}
+
+#[test]
+fn test_read_errors() {
+    let read = |file: Vec<u8>| WavFile::read_from(&mut std::io::Cursor::new(file));
+    assert!(matches!(read(b"RIFX".to_vec()), Err(WavError::Truncated)));
+    assert!(matches!(
+        read(b"RIFX\x04\x00\x00\x00WAVE".to_vec()),
+        Err(WavError::NotRiff)
+    ));
+    assert!(matches!(
+        read(b"RIFF\x04\x00\x00\x00AVI ".to_vec()),
+        Err(WavError::NotWave)
+    ));
+    // 12 bit samples
+    let fmt = [1, 0, 1, 0, 0x40, 0x1f, 0, 0, 0, 0x7d, 0, 0, 2, 0, 12, 0];
+    assert!(matches!(
+        read(riff_bytes(&[(b"fmt ", &fmt), (b"data", &[0; 4])])),
+        Err(WavError::UnsupportedFormat {
+            fmt_tag: WAVE_FORMAT_PCM,
+            bits_per_sample: 12
+        })
+    ));
+    assert!(matches!(
+        read(riff_bytes(&[(b"fmt ", &fmt[..10]), (b"data", &[0; 4])])),
+        Err(WavError::MalformedChunk(_))
+    ));
+    // 0 Hz
+    let still = [1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 16, 0];
+    assert!(matches!(
+        read(riff_bytes(&[(b"fmt ", &still), (b"data", &[0; 4])])),
+        Err(WavError::MalformedChunk(id)) if &id == b"fmt "
+    ));
+    // A frame of 20000 channels of 32 bits is too big for the block align, and 16 bit stereo
+    // at 0xFFFFFFFF Hz for the byte rate
+    let wide = [1, 0, 0x20, 0x4e, 0x40, 0x1f, 0, 0, 0, 0, 0, 0, 0, 0, 32, 0];
+    let fast = [1, 0, 2, 0, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 4, 0, 16, 0];
+    for (fmt, frame) in [(wide, 80000), (fast, 4)] {
+        assert!(matches!(
+            read(riff_bytes(&[(b"fmt ", &fmt), (b"data", &vec![0; frame])])),
+            Err(WavError::MalformedChunk(id)) if &id == b"fmt "
         ));
     }
 }
+
+#[test]
+fn test_24bit_round_trip() -> Result<(), String> {
+    let path = std::env::temp_dir().join("test_24bit_round_trip.wav");
+    let data = vec![
+        BitDepth::U24(U24_MAX),
+        BitDepth::U24(-1),
+        BitDepth::U24(-300000),
+    ];
+    let params = WavParams {
+        sample_rate: 48000,
+        channels: 1,
+    };
+    WavFile::new(params, data)
+        .and_then(|wav| wav.write(&path))
+        .map_err(|e| e.to_string())?;
+    let wav = WavFile::read(&path).map_err(|e| e.to_string())?;
+    std::fs::remove_file(&path).map_err(|e| e.to_string())?;
+    assert_eq!(wav.hdr.fmt_ck.bits_per_sample, 24);
+    let read: Vec<i32> = wav
+        .data
+        .interleaved()
+        .iter()
+        .map(|s| match s {
+            BitDepth::U24(v) => *v,
+            _ => 0,
+        })
+        .collect();
+    assert_eq!(read, [U24_MAX, -1, -300000]);
+    Ok(())
+}
+
+#[test]
+fn test_float_round_trip() -> Result<(), String> {
+    let path = std::env::temp_dir().join("test_float_round_trip.wav");
+    let data = vec![BitDepth::F32(0.25), BitDepth::F32(-1.0)];
+    let params = WavParams {
+        sample_rate: 48000,
+        channels: 2,
+    };
+    let wav = WavFile::new(params, data).map_err(|e| e.to_string())?;
+    assert_eq!(wav.hdr.fmt_ck.fmt_tag, WAVE_FORMAT_IEEE_FLOAT);
+    assert_eq!(wav.hdr.fact_ck.as_ref().unwrap().sample_length, 1);
+    wav.write(&path).map_err(|e| e.to_string())?;
+    let wav = WavFile::read(&path).map_err(|e| e.to_string())?;
+    std::fs::remove_file(&path).map_err(|e| e.to_string())?;
+    assert!(
+        matches!(wav.data.interleaved(), [BitDepth::F32(a), BitDepth::F32(b)] if *a == 0.25 && *b == -1.0)
+    );
+    assert!(wav.chunks.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_extensible_channel_mask() -> Result<(), String> {
+    let path = std::env::temp_dir().join("test_extensible_channel_mask.wav");
+    let params = WavParams {
+        sample_rate: 48000,
+        channels: 2,
+    };
+    let mut wav = WavFile::new(params, vec![BitDepth::U16(1), BitDepth::U16(2)])
+        .map_err(|e| e.to_string())?;
+    assert_eq!(wav.hdr.fmt_ck.fmt_tag, WAVE_FORMAT_PCM);
+    assert_eq!(wav.channel_mask(), ChannelMask::STEREO);
+    // Stereo pair on the sides needs an extensible header
+    wav.set_channel_mask(ChannelMask::SIDE_LEFT | ChannelMask::SIDE_RIGHT);
+    assert_eq!(wav.hdr.fmt_ck.fmt_tag, WAVE_FORMAT_EXTENSIBLE);
+    wav.write(&path).map_err(|e| e.to_string())?;
+
+    let wav = WavFile::read(&path).map_err(|e| e.to_string())?;
+    std::fs::remove_file(&path).map_err(|e| e.to_string())?;
+    assert_eq!(wav.hdr.fmt_ck.size, 40);
+    assert_eq!(wav.hdr.fmt_ck.format(), WAVE_FORMAT_PCM);
+    assert_eq!(wav.channel_mask(), ChannelMask(0x600));
+    Ok(())
+}
+
+#[test]
+fn test_extensible_chosen_for_surround() {
+    let params = WavParams {
+        sample_rate: 48000,
+        channels: 6,
+    };
+    let wav = WavFile::new(params, vec![BitDepth::F32(0.0); 6]).unwrap();
+    assert_eq!(wav.hdr.fmt_ck.fmt_tag, WAVE_FORMAT_EXTENSIBLE);
+    assert_eq!(wav.hdr.fmt_ck.format(), WAVE_FORMAT_IEEE_FLOAT);
+    assert_eq!(wav.channel_mask(), ChannelMask::SURROUND_5_1);
+    assert!(wav.hdr.fact_ck.is_some());
+}
+
+#[test]
+fn test_8bit_offset() -> Result<(), String> {
+    let fmt = [1, 0, 1, 0, 0x40, 0x1f, 0, 0, 0x40, 0x1f, 0, 0, 1, 0, 8, 0];
+    let file = riff_bytes(&[(b"fmt ", &fmt), (b"data", &[128, 255, 0, 129])]);
+    let wav = WavFile::read_from(&mut std::io::Cursor::new(file)).map_err(|e| e.to_string())?;
+    let read: Vec<i8> = wav
+        .data
+        .interleaved()
+        .iter()
+        .map(|s| match s {
+            BitDepth::U8(v) => *v,
+            _ => 0,
+        })
+        .collect();
+    assert_eq!(read, [0, 127, -128, 1]);
+
+    let path = std::env::temp_dir().join("test_8bit_offset.wav");
+    wav.write(&path).map_err(|e| e.to_string())?;
+    let file = std::fs::read(&path).map_err(|e| e.to_string())?;
+    std::fs::remove_file(&path).map_err(|e| e.to_string())?;
+    assert_eq!(file[file.len() - 4..], [128, 255, 0, 129]);
+    Ok(())
+}
+
+#[test]
+fn test_reader_blocks_and_seek() -> Result<(), String> {
+    let fmt = [1, 0, 2, 0, 0x40, 0x1f, 0, 0, 0, 0x7d, 0, 0, 4, 0, 16, 0];
+    let samples: Vec<u8> = (0..10i16).flat_map(|s| s.to_le_bytes()).collect();
+    let file = riff_bytes(&[(b"fmt ", &fmt), (b"data", &samples), (b"JUNK", &[0; 4])]);
+    let mut reader = WavReader::new(std::io::Cursor::new(file)).map_err(|e| e.to_string())?;
+    assert_eq!(reader.len(), 5);
+    assert_eq!(reader.chunks.len(), 1);
+    let lens: Vec<usize> = reader.blocks(2).map(|b| b.unwrap().frames()).collect();
+    assert_eq!(lens, [2, 2, 1]);
+    reader.seek(3).map_err(|e| e.to_string())?;
+    let frame = reader.read_frame().map_err(|e| e.to_string())?.unwrap();
+    assert!(matches!(frame[..], [BitDepth::U16(6), BitDepth::U16(7)]));
+    assert_eq!(reader.position(), 4);
+    assert!(reader.seek(6).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_writer_patches_sizes() -> Result<(), String> {
+    let mut file = std::io::Cursor::new(Vec::new());
+    let params = WavParams {
+        sample_rate: 8000,
+        channels: 2,
+    };
+    let mut writer =
+        WavWriter::new(&mut file, params, BitDepth::F32(0.0)).map_err(|e| e.to_string())?;
+    for i in 0..3 {
+        writer
+            .write_samples(&[BitDepth::F32(i as f32), BitDepth::F32(-i as f32)])
+            .map_err(|e| e.to_string())?;
+    }
+    assert!(writer.write_sample(BitDepth::U16(0)).is_err());
+    assert_eq!(writer.len(), 3);
+    writer.finalize().map_err(|e| e.to_string())?;
+
+    let wav = WavFile::read_from(&mut file).map_err(|e| e.to_string())?;
+    assert_eq!(wav.data.frames(), 3);
+    assert_eq!(wav.hdr.fact_ck.unwrap().sample_length, 3);
+    let bytes = file.into_inner();
+    assert_eq!(
+        u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize,
+        bytes.len() - 8
+    );
+    Ok(())
+}
+
+#[test]
+fn test_recover_unfinalized() -> Result<(), String> {
+    let params = WavParams {
+        sample_rate: 8000,
+        channels: 1,
+    };
+    let mut file = std::io::Cursor::new(Vec::new());
+    let hdr = WavHdr::new(&params, BitDepth::U16(0), 0).map_err(|e| e.to_string())?;
+    hdr.write_to(&mut file, &[], true)
+        .map_err(|e| e.to_string())?;
+    // Samples made it to disk but the sizes were never patched
+    for s in [1i16, 2, 3, 4, 5] {
+        file.write_all(&s.to_le_bytes())
+            .map_err(|e| e.to_string())?;
+    }
+    assert_eq!(recover(&mut file).map_err(|e| e.to_string())?, 5);
+    let wav = WavFile::read_from(&mut file).map_err(|e| e.to_string())?;
+    assert_eq!(wav.data.frames(), 5);
+    Ok(())
+}
+
+#[test]
+fn test_recover_keeps_foreign_junk() -> Result<(), String> {
+    let fmt = [1, 0, 1, 0, 0x40, 0x1f, 0, 0, 0x80, 0x3e, 0, 0, 2, 0, 16, 0];
+    let mut bytes = riff_bytes(&[(b"JUNK", &[7; 92]), (b"fmt ", &fmt), (b"data", &[])]);
+    bytes.extend((1..=5i16).flat_map(|s| s.to_le_bytes()));
+    let mut file = std::io::Cursor::new(bytes);
+    assert_eq!(recover(&mut file).map_err(|e| e.to_string())?, 5);
+    let wav = WavFile::read_from(&mut file).map_err(|e| e.to_string())?;
+    assert_eq!(wav.data.frames(), 5);
+    assert_eq!(wav.chunks.len(), 1);
+    assert_eq!(wav.chunks[0].data, [7; 92]);
+    Ok(())
+}
+
+#[test]
+fn test_header_sizes() {
+    let params = WavParams {
+        sample_rate: 44100,
+        channels: 2,
+    };
+    let wav = WavFile::new(params, vec![BitDepth::U16(0); 6]).unwrap();
+    assert_eq!(wav.hdr.data_hdr.size, 12);
+    assert_eq!(wav.hdr.fmt_ck.block_align, 4);
+    assert_eq!(wav.hdr.fmt_ck.byte_rate, 176400);
+    assert_eq!(wav.hdr.riff_hdr.size, 36 + 12);
+
+    let params = WavParams {
+        sample_rate: 8000,
+        channels: 1,
+    };
+    let wav = WavFile::new(params, vec![BitDepth::U8(0); 3]).unwrap();
+    // Odd data is padded, but only the RIFF size counts the pad byte
+    assert_eq!(wav.hdr.data_hdr.size, 3);
+    assert_eq!(wav.hdr.riff_hdr.size, 36 + 4);
+}
+
+#[test]
+fn test_bad_params_and_mixed_samples() {
+    let params = WavParams {
+        sample_rate: 8000,
+        channels: 0,
+    };
+    assert!(matches!(
+        WavFile::new(params, vec![BitDepth::U16(0); 2]),
+        Err(WavError::MalformedChunk(id)) if &id == b"fmt "
+    ));
+    let file = std::io::Cursor::new(Vec::new());
+    assert!(matches!(
+        WavWriter::new(file, params, BitDepth::U16(0)),
+        Err(WavError::MalformedChunk(id)) if &id == b"fmt "
+    ));
+
+    // The header comes from the first sample, so the rest have to match it
+    let path = std::env::temp_dir().join("test_bad_params_and_mixed_samples.wav");
+    let params = WavParams {
+        sample_rate: 8000,
+        channels: 1,
+    };
+    let wav = WavFile::new(
+        params,
+        vec![BitDepth::U8(0), BitDepth::U16(0), BitDepth::U16(0)],
+    )
+    .unwrap();
+    assert!(matches!(wav.write(&path), Err(WavError::SampleMismatch)));
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_written_header_is_consistent() -> Result<(), String> {
+    let path = std::env::temp_dir().join("test_written_header_is_consistent.wav");
+    let params = WavParams {
+        sample_rate: 22050,
+        channels: 3,
+    };
+    WavFile::new(params, vec![BitDepth::U24(1); 9])
+        .and_then(|wav| wav.write(&path))
+        .map_err(|e| e.to_string())?;
+    let issues = WavFile::validate(&path).map_err(|e| e.to_string())?;
+    std::fs::remove_file(&path).map_err(|e| e.to_string())?;
+    assert_eq!(issues, []);
+    Ok(())
+}
+
+#[test]
+fn test_validate_reports_issues() -> Result<(), String> {
+    // block_align and byte_rate given in bits, like old versions of this crate wrote them
+    let fmt = [1, 0, 1, 0, 0x40, 0x1f, 0, 0, 0, 0xfa, 0, 0, 16, 0, 16, 0];
+    let mut file = riff_bytes(&[(b"fmt ", &fmt), (b"data", &[0; 5])]);
+    // Claim more data than there is
+    let data_size_pos = file.len() - 10;
+    file[data_size_pos] = 8;
+    let reader = WavReader::new(std::io::Cursor::new(file)).map_err(|e| e.to_string())?;
+    assert_eq!(
+        reader.issues,
+        [
+            HeaderIssue::DataSize {
+                header: 8,
+                actual: 6
+            },
+            HeaderIssue::BlockAlign {
+                header: 16,
+                expected: 2
+            },
+            HeaderIssue::ByteRate {
+                header: 64000,
+                expected: 16000
+            },
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_add_saturates() {
+    let sum = BitDepth::U16(30000) + BitDepth::U16(30000);
+    assert!(matches!(sum, BitDepth::U16(i16::MAX)));
+    let sum = BitDepth::U24(-U24_MAX) + BitDepth::U24(-U24_MAX);
+    assert!(matches!(sum, BitDepth::U24(v) if v == -U24_MAX - 1));
+    assert!(matches!(BitDepth::U8(100).scale(2.0), BitDepth::U8(127)));
+}
+
+#[test]
+fn test_add_mixed_depths_by_value() {
+    // Half scale in 8 bits plus a quarter scale in 16 bits
+    let sum = BitDepth::U8(64) + BitDepth::U16(8192);
+    assert!(matches!(sum, BitDepth::U16(24576)));
+    let sum = BitDepth::U16(16384).add_scaled(BitDepth::F32(1.0), 0.25);
+    assert!(matches!(sum, BitDepth::F32(v) if v == 0.75));
+}
+
+#[test]
+fn test_info_survives_rewrite() -> Result<(), WavError> {
+    let path = std::env::temp_dir().join("test_info_survives_rewrite.wav");
+    let mut wav = WavFile::new(
+        WavParams {
+            sample_rate: 8000,
+            channels: 1,
+        },
+        vec![BitDepth::U16(1); 4],
+    )?;
+    wav.info.insert(InfoTag::Title, "Bass stem".into());
+    wav.info.insert(InfoTag::Other(*b"IXYZ"), "odd".into());
+    wav.chunks.push(Chunk {
+        id: *b"JUNK",
+        data: vec![0; 5],
+    });
+    wav.write(&path)?;
+
+    let mut wav = WavFile::read(&path)?;
+    assert_eq!(wav.info[&InfoTag::Title], "Bass stem");
+    wav.info.insert(InfoTag::Artist, "Me".into());
+    wav.write(&path)?;
+
+    let wav = WavFile::read(&path)?;
+    assert!(WavFile::validate(&path)?.is_empty());
+    std::fs::remove_file(&path)?;
+    assert_eq!(wav.info.len(), 3);
+    assert_eq!(wav.info[&InfoTag::Other(*b"IXYZ")], "odd");
+    assert_eq!(wav.info[&InfoTag::Artist], "Me");
+    assert_eq!(wav.chunks.len(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_cues_and_sampler_round_trip() -> Result<(), WavError> {
+    let path = std::env::temp_dir().join("test_cues_and_sampler_round_trip.wav");
+    let mut wav = WavFile::new(
+        WavParams {
+            sample_rate: 44100,
+            channels: 1,
+        },
+        vec![BitDepth::U16(0); 100],
+    )?;
+    let mut attack = CuePoint::new(1, 0);
+    attack.label = Some("Attack".into());
+    let mut sustain = CuePoint::new(2, 40);
+    sustain.note = Some("Loops".into());
+    sustain.region = Some(CueRegion {
+        length: 20,
+        purpose: *b"rgn ",
+        text: "Sustain".into(),
+    });
+    wav.cues = vec![attack, sustain];
+    let mut sampler = SamplerInfo::new(44100, "C4").unwrap();
+    sampler.loops.push(SampleLoop {
+        cue_id: 2,
+        kind: LoopKind::Forward,
+        start: 40,
+        end: 59,
+        fraction: 0,
+        play_count: 0,
+    });
+    wav.sampler = Some(sampler.clone());
+    let cues = wav.cues.clone();
+    wav.write(&path)?;
+
+    let wav = WavFile::read(&path)?;
+    assert!(WavFile::validate(&path)?.is_empty());
+    std::fs::remove_file(&path)?;
+    assert_eq!(wav.cues, cues);
+    assert_eq!(wav.sampler, Some(sampler));
+    assert!(wav.chunks.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_sampler_root_note() {
+    let mut sampler = SamplerInfo::new(48000, "A4").unwrap();
+    assert_eq!(sampler.root_note, 69);
+    assert_eq!(sampler.sample_period, 20833);
+    sampler.set_root_note("C4").unwrap();
+    assert_eq!((sampler.root_note, sampler.fine_tune), (60, 0));
+    // 0.4 of a semitone sharp is kept as fine tuning
+    sampler
+        .set_root_freq(notation::note_to_freq("C#4") * 2f64.powf(0.4 / 12.0))
+        .unwrap();
+    assert_eq!(sampler.root_note, 61);
+    assert_eq!(sampler.root_note_name(), "C#4");
+
+    // Nothing changes for a bad name or frequency
+    let tuned = sampler.clone();
+    assert!(SamplerInfo::new(48000, "H4").is_none());
+    assert!(sampler.set_root_note("Cb4").is_none());
+    for freq in [0.0, -440.0, f64::NAN, f64::INFINITY, 1.0, 20000.0] {
+        assert!(sampler.set_root_freq(freq).is_none(), "{freq}");
+    }
+    assert_eq!(sampler, tuned);
+}
+
+#[test]
+fn test_bext_and_ixml_round_trip() -> Result<(), WavError> {
+    let path = std::env::temp_dir().join("test_bext_and_ixml_round_trip.wav");
+    let mut wav = WavFile::new(
+        WavParams {
+            sample_rate: 48000,
+            channels: 1,
+        },
+        vec![BitDepth::U24(0); 10],
+    )?;
+    // 2024-02-29 10:00:00.5 UTC
+    let start = std::time::Duration::from_millis(1_709_200_800_500);
+    let bext = BroadcastExt {
+        originator: "rust_audio_playground".into(),
+        loudness_value: Some(-23.0),
+        max_true_peak_level: Some(-1.5),
+        coding_history: "A=PCM,F=48000,W=24,M=mono\r\n".into(),
+        ..BroadcastExt::at(start, 48000)
+    };
+    assert_eq!(bext.origination_date, "2024-02-29");
+    assert_eq!(bext.origination_time, "10:00:00");
+    assert_eq!(bext.time_reference, 36000 * 48000 + 24000);
+    wav.bext = Some(bext.clone());
+    wav.ixml = Some("<BWFXML><SCENE>1</SCENE></BWFXML>".into());
+    wav.write(&path)?;
+
+    let wav = WavFile::read(&path)?;
+    assert!(WavFile::validate(&path)?.is_empty());
+    std::fs::remove_file(&path)?;
+    assert_eq!(wav.bext, Some(bext));
+    assert_eq!(
+        wav.ixml.as_deref(),
+        Some("<BWFXML><SCENE>1</SCENE></BWFXML>")
+    );
+    Ok(())
+}
+
+#[test]
+fn test_writer_with_chunks() -> Result<(), WavError> {
+    let mut file = std::io::Cursor::new(Vec::new());
+    let params = WavParams {
+        sample_rate: 8000,
+        channels: 1,
+    };
+    let bext = BroadcastExt::now(8000).to_chunk();
+    let mut writer = WavWriter::with_chunks(&mut file, params, BitDepth::F32(0.0), &[bext])?;
+    writer.write_samples(&[BitDepth::F32(0.5); 3])?;
+    writer.finalize()?;
+
+    file.set_position(0);
+    let reader = WavReader::new(&mut file)?;
+    assert!(reader.issues.is_empty());
+    assert_eq!(reader.len(), 3);
+    assert!(reader.bext.is_some());
+    Ok(())
+}
+
+#[test]
+fn test_short_metadata_is_kept() -> Result<(), WavError> {
+    let fmt = [1, 0, 1, 0, 0x40, 0x1f, 0, 0, 0x80, 0x3e, 0, 0, 2, 0, 16, 0];
+    let file = riff_bytes(&[
+        (b"bext", &[1; 100]),
+        (b"smpl", &[2; 20]),
+        (b"fmt ", &fmt),
+        (b"data", &[1, 0, 2, 0]),
+    ]);
+    let reader = WavReader::new(std::io::Cursor::new(&file))?;
+    assert_eq!(
+        reader.issues,
+        [
+            HeaderIssue::MalformedChunk(*b"bext"),
+            HeaderIssue::MalformedChunk(*b"smpl")
+        ]
+    );
+    assert!(reader.bext.is_none());
+    assert!(reader.sampler.is_none());
+
+    let wav = WavFile::read_from(&mut std::io::Cursor::new(&file))?;
+    assert_eq!(wav.data.frames(), 2);
+    assert_eq!(wav.chunks.len(), 2);
+    assert_eq!(wav.chunks[0].data, [1; 100]);
+    assert_eq!(wav.chunks[1].id, *b"smpl");
+    assert_eq!(wav.chunks[1].data, [2; 20]);
+    Ok(())
+}
+
+#[test]
+fn test_read_rf64() -> Result<(), WavError> {
+    let fmt = [1, 0, 1, 0, 0x44, 0xac, 0, 0, 0x88, 0x58, 1, 0, 2, 0, 16, 0];
+    let samples: Vec<u8> = [5i16, -5, 7].iter().flat_map(|s| s.to_le_bytes()).collect();
+    let list = b"INFOINAM\x04\x00\x00\x00rf6\x00";
+    let riff_size = 4 + 36 + 8 + 16 + 8 + 6 + 8 + list.len() as u64;
+    for form in [b"RF64", b"BW64"] {
+        let mut file = Vec::new();
+        file.extend_from_slice(form);
+        file.extend_from_slice(&SIZE_IN_DS64.to_le_bytes());
+        file.extend_from_slice(b"WAVE");
+        let ds64 = Ds64 {
+            riff_size,
+            data_size: 6,
+            sample_count: 3,
+            table: Vec::new(),
+        };
+        ds64.write_to(&mut file, b"ds64")?;
+        file.extend_from_slice(b"fmt \x10\x00\x00\x00");
+        file.extend_from_slice(&fmt);
+        file.extend_from_slice(b"data");
+        file.extend_from_slice(&SIZE_IN_DS64.to_le_bytes());
+        file.extend_from_slice(&samples);
+        // Chunks after the data are only found if the ds64 size is used
+        file.extend_from_slice(b"LIST");
+        file.extend_from_slice(&(list.len() as u32).to_le_bytes());
+        file.extend_from_slice(list);
+
+        let reader = WavReader::new(std::io::Cursor::new(&file))?;
+        assert!(reader.issues.is_empty(), "{:?}", reader.issues);
+        assert_eq!(reader.len(), 3);
+        assert_eq!(reader.info[&InfoTag::Title], "rf6");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_huge_ds64_sizes() -> Result<(), WavError> {
+    let fmt = [1, 0, 1, 0, 0x40, 0x1f, 0, 0, 0x80, 0x3e, 0, 0, 2, 0, 16, 0];
+    for data_size in [u64::MAX, u64::MAX - 1] {
+        let mut file = b"RF64\xff\xff\xff\xffWAVE".to_vec();
+        let ds64 = Ds64 {
+            riff_size: u64::MAX,
+            data_size,
+            sample_count: 2,
+            table: Vec::new(),
+        };
+        ds64.write_to(&mut file, b"ds64")?;
+        file.extend_from_slice(b"fmt \x10\x00\x00\x00");
+        file.extend_from_slice(&fmt);
+        file.extend_from_slice(b"data\xff\xff\xff\xff\x01\x00\x02\x00");
+
+        let reader = WavReader::new(std::io::Cursor::new(&file))?;
+        assert_eq!(reader.len(), 2);
+        assert!(reader.issues.contains(&HeaderIssue::RiffSize {
+            header: u64::MAX,
+            actual: file.len() as u64 - 8
+        }));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_writer_switches_to_rf64() -> Result<(), WavError> {
+    let mut file = std::io::Cursor::new(Vec::new());
+    let params = WavParams {
+        sample_rate: 8000,
+        channels: 1,
+    };
+    let mut writer = WavWriter::new(&mut file, params, BitDepth::U16(0))?;
+    writer.write_samples(&[BitDepth::U16(1), BitDepth::U16(2)])?;
+    // Pretend the recording went on for 5 GiB
+    writer.data_size = 5 << 30;
+    writer.flush()?;
+    let bytes = writer.writer.get_ref();
+    assert_eq!(&bytes[0..8], b"RF64\xff\xff\xff\xff");
+    assert_eq!(&bytes[12..16], b"ds64");
+    let ds64 = Ds64::parse(&bytes[20..48])?;
+    assert_eq!(ds64.data_size, 5 << 30);
+    assert_eq!(ds64.sample_count, 5 << 29);
+
+    let reader = WavReader::new(std::io::Cursor::new(bytes))?;
+    assert_eq!(reader.len(), 2);
+    assert!(reader.issues.contains(&HeaderIssue::DataSize {
+        header: 5 << 30,
+        actual: 4
+    }));
+
+    // Back under the limit, it's a plain RIFF file with room saved in a JUNK chunk
+    writer.data_size = 4;
+    writer.finalize()?;
+    let bytes = file.into_inner();
+    assert_eq!(&bytes[0..4], b"RIFF");
+    assert_eq!(&bytes[12..16], b"JUNK");
+    let wav = WavFile::read_from(&mut std::io::Cursor::new(&bytes))?;
+    assert!(matches!(
+        wav.data.interleaved(),
+        [BitDepth::U16(1), BitDepth::U16(2)]
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_g711_round_trip() -> Result<(), WavError> {
+    let samples: Vec<BitDepth> = [0i16, 1000, -1000, 32000, -32768]
+        .iter()
+        .map(|&s| BitDepth::U16(s))
+        .collect();
+    for (encoding, decode) in [
+        (Encoding::ALaw, g711::alaw_decode as fn(u8) -> i16),
+        (Encoding::MuLaw, g711::ulaw_decode),
+    ] {
+        let path = std::env::temp_dir().join(format!("test_g711_round_trip_{encoding:?}.wav"));
+        let mut wav = WavFile::from_buffer(SampleBuffer::mono(8000, samples.clone()))?;
+        wav.set_encoding(encoding)?;
+        wav.write(&path)?;
+
+        let wav = WavFile::read(&path)?;
+        assert!(WavFile::validate(&path)?.is_empty());
+        std::fs::remove_file(&path)?;
+        assert_eq!(wav.encoding(), encoding);
+        assert_eq!(wav.hdr.fmt_ck.block_align, 1);
+        assert_eq!(wav.hdr.fact_ck.as_ref().map(|f| f.sample_length), Some(5));
+        let expected: Vec<String> = samples
+            .iter()
+            .map(|s| match s {
+                BitDepth::U16(v) => {
+                    let code = match encoding {
+                        Encoding::ALaw => g711::alaw_encode(*v),
+                        _ => g711::ulaw_encode(*v),
+                    };
+                    format!("{:?}", BitDepth::U16(decode(code)))
+                }
+                _ => unreachable!(),
+            })
+            .collect();
+        let read: Vec<String> = wav
+            .data
+            .interleaved()
+            .iter()
+            .map(|s| format!("{s:?}"))
+            .collect();
+        assert_eq!(read, expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_ima_adpcm_blocks() -> Result<(), WavError> {
+    let path = std::env::temp_dir().join("test_ima_adpcm_blocks.wav");
+    // Stereo at 8 kHz has 505 frames in a block, so this ends part way through the third
+    let frames = 1200;
+    let samples = (0..frames * 2)
+        .map(|i| BitDepth::U16((((i / 2) as f64 * 0.05).sin() * 10000.0) as i16))
+        .collect();
+    let mut wav = WavFile::from_buffer(SampleBuffer::from_interleaved(8000, 2, samples))?;
+    assert!(matches!(
+        wav.set_encoding(Encoding::ImaAdpcm { block_align: 3 }),
+        Err(WavError::MalformedChunk(id)) if &id == b"fmt "
+    ));
+    wav.set_encoding(Encoding::ima_adpcm(8000, 2))?;
+    wav.write(&path)?;
+    assert!(WavFile::validate(&path)?.is_empty());
+
+    let mut reader = WavReader::open(&path)?;
+    assert_eq!(reader.len(), frames as u64);
+    assert_eq!(reader.fmt_ck.block_align, 512);
+    let all = reader.read_block(frames)?;
+    // Reads that start and end inside blocks match the whole decode
+    reader.seek(500)?;
+    let part = reader.read_block(20)?;
+    assert_eq!(
+        format!("{:?}", part.interleaved()),
+        format!("{:?}", &all.interleaved()[1000..1040])
+    );
+    assert_eq!(reader.position(), 520);
+    reader.seek(1190)?;
+    assert_eq!(reader.read_block(100)?.frames(), 10);
+
+    let wav = WavFile::read(&path)?;
+    std::fs::remove_file(&path)?;
+    assert_eq!(wav.encoding(), Encoding::ImaAdpcm { block_align: 512 });
+    assert_eq!(wav.data.frames(), frames);
+    Ok(())
+}
@@ -0,0 +1,139 @@
+// Periodic waveforms by phase, either naive or band-limited with polynomial corrections around
+// their steps (PolyBLEP) and corners (PolyBLAMP)
+use std::f64::consts::PI;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Sine,
+    /// Rising ramp
+    Saw,
+    /// High for the start of the period, half of it unless a pulse width says otherwise
+    Square,
+    Triangle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Antialias {
+    /// The ideal shape sampled as is, which aliases badly above a few hundred Hz
+    Naive,
+    #[default]
+    PolyBlep,
+}
+
+impl Waveform {
+    /// Value in [-1, 1] at `phase` in [0, 1), for a phase that advances `dt` every sample
+    pub fn at(&self, phase: f64, dt: f64, antialias: Antialias) -> f64 {
+        self.at_width(phase, dt, 0.5, antialias)
+    }
+    /// Like `at`, with a square that's high for `width` of the period
+    pub fn at_width(&self, phase: f64, dt: f64, width: f64, antialias: Antialias) -> f64 {
+        let naive = match self {
+            Waveform::Sine => return (phase * 2.0 * PI).sin(),
+            Waveform::Saw => 2.0 * phase - 1.0,
+            Waveform::Square => match phase < width {
+                true => 1.0,
+                false => -1.0,
+            },
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+        };
+        if antialias == Antialias::Naive {
+            return naive;
+        }
+        // Corrections only reach one sample either side, so they can't overlap past Nyquist
+        let dt = dt.abs().min(0.5);
+        let half = (phase + 0.5).fract();
+        match self {
+            Waveform::Saw => naive - poly_blep(phase, dt),
+            Waveform::Square => {
+                naive + poly_blep(phase, dt) - poly_blep((phase + 1.0 - width).fract(), dt)
+            }
+            // The slope turns by 8 a period at each corner
+            Waveform::Triangle => {
+                naive - 8.0 * dt * poly_blamp(half, dt) + 8.0 * dt * poly_blamp(phase, dt)
+            }
+            Waveform::Sine => unreachable!(),
+        }
+    }
+}
+
+/// Difference between a band-limited unit step at phase 0 and a naive one
+fn poly_blep(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let t = t / dt;
+        t + t - t * t - 1.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// The integral of `poly_blep`, for a unit change of slope at phase 0
+fn poly_blamp(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let t = t / dt - 1.0;
+        -t * t * t / 3.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt + 1.0;
+        t * t * t / 3.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+fn amplitude_at(samples: &[f64], freq: f64, sample_rate: f64) -> f64 {
+    let (mut re, mut im) = (0.0, 0.0);
+    for (i, s) in samples.iter().enumerate() {
+        let angle = 2.0 * PI * freq * i as f64 / sample_rate;
+        re += s * angle.cos();
+        im += s * angle.sin();
+    }
+    2.0 * (re * re + im * im).sqrt() / samples.len() as f64
+}
+
+#[test]
+fn test_band_limited_waves_alias_less() {
+    // 5 kHz at 44.1 kHz, whose 5th harmonic folds back to 19.1 kHz
+    let (rate, freq) = (44100.0, 5000.0);
+    let dt = freq / rate;
+    for waveform in [Waveform::Saw, Waveform::Square, Waveform::Triangle] {
+        let render = |antialias| -> Vec<f64> {
+            (0..44100)
+                .map(|i| waveform.at((i as f64 * dt).fract(), dt, antialias))
+                .collect()
+        };
+        let (naive, blep) = (render(Antialias::Naive), render(Antialias::PolyBlep));
+        let (a, b) = (
+            amplitude_at(&naive, 19100.0, rate),
+            amplitude_at(&blep, 19100.0, rate),
+        );
+        assert!(b < a / 2.0, "{waveform:?}: {b} vs {a}");
+        // While the fundamental stays
+        let (a, b) = (
+            amplitude_at(&naive, freq, rate),
+            amplitude_at(&blep, freq, rate),
+        );
+        assert!((a - b).abs() < a * 0.25, "{waveform:?}: {b} vs {a}");
+    }
+}
+
+#[test]
+fn test_band_limited_matches_naive_away_from_edges() {
+    let dt = 0.01;
+    for waveform in [
+        Waveform::Sine,
+        Waveform::Saw,
+        Waveform::Square,
+        Waveform::Triangle,
+    ] {
+        for phase in [0.1, 0.3, 0.6, 0.8] {
+            let naive = waveform.at(phase, dt, Antialias::Naive);
+            assert_eq!(waveform.at(phase, dt, Antialias::PolyBlep), naive);
+        }
+    }
+    // Right on a step it's halfway between the two sides
+    assert!(Waveform::Saw.at(0.0, dt, Antialias::PolyBlep).abs() < 1e-12);
+    assert!(Waveform::Square.at(0.5, dt, Antialias::PolyBlep).abs() < 1e-12);
+}
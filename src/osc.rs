@@ -6,6 +6,8 @@ use autopilot::screen::size;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{FromSample, Sample, SizedSample};
 use libs::wav::BitDepth;
+use libs::oscillator::{Oscillator, WaveOscillator};
+use libs::waveform::Waveform;
 
 fn main() {
     let host = cpal::default_host();
@@ -41,7 +43,8 @@ pub fn run<T>(device: &cpal::Device, config: &cpal::StreamConfig) -> Result<(),
 where
     T: SizedSample + FromSample<f32>,
 {
-    let mut ph = Phasor::new(440.0, config.sample_rate.0);
+    // The same oscillator sampling::tone renders offline
+    let mut osc = WaveOscillator::new(Waveform::Triangle, 440.0, config.sample_rate.0);
     let screen_size = size();
     let scale = gen_notes();
     let pentatonic = vec![
@@ -62,9 +65,8 @@ where
         let scale_index = fit_to_scale(&pentatonic, raw_f as f64);
         let f = pentatonic[scale_index] as f32;
         print!("y:{ny:.3} f:{f:.3}  \r");
-        ph.set_f(f);
-
-        triangle_osc(ph.next().unwrap())
+        osc.set_frequency(f as f64);
+        osc.next_sample() as f32
     };
 
     let channels = config.channels as usize;
@@ -94,30 +96,6 @@ where
     }
 }
 
-fn sine_osc(phase: f32) -> f32 {
-    (phase * 2.0 * std::f32::consts::PI).sin()
-}
-
-fn square_osc(phase: f32) -> f32 {
-    if phase > 0.5 {
-        1.0
-    } else {
-        -1.0
-    }
-}
-
-fn saw_osc(phase: f32) -> f32 {
-    (2.0 * phase) - 1.0
-}
-
-fn triangle_osc(phase: f32) -> f32 {
-    if phase < 0.5 {
-        (4.0 * phase) - 1.0
-    } else {
-        (4.0 * (1.0 - phase)) - 1.0
-    }
-}
-
 fn lin_map(min: f32, max: f32, v: f32) -> f32 {
     //for v in [0,1]
     min + (max - min) * v
@@ -127,33 +105,3 @@ fn log_map(min: f32, max: f32, v: f32) -> f32 {
     let semitones = 12.0 * (max / min).log2();
     min * 2.0f32.powf((v * semitones) / 12.0)
 }
-
-struct Phasor {
-    f: f32,
-    t: f32,
-    sample_rate: u32,
-}
-
-impl Phasor {
-    fn new(initial_f: f32, sample_rate: u32) -> Phasor {
-        Phasor {
-            f: initial_f,
-            t: 0.0,
-            sample_rate,
-        }
-    }
-    fn set_f(&mut self, f: f32) {
-        self.f = f;
-    }
-}
-
-impl Iterator for Phasor {
-    type Item = f32;
-    fn next(&mut self) -> Option<Self::Item> {
-        // Save current t to return
-        let t = self.t;
-        // Incremet t for next iteration
-        self.t = (self.t + self.f / self.sample_rate as f32) % 1.0;
-        Some(t)
-    }
-}
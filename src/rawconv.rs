@@ -0,0 +1,74 @@
+//! Converts raw headerless dumps to WAV (or any other supported file) and back.
+//!
+//! A side whose extension is `.raw` or `.pcm` is raw, described by the options. When
+//! exporting, the rate and channel count come from the input file instead.
+//!
+//!     rawconv board.raw out/board.wav --format s24 --endian be --rate 48000 --channels 2
+//!     rawconv out/board.wav board.pcm --format f32
+
+mod libs;
+
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use libs::raw::{self, Endian, RawFormat, SampleType};
+use libs::source;
+use libs::wav::WavParams;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Raw PCM to WAV and back", long_about = None)]
+struct Opt {
+    input: PathBuf,
+
+    output: PathBuf,
+
+    /// Sample type of the raw side: s8, u8, s16, s24, s32, f32, f64, alaw or mulaw
+    #[arg(short, long, default_value = "s16")]
+    format: SampleType,
+
+    /// Byte order of the raw side: le or be
+    #[arg(short, long, default_value = "le")]
+    endian: Endian,
+
+    /// Sample rate of a raw input
+    #[arg(short, long, default_value_t = 44100)]
+    rate: u32,
+
+    /// Channels of a raw input
+    #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    channels: u16,
+}
+
+fn is_raw(path: &Path) -> bool {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    matches!(extension.to_ascii_lowercase().as_str(), "raw" | "pcm")
+}
+
+fn main() -> anyhow::Result<()> {
+    let opt = Opt::parse();
+    let mut format = RawFormat::new(
+        opt.format,
+        opt.endian,
+        WavParams {
+            sample_rate: opt.rate,
+            channels: opt.channels,
+        },
+    );
+
+    let buffer = if is_raw(&opt.input) {
+        raw::read(&opt.input, &format)?
+    } else {
+        source::read(&opt.input)?
+    };
+    if is_raw(&opt.output) {
+        format.params = WavParams {
+            sample_rate: buffer.sample_rate,
+            channels: buffer.channels(),
+        };
+        raw::write(&opt.output, &buffer, &format)?;
+    } else {
+        source::write(&opt.output, buffer)?;
+    }
+    println!("Wrote {}", opt.output.display());
+    Ok(())
+}
@@ -1,9 +1,11 @@
 mod libs;
 
-use std::path::Path;
+use std::path::PathBuf;
 
-use crate::libs::sampling;
-use crate::libs::wav::{BitDepth, WavFile, WavParams};
+use crate::libs::sampling::{self, Signal};
+use crate::libs::source;
+use crate::libs::wav::BitDepth;
+use crate::libs::waveform::Waveform;
 
 fn main() {
     let sample_rate = 44100;
@@ -12,159 +14,37 @@ fn main() {
     let half_note = whole_note / 2.0;
     let quarter_note = half_note / 2.0;
     let eighth_note = quarter_note / 2.0;
-    // let sixteenth_note = eighth_note / 2.0;
     let dot = 1.5;
 
-    let note = 440.0;
     let semitone = 2.0f64.powf(1.0 / 12.0);
-    let a4 = note;
+    let a4 = 440.0;
     let e4 = a4 / semitone.powf(5.0);
     let c4 = a4 / semitone.powf(9.0);
     let g4 = a4 / semitone.powf(2.0);
 
     let volume = 0.5;
 
-    let mut data =
-        sampling::sine_wave_truncated(e4, sample_rate, half_note, BitDepth::U16(0), volume);
-    data.append(&mut sampling::sine_wave_truncated(
-        e4,
-        sample_rate,
-        half_note,
-        BitDepth::U16(0),
-        volume,
-    ));
-    data.append(&mut sampling::sine_wave_truncated(
-        e4,
-        sample_rate,
-        half_note,
-        BitDepth::U16(0),
-        volume,
-    ));
-    data.append(&mut sampling::sine_wave_truncated(
-        c4,
-        sample_rate,
-        quarter_note * dot,
-        BitDepth::U16(0),
-        volume,
-    ));
-    data.append(&mut sampling::sine_wave_truncated(
-        g4,
-        sample_rate,
-        eighth_note,
-        BitDepth::U16(0),
-        volume,
-    ));
-    data.append(&mut sampling::sine_wave_truncated(
-        e4,
-        sample_rate,
-        half_note,
-        BitDepth::U16(0),
-        volume,
-    ));
-    data.append(&mut sampling::sine_wave_truncated(
-        c4,
-        sample_rate,
-        quarter_note * dot,
-        BitDepth::U16(0),
-        volume,
-    ));
-    data.append(&mut sampling::sine_wave_truncated(
-        g4,
-        sample_rate,
-        eighth_note,
-        BitDepth::U16(0),
-        volume,
-    ));
-    data.append(&mut sampling::sine_wave_truncated(
-        e4,
-        sample_rate,
-        whole_note,
-        BitDepth::U16(0),
-        volume,
-    ));
-
-    // Add a dirty bass. Downside: this notes can't be detected by my primitive algorithm.
-    let mut octave =
-        sampling::saw_wave_truncated(e4 / 2.0, sample_rate, half_note, BitDepth::U16(0), volume);
-    octave.append(&mut sampling::saw_wave_truncated(
-        e4 / 2.0,
-        sample_rate,
-        half_note,
-        BitDepth::U16(0),
-        volume,
-    ));
-    octave.append(&mut sampling::saw_wave_truncated(
-        e4 / 2.0,
-        sample_rate,
-        half_note,
-        BitDepth::U16(0),
-        volume,
-    ));
-    octave.append(&mut sampling::saw_wave_truncated(
-        c4 / 2.0,
-        sample_rate,
-        quarter_note * dot,
-        BitDepth::U16(0),
-        volume,
-    ));
-    octave.append(&mut sampling::saw_wave_truncated(
-        g4 / 2.0,
-        sample_rate,
-        eighth_note,
-        BitDepth::U16(0),
-        volume,
-    ));
-    octave.append(&mut sampling::saw_wave_truncated(
-        e4 / 2.0,
-        sample_rate,
-        half_note,
-        BitDepth::U16(0),
-        volume,
-    ));
-    octave.append(&mut sampling::saw_wave_truncated(
-        c4 / 2.0,
-        sample_rate,
-        quarter_note * dot,
-        BitDepth::U16(0),
-        volume,
-    ));
-    octave.append(&mut sampling::saw_wave_truncated(
-        g4 / 2.0,
-        sample_rate,
-        eighth_note,
-        BitDepth::U16(0),
-        volume,
-    ));
-    octave.append(&mut sampling::saw_wave_truncated(
-        e4 / 2.0,
-        sample_rate,
-        whole_note,
-        BitDepth::U16(0),
-        volume,
-    ));
-
-    let mut output = Vec::new();
-    let mut i = 0;
-    for s in data {
-        output.push(s + octave[i]);
-        i += 1;
-    }
-    // let output = data;
+    let notes = [
+        (e4, half_note),
+        (e4, half_note),
+        (e4, half_note),
+        (c4, quarter_note * dot),
+        (g4, eighth_note),
+        (e4, half_note),
+        (c4, quarter_note * dot),
+        (g4, eighth_note),
+        (e4, whole_note),
+    ];
+    let lead = sampling::melody(&notes, Waveform::Sine, sample_rate).gain(volume);
+    // Add a bass saw, band-limited so it doesn't fold back over the lead
+    let octave_down = notes.map(|(freq, duration)| (freq / 2.0, duration));
+    let bass = sampling::melody(&octave_down, Waveform::Saw, sample_rate).gain(volume / 2.0);
+    let output = lead.plus(bass).render(sample_rate, BitDepth::U16(0));
 
     println!("Feel the evil");
 
-    // print!("{:?}", data);
-    let params = WavParams {
-        sample_rate: sample_rate,
-        channels: 1,
-    };
-    let wav = WavFile::new(params, output);
-
-    wav.write(&Path::new("out/test.wav")).unwrap();
-    println!("Wrote test.wav");
+    // The extension picks the format, e.g. out/test.flac
+    let path = PathBuf::from(std::env::args().nth(1).unwrap_or("out/test.wav".into()));
+    source::write(&path, output).unwrap();
+    println!("Wrote {}", path.display());
 }
-
-// #[test]
-// fn test_main() {
-//     main();
-// }